@@ -0,0 +1,11 @@
+-- Keep track of who exported which slice of the subscriber list
+CREATE TABLE subscriber_exports(
+   id uuid PRIMARY KEY,
+   -- NULL when the export was not triggered through the HTTP API (e.g. from the command line)
+   user_id uuid NULL REFERENCES users (user_id),
+   format TEXT NOT NULL,
+   status TEXT NULL,
+   subscribed_after timestamptz NULL,
+   subscribed_before timestamptz NULL,
+   exported_at timestamptz NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Erased subscribers keep their row (and therefore our aggregate numbers),
+-- but their personal details are overwritten and the row is hidden from every other query.
+ALTER TABLE subscriptions ADD COLUMN erased_at timestamptz NULL;
@@ -0,0 +1,2 @@
+-- Users created with a one-time password have to pick their own before they can do anything else.
+ALTER TABLE users ADD COLUMN must_change_password BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,6 @@
+-- Users that existed before roles were introduced could already do everything.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner';
+-- From now on, every new user gets an explicit role.
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
+-- Disabled users keep their row (it is referenced by other tables), but can no longer authenticate.
+ALTER TABLE users ADD COLUMN disabled_at timestamptz NULL;
@@ -0,0 +1,14 @@
+-- Long-lived credentials for automation, sent as `Authorization: Bearer <token>`.
+-- Only a SHA-256 hash of the token is stored: the token itself is shown once, when it is created.
+CREATE TABLE api_tokens(
+   id uuid PRIMARY KEY,
+   user_id uuid NOT NULL REFERENCES users (user_id),
+   name TEXT NOT NULL,
+   token_hash TEXT NOT NULL UNIQUE,
+   -- Subset of the permissions granted by the role of the user, e.g. `publish`
+   scopes TEXT[] NOT NULL,
+   created_at timestamptz NOT NULL,
+   expires_at timestamptz NULL,
+   last_used_at timestamptz NULL,
+   revoked_at timestamptz NULL
+);
@@ -0,0 +1,14 @@
+-- RFC 6238 second factor. The secret is written when enrollment starts,
+-- but only enforced once the user has proven their authenticator works (`totp_enabled_at`).
+ALTER TABLE users ADD COLUMN totp_secret BYTEA NULL;
+ALTER TABLE users ADD COLUMN totp_enabled_at timestamptz NULL;
+-- Each time step can be used at most once, so that an intercepted code cannot be replayed.
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT NULL;
+
+-- Single-use codes for when the authenticator is lost. Only a SHA-256 hash is stored.
+CREATE TABLE totp_recovery_codes(
+   user_id uuid NOT NULL REFERENCES users (user_id),
+   code_hash TEXT NOT NULL,
+   used_at timestamptz NULL,
+   PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,13 @@
+-- Where password reset links are sent. Optional: users without one have to ask an owner.
+ALTER TABLE users ADD COLUMN email TEXT NULL;
+CREATE UNIQUE INDEX users_email_key ON users (LOWER(email));
+
+CREATE TABLE password_reset_tokens(
+    -- SHA-256 of the token sent by email, never the token itself
+    token_hash TEXT PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz NULL
+);
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id);
@@ -0,0 +1,17 @@
+CREATE TABLE newsletter_issues(
+    newsletter_issue_id uuid PRIMARY KEY,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    published_at timestamptz NOT NULL
+);
+
+-- One row per email still to send: a row is only deleted once its email has been sent,
+-- so that deliveries interrupted by a crash or a shutdown are picked up again.
+CREATE TABLE issue_delivery_queue(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    n_retries INT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
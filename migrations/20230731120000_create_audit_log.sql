@@ -0,0 +1,26 @@
+-- Who did what through the application: logins, publishing, user and subscriber management.
+-- Rows are never changed nor deleted, see the trigger below.
+-- Nothing about subscribers beyond their id goes in here, so that erasing them leaves no trace.
+CREATE TABLE audit_log(
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at timestamptz NOT NULL DEFAULT now(),
+    -- `NULL` for failed logins with an unknown username
+    user_id uuid NULL,
+    ip TEXT NULL,
+    -- Ties the entry to the logs of the request, see `request_id`
+    request_id TEXT NULL,
+    action TEXT NOT NULL,
+    details JSONB NOT NULL DEFAULT '{}'
+);
+CREATE INDEX audit_log_user_id_idx ON audit_log (user_id, id);
+CREATE INDEX audit_log_action_idx ON audit_log (action, id);
+
+CREATE FUNCTION audit_log_is_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_is_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_is_append_only();
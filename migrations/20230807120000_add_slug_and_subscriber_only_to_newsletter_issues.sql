@@ -0,0 +1,7 @@
+-- Published issues are served at `/archive/{slug}`, unless they are for subscribers only.
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN subscriber_only BOOLEAN NOT NULL DEFAULT false;
+UPDATE newsletter_issues SET slug = newsletter_issue_id::text;
+ALTER TABLE newsletter_issues ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
+CREATE INDEX newsletter_issues_published_at_idx ON newsletter_issues (published_at);
@@ -0,0 +1,14 @@
+-- One row per newsletter email sent while tracking is enabled.
+-- The token identifies the email in the tracking URLs it contains.
+CREATE TABLE tracked_deliveries(
+    token TEXT PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    delivered_at timestamptz NOT NULL,
+    -- Opens are counted once per email, whenever its tracking pixel is loaded
+    first_opened_at timestamptz NULL,
+    last_opened_at timestamptz NULL,
+    open_count INT NOT NULL DEFAULT 0
+);
+CREATE INDEX tracked_deliveries_newsletter_issue_id_idx ON tracked_deliveries (newsletter_issue_id);
+CREATE INDEX tracked_deliveries_subscriber_id_idx ON tracked_deliveries (subscriber_id);
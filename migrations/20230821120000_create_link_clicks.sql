@@ -0,0 +1,9 @@
+-- One row per link clicked in a newsletter email, see `tracked_deliveries`.
+CREATE TABLE link_clicks(
+    delivery_token TEXT NOT NULL REFERENCES tracked_deliveries (token),
+    url TEXT NOT NULL,
+    first_clicked_at timestamptz NOT NULL,
+    last_clicked_at timestamptz NOT NULL,
+    click_count INT NOT NULL DEFAULT 1,
+    PRIMARY KEY (delivery_token, url)
+);
@@ -0,0 +1,4 @@
+-- `TRUNCATE` skips row triggers: it needs a statement trigger of its own.
+CREATE TRIGGER audit_log_is_not_truncated
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_log_is_append_only();
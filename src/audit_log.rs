@@ -0,0 +1,320 @@
+use crate::authentication::{basic_authentication, get_user_id, AuthError};
+use crate::configuration::AuditLogSettings;
+use crate::request_id::RequestId;
+use crate::utils::client_ip;
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// Something done through the application that somebody may have to account for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    LoginSucceeded,
+    LoginFailed,
+    NewsletterPublished,
+    UserAdded,
+    UserChanged,
+    ApiTokenCreated,
+    ApiTokenRevoked,
+    SubscriberStatusChanged,
+    SubscribersImported,
+    DataSubjectErased,
+    LoginThrottled,
+    ApiAuthFailed,
+    PasswordChanged,
+    PasswordReset,
+    TotpEnabled,
+    TotpDisabled,
+    DataSubjectExported,
+}
+
+const ACTIONS: [AuditAction; 17] = [
+    AuditAction::LoginSucceeded,
+    AuditAction::LoginFailed,
+    AuditAction::NewsletterPublished,
+    AuditAction::UserAdded,
+    AuditAction::UserChanged,
+    AuditAction::ApiTokenCreated,
+    AuditAction::ApiTokenRevoked,
+    AuditAction::SubscriberStatusChanged,
+    AuditAction::SubscribersImported,
+    AuditAction::DataSubjectErased,
+    AuditAction::LoginThrottled,
+    AuditAction::ApiAuthFailed,
+    AuditAction::PasswordChanged,
+    AuditAction::PasswordReset,
+    AuditAction::TotpEnabled,
+    AuditAction::TotpDisabled,
+    AuditAction::DataSubjectExported,
+];
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LoginSucceeded => "login.succeeded",
+            AuditAction::LoginFailed => "login.failed",
+            AuditAction::NewsletterPublished => "newsletter.published",
+            AuditAction::UserAdded => "user.added",
+            AuditAction::UserChanged => "user.changed",
+            AuditAction::ApiTokenCreated => "api_token.created",
+            AuditAction::ApiTokenRevoked => "api_token.revoked",
+            AuditAction::SubscriberStatusChanged => "subscriber.status_changed",
+            AuditAction::SubscribersImported => "subscribers.imported",
+            AuditAction::DataSubjectErased => "data_subject.erased",
+            AuditAction::LoginThrottled => "login.throttled",
+            AuditAction::ApiAuthFailed => "api.authentication_failed",
+            AuditAction::PasswordChanged => "password.changed",
+            AuditAction::PasswordReset => "password.reset",
+            AuditAction::TotpEnabled => "two_factor.enabled",
+            AuditAction::TotpDisabled => "two_factor.disabled",
+            AuditAction::DataSubjectExported => "data_subject.exported",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<AuditAction, String> {
+        ACTIONS
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid action", s))
+    }
+}
+
+/// Writes the append-only `audit_log` table. Handlers get it from the application data.
+/// An entry is written once the action has been carried out: failing to write it is logged,
+/// but doesn't fail the request, which has done its job by then.
+pub struct AuditLog {
+    settings: AuditLogSettings,
+    // See `utils::client_ip`
+    trusted_proxies: usize,
+    pool: PgPool,
+}
+
+impl AuditLog {
+    pub fn new(settings: AuditLogSettings, trusted_proxies: usize, pool: PgPool) -> Self {
+        Self {
+            settings,
+            trusted_proxies,
+            pool,
+        }
+    }
+
+    /// Records that `user_id` did `action` with `request`.
+    /// `details` summarises the request: it must not hold anything personal about subscribers,
+    /// since entries outlive their erasure.
+    #[tracing::instrument(name = "Record an audit log entry", skip(self, request, details))]
+    pub async fn record(
+        &self,
+        request: &HttpRequest,
+        user_id: Option<Uuid>,
+        action: AuditAction,
+        details: serde_json::Value,
+    ) {
+        let ip = client_ip(
+            request,
+            self.settings.trust_forwarded_headers,
+            self.trusted_proxies,
+        );
+        let request_id = RequestId::current().map(|id| id.to_string());
+        let result = insert_entry(&self.pool, user_id, ip, request_id, action, details).await;
+        if let Err(e) = result {
+            tracing::error!(error.cause_chain = ?e, "Failed to write an audit log entry.");
+        }
+    }
+
+    /// Records a failed (or throttled) attempt to authenticate as `username`.
+    /// Nobody has vouched for the username: it may be a typo, or a password typed in the wrong
+    /// field. Only the id of the user it belongs to is kept, if there is one.
+    #[tracing::instrument(
+        name = "Record a failed authentication",
+        skip(self, request, username, details)
+    )]
+    pub async fn record_failed_authentication(
+        &self,
+        request: &HttpRequest,
+        username: &str,
+        action: AuditAction,
+        details: serde_json::Value,
+    ) {
+        let target_user_id = match get_user_id(username, &self.pool).await {
+            Ok(user_id) => user_id,
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Failed to look up a user to audit.");
+                None
+            }
+        };
+        self.record_failed_authentication_of(request, target_user_id, action, details)
+            .await
+    }
+
+    /// Records a failed (or throttled) attempt to authenticate as `target_user_id`.
+    /// The attempt was not made by that user as far as we know, so the entry has no user:
+    /// the target goes in `details` instead.
+    pub async fn record_failed_authentication_of(
+        &self,
+        request: &HttpRequest,
+        target_user_id: Option<Uuid>,
+        action: AuditAction,
+        mut details: serde_json::Value,
+    ) {
+        if let (Some(target_user_id), Some(details)) = (target_user_id, details.as_object_mut()) {
+            details.insert("target_user_id".into(), target_user_id.to_string().into());
+        }
+        self.record(request, None, action, details).await
+    }
+
+    /// Records why the API credentials of `request` were rejected, if they were wrong
+    /// or tried too often. Requests without credentials are not worth an entry.
+    pub async fn record_api_auth_failure(&self, request: &HttpRequest, error: &AuthError) {
+        let action = match error {
+            AuthError::InvalidCredentials(_) => AuditAction::ApiAuthFailed,
+            AuthError::Throttled(_) => AuditAction::LoginThrottled,
+            _ => return,
+        };
+        let scheme = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split_once(' '))
+            .map(|(scheme, _)| scheme.to_lowercase());
+        match scheme.as_deref() {
+            Some("basic") => {
+                let details = serde_json::json!({ "scheme": "basic" });
+                match basic_authentication(request.headers()) {
+                    Ok(credentials) => {
+                        self.record_failed_authentication(
+                            request,
+                            &credentials.username,
+                            action,
+                            details,
+                        )
+                        .await
+                    }
+                    Err(_) => self.record(request, None, action, details).await,
+                }
+            }
+            // Unknown tokens belong to nobody
+            Some("bearer") => {
+                let details = serde_json::json!({ "scheme": "bearer" });
+                self.record(request, None, action, details).await
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Records `action`, done with `newsletter-admin` rather than through the application:
+/// there is neither an authenticated user nor a request.
+/// Unlike `AuditLog::record`, a failure is returned: the command has not finished yet.
+#[tracing::instrument(name = "Record an audit log entry of a command", skip(pool, details))]
+pub async fn record_command(
+    pool: &PgPool,
+    action: AuditAction,
+    details: serde_json::Value,
+) -> Result<(), anyhow::Error> {
+    insert_entry(pool, None, None, None, action, details)
+        .await
+        .context("Failed to write an audit log entry.")
+}
+
+async fn insert_entry(
+    pool: &PgPool,
+    user_id: Option<Uuid>,
+    ip: Option<IpAddr>,
+    request_id: Option<String>,
+    action: AuditAction,
+    details: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (occurred_at, user_id, ip, request_id, action, details)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Utc::now(),
+        user_id,
+        ip.map(|ip| ip.to_string()),
+        request_id,
+        action.as_str(),
+        details,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub user_id: Option<Uuid>,
+    // As it is now, not as it was when the entry was written
+    pub username: Option<String>,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    pub action: String,
+    pub details: serde_json::Value,
+}
+
+#[derive(Default)]
+pub struct AuditFilter {
+    pub user_id: Option<Uuid>,
+    pub action: Option<AuditAction>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Up to `limit` entries matching `filter`, newest first, older than the entry `before`.
+#[tracing::instrument(name = "Get audit log entries", skip(pool, filter))]
+pub async fn get_audit_entries(
+    pool: &PgPool,
+    filter: &AuditFilter,
+    before: Option<i64>,
+    limit: i64,
+) -> Result<Vec<AuditEntry>, anyhow::Error> {
+    let entries = sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT a.id, a.occurred_at, a.user_id, u.username AS "username?", a.ip, a.request_id,
+            a.action, a.details
+        FROM audit_log a
+        LEFT JOIN users u ON u.user_id = a.user_id
+        WHERE ($1::uuid IS NULL OR a.user_id = $1)
+            AND ($2::text IS NULL OR a.action = $2)
+            AND ($3::timestamptz IS NULL OR a.occurred_at >= $3)
+            AND ($4::timestamptz IS NULL OR a.occurred_at < $4)
+            AND ($5::bigint IS NULL OR a.id < $5)
+        ORDER BY a.id DESC
+        LIMIT $6
+        "#,
+        filter.user_id,
+        filter.action.map(|a| a.as_str()),
+        filter.since,
+        filter.until,
+        before,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve audit log entries.")?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditAction, ACTIONS};
+
+    #[test]
+    fn actions_round_trip() {
+        for action in ACTIONS {
+            assert_eq!(AuditAction::parse(action.as_str()), Ok(action));
+        }
+    }
+
+    #[test]
+    fn unknown_actions_are_rejected() {
+        assert!(AuditAction::parse("login").is_err());
+    }
+}
@@ -1,42 +1,101 @@
+use crate::authorization::Permission;
+use crate::configuration::{BootstrapAdminSettings, PasswordHashingSettings, TwoFactorSettings};
+use crate::domain::UserRole;
+use crate::login_throttle::LoginThrottle;
+use crate::metrics::record_password_verification;
 use crate::routes::spawn_blocking_with_tracing;
+use crate::two_factor::{second_factor_status, verify_second_factor, SecondFactor};
+use actix_web::http::header::HeaderMap;
+use actix_web::{web, HttpRequest};
 use anyhow::Context;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
 use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
+use std::net::IpAddr;
+use std::time::Instant;
 
 #[derive(thiserror::Error, Debug)]
 pub enum AuthError {
     #[error("Invalid credentials.")]
     InvalidCredentials(#[source] anyhow::Error),
+    // Too many failed attempts lately: the credentials were not even checked.
+    // Callers must answer as for invalid ones, so that the account isn't given away.
+    #[error("Invalid credentials.")]
+    Throttled(#[source] anyhow::Error),
+    // The credentials are valid, but the user still has a one-time password.
+    #[error("The password must be changed before the account can be used.")]
+    PasswordChangeRequired(uuid::Uuid),
+    // The role of the user requires a second factor, and they haven't set one up yet.
+    #[error("Two-factor authentication must be set up before the account can be used.")]
+    TwoFactorEnrollmentRequired(uuid::Uuid),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+/// Who is making a request, once their credentials have been checked.
+#[derive(Debug)]
+pub struct AuthenticatedUser {
+    pub user_id: uuid::Uuid,
+    pub username: String,
+    // `None` for passwords, which can do anything the role of the user allows.
+    // API tokens are restricted to the scopes they were created with.
+    pub scopes: Option<Vec<Permission>>,
+}
+
 pub struct Credentials {
     pub username: String,
     pub password: Secret<String>,
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+/// Check a username and password, unless there were too many failed attempts lately.
+/// Failures are recorded; a success is not, since a second factor may still be missing:
+/// callers call `LoginThrottle::record_success` once the user is fully authenticated.
+#[tracing::instrument(
+    name = "Validate credentials",
+    skip(credentials, throttle, policy, pool)
+)]
 pub(crate) async fn validate_credentials(
     credentials: Credentials,
+    client_ip: Option<IpAddr>,
+    throttle: &LoginThrottle,
+    policy: &PasswordPolicy,
+    pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+    let username = credentials.username.clone();
+    if let Err(wait) = throttle.check(&username, client_ip) {
+        return Err(AuthError::Throttled(anyhow::anyhow!(
+            "Too many failed attempts, retry in {} seconds.",
+            wait.as_secs() + 1
+        )));
+    }
+    let result = check_credentials(credentials, policy, pool).await;
+    if let Err(AuthError::InvalidCredentials(_)) = result {
+        throttle.record_failure(&username, client_ip);
+    }
+    result
+}
+
+async fn check_credentials(
+    credentials: Credentials,
+    policy: &PasswordPolicy,
     pool: &PgPool,
 ) -> Result<uuid::Uuid, AuthError> {
     let mut user_id = None;
-    let mut expected_password_hash = Secret::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-        gZiV/M1gPc22ElAH/Jh1Hw$\
-        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-            .to_string(),
-    );
+    let mut must_change_password = false;
+    let mut expected_password_hash = policy.dummy_hash.clone();
 
-    if let Some((stored_user_id, stored_password_hash)) =
-        get_stored_credentials(&credentials.username, &pool).await?
-    {
-        user_id = Some(stored_user_id);
-        expected_password_hash = stored_password_hash;
+    if let Some(stored) = get_stored_credentials(&credentials.username, pool).await? {
+        user_id = Some(stored.user_id);
+        must_change_password = stored.must_change_password;
+        expected_password_hash = stored.password_hash;
     }
 
+    let stored_password_hash = expected_password_hash.clone();
+    let password = credentials.password.clone();
     // expensive tasks get their own thread
     spawn_blocking_with_tracing(move || {
         verify_password_hash(expected_password_hash, credentials.password)
@@ -48,31 +107,130 @@ pub(crate) async fn validate_credentials(
     // So, even if the default password ends up matching (somehow)
     // with the provided password,
     // we never authenticate a non-existing user.
-    user_id
+    let user_id = user_id
         .ok_or_else(|| anyhow::anyhow!("Unknown username."))
-        .map_err(AuthError::InvalidCredentials)
+        .map_err(AuthError::InvalidCredentials)?;
+    if policy.needs_rehash(&stored_password_hash) {
+        upgrade_password_hash(user_id, stored_password_hash, password, policy, pool).await;
+    }
+    if must_change_password {
+        return Err(AuthError::PasswordChangeRequired(user_id));
+    }
+    Ok(user_id)
 }
 
+// This is the only time we get to see the password in clear, so this is when old hashes get stronger.
+// Failures are only logged: the user typed the right password after all.
+#[tracing::instrument(
+    name = "Upgrade password hash",
+    skip(old_password_hash, password, policy, pool)
+)]
+async fn upgrade_password_hash(
+    user_id: uuid::Uuid,
+    old_password_hash: Secret<String>,
+    password: Secret<String>,
+    policy: &PasswordPolicy,
+    pool: &PgPool,
+) {
+    let result: Result<(), anyhow::Error> = async {
+        let new_password_hash = {
+            let policy = policy.clone();
+            spawn_blocking_with_tracing(move || compute_password_hash(password, &policy))
+                .await
+                .context("Failed to spawn blocking task.")??
+        };
+        // Unless the password was changed in the meantime
+        sqlx::query!(
+            r#"
+            UPDATE users SET password_hash = $1
+            WHERE user_id = $2 AND password_hash = $3
+            "#,
+            new_password_hash.expose_secret(),
+            user_id,
+            old_password_hash.expose_secret(),
+        )
+        .execute(pool)
+        .await
+        .context("Failed to store an upgraded password hash.")?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            "Failed to upgrade a password hash to the current parameters."
+        );
+    }
+}
+
+struct StoredCredentials {
+    user_id: uuid::Uuid,
+    password_hash: Secret<String>,
+    must_change_password: bool,
+}
+
+// Disabled users are treated as unknown ones.
 #[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
 async fn get_stored_credentials(
     username: &str,
     pool: &PgPool,
-) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
+) -> Result<Option<StoredCredentials>, anyhow::Error> {
     let row = sqlx::query!(
         r#"
-        SELECT user_id, password_hash
+        SELECT user_id, password_hash, must_change_password
         FROM users
-        WHERE username = $1
+        WHERE username = $1 AND disabled_at IS NULL
         "#,
         username,
     )
     .fetch_optional(pool)
     .await
     .context("Failed to perform a query to retrieve stored credentials.")?
-    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+    .map(|row| StoredCredentials {
+        user_id: row.user_id,
+        password_hash: Secret::new(row.password_hash),
+        must_change_password: row.must_change_password,
+    });
     Ok(row)
 }
 
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: uuid::Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT username FROM users WHERE user_id = $1"#, user_id,)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve a username.")?;
+    Ok(row.username)
+}
+
+#[tracing::instrument(name = "Get user id", skip(pool))]
+pub async fn get_user_id(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<uuid::Uuid>, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT user_id FROM users WHERE username = $1"#, username,)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to perform a query to retrieve a user id.")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+// Whether the user still has to replace a one-time password.
+#[tracing::instrument(name = "Check for a pending password change", skip(pool))]
+pub async fn password_change_required(
+    user_id: uuid::Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT must_change_password FROM users WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve the password status of a user.")?;
+    Ok(row.must_change_password)
+}
+
 #[tracing::instrument(
     name = "Verify password hash",
     skip(expected_password_hash, password_candidate)
@@ -84,11 +242,447 @@ fn verify_password_hash(
     let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
         .context("Failed to parse hash in PHC string format.")?;
 
-    Argon2::default()
-        .verify_password(
-            password_candidate.expose_secret().as_bytes(),
-            &expected_password_hash,
-        )
+    let started_at = Instant::now();
+    let verified = Argon2::default().verify_password(
+        password_candidate.expose_secret().as_bytes(),
+        &expected_password_hash,
+    );
+    record_password_verification(started_at.elapsed());
+    verified
         .context("Invalid password.")
         .map_err(AuthError::InvalidCredentials)
 }
+
+/// Hash a password with the Argon2 parameters of `policy`, the same ones as its dummy hash,
+/// so that verifying an unknown username costs as much as verifying a known one.
+pub fn compute_password_hash(
+    password: Secret<String>,
+    policy: &PasswordPolicy,
+) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = policy
+        .argon2()
+        .hash_password(password.expose_secret().as_bytes(), &salt)?
+        .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+/// How new password hashes are computed, see `PasswordHashingSettings`.
+#[derive(Clone)]
+pub struct PasswordPolicy {
+    params: Params,
+    // Checked when the username is unknown, so that it takes as long as for an existing user
+    dummy_hash: Secret<String>,
+}
+
+impl PasswordPolicy {
+    pub fn new(settings: &PasswordHashingSettings) -> Result<Self, anyhow::Error> {
+        let params = Params::new(
+            settings.memory_kib,
+            settings.iterations,
+            settings.parallelism,
+            None,
+        )
+        .map_err(|e| anyhow::anyhow!("Invalid password hashing parameters: {}", e))?;
+        let mut policy = Self {
+            params,
+            dummy_hash: Secret::new(String::new()),
+        };
+        policy.dummy_hash = compute_password_hash(generate_one_time_password(), &policy)?;
+        Ok(policy)
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    // Whether a stored hash is weaker than the ones we compute now.
+    // Stronger ones are left alone: lowering the settings never downgrades a hash.
+    fn needs_rehash(&self, password_hash: &Secret<String>) -> bool {
+        let password_hash = match PasswordHash::new(password_hash.expose_secret()) {
+            Ok(password_hash) => password_hash,
+            Err(_) => return false,
+        };
+        if password_hash.algorithm != Algorithm::Argon2id.ident()
+            || password_hash.version != Some(Version::V0x13.into())
+        {
+            return true;
+        }
+        match Params::try_from(&password_hash) {
+            Ok(params) => {
+                params.m_cost() < self.params.m_cost()
+                    || params.t_cost() < self.params.t_cost()
+                    || params.p_cost() < self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+/// Returns `None` if the username or the email address is already taken.
+/// The email address is where password reset links are sent.
+/// Users created with `must_change_password` have to pick a new password on their first login.
+#[tracing::instrument(name = "Create user", skip(password, policy, pool))]
+pub async fn create_user(
+    username: &str,
+    email: Option<&str>,
+    password: Secret<String>,
+    role: UserRole,
+    must_change_password: bool,
+    policy: &PasswordPolicy,
+    pool: &PgPool,
+) -> Result<Option<uuid::Uuid>, anyhow::Error> {
+    let policy = policy.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &policy))
+            .await
+            .context("Failed to spawn blocking task.")??;
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, email, password_hash, role, must_change_password)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT DO NOTHING
+        RETURNING user_id
+        "#,
+        uuid::Uuid::new_v4(),
+        username,
+        email,
+        password_hash.expose_secret(),
+        role.as_str(),
+        must_change_password,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to store a new user in the database.")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+/// The rules for a new password, wherever it is chosen.
+/// The error is meant to be shown to the user.
+pub fn validate_new_password(
+    new_password: &Secret<String>,
+    new_password_check: &Secret<String>,
+) -> Result<(), &'static str> {
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        return Err("You entered two different new passwords - the field values must match.");
+    }
+    let length = new_password.expose_secret().chars().count();
+    if !(12..=128).contains(&length) {
+        return Err("The new password must be between 12 and 128 characters long.");
+    }
+    Ok(())
+}
+
+// Handed out once, out of band: the user is asked to replace it on their first login.
+pub fn generate_one_time_password() -> Secret<String> {
+    let password: String = std::iter::repeat_with(|| rand::thread_rng().sample(Alphanumeric))
+        .map(char::from)
+        .take(24)
+        .collect();
+    Secret::new(password)
+}
+
+/// Returns `false` if there is no user with the given username.
+/// A one-time password is replaced like any other, so the user is no longer asked to change it.
+#[tracing::instrument(name = "Change password", skip(password, policy, pool))]
+pub async fn change_password(
+    username: &str,
+    password: Secret<String>,
+    policy: &PasswordPolicy,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let policy = policy.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, &policy))
+            .await
+            .context("Failed to spawn blocking task.")??;
+    let result = sqlx::query!(
+        r#"
+        UPDATE users SET password_hash = $1, must_change_password = FALSE
+        WHERE username = $2
+        "#,
+        password_hash.expose_secret(),
+        username,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change a user's password in the database.")?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Create the first user of a fresh deployment, as an owner, so that somebody can log in at all.
+/// Does nothing if there is already at least one user.
+#[tracing::instrument(name = "Bootstrap admin user", skip(settings, policy, pool))]
+pub async fn bootstrap_admin(
+    settings: &BootstrapAdminSettings,
+    policy: &PasswordPolicy,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let (password_hash, one_time_password) = match &settings.password_hash {
+        Some(password_hash) => {
+            PasswordHash::new(password_hash.expose_secret())
+                .context("The bootstrap admin password hash is not a valid PHC string.")?;
+            (password_hash.clone(), None)
+        }
+        None => {
+            let password = generate_one_time_password();
+            let password_hash = {
+                let password = password.clone();
+                let policy = policy.clone();
+                spawn_blocking_with_tracing(move || compute_password_hash(password, &policy))
+                    .await
+                    .context("Failed to spawn blocking task.")??
+            };
+            (password_hash, Some(password))
+        }
+    };
+
+    // A single statement, so that instances starting at the same time cannot both create a user
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role, must_change_password)
+        SELECT $1, $2, $3, 'owner', $4
+        WHERE NOT EXISTS (SELECT 1 FROM users)
+        ON CONFLICT DO NOTHING
+        "#,
+        uuid::Uuid::new_v4(),
+        settings.username,
+        password_hash.expose_secret(),
+        one_time_password.is_some(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the bootstrap admin user in the database.")?;
+    if result.rows_affected() == 0 {
+        return Ok(());
+    }
+
+    match one_time_password {
+        // This is the only time the password is ever shown.
+        Some(password) => tracing::warn!(
+            username = %settings.username,
+            one_time_password = %password.expose_secret(),
+            "Created the initial admin user. Log in with this one-time password to choose a new one."
+        ),
+        None => tracing::info!(
+            username = %settings.username,
+            "Created the initial admin user with the configured password hash."
+        ),
+    }
+    Ok(())
+}
+
+/// Authenticate a request with either 'Basic' credentials or a 'Bearer' API token.
+pub async fn authenticate(
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<AuthenticatedUser, AuthError> {
+    if let Some(token) = bearer_token(request.headers()).map_err(AuthError::InvalidCredentials)? {
+        return validate_api_token(token, pool).await;
+    }
+    authenticate_with_password(request, pool).await
+}
+
+/// Check 'Basic' credentials.
+/// Users with a second factor must send their current code in the `X-TOTP-Code` header as well;
+/// scripts are better off with an API token.
+pub async fn authenticate_with_password(
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<AuthenticatedUser, AuthError> {
+    let throttle = app_data::<LoginThrottle>(request);
+    let client_ip = throttle.client_ip(request);
+    let credentials =
+        basic_authentication(request.headers()).map_err(AuthError::InvalidCredentials)?;
+    let username = credentials.username.clone();
+    let policy = app_data::<PasswordPolicy>(request);
+    let user_id = validate_credentials(credentials, client_ip, throttle, policy, pool).await?;
+    match second_factor_status(user_id, pool, app_data::<TwoFactorSettings>(request)).await? {
+        SecondFactor::Disabled => {}
+        SecondFactor::EnrollmentRequired => {
+            return Err(AuthError::TwoFactorEnrollmentRequired(user_id))
+        }
+        SecondFactor::Enabled => {
+            let code = request
+                .headers()
+                .get("X-TOTP-Code")
+                .and_then(|h| h.to_str().ok())
+                .ok_or_else(|| anyhow::anyhow!("Missing 'X-TOTP-Code' header."))
+                .map_err(AuthError::InvalidCredentials)?;
+            if !verify_second_factor(user_id, code, pool).await? {
+                throttle.record_failure(&username, client_ip);
+                return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+                    "Invalid second factor."
+                )));
+            }
+        }
+    }
+    throttle.record_success(&username);
+    Ok(AuthenticatedUser {
+        user_id,
+        username,
+        scopes: None,
+    })
+}
+
+// Shared state registered on startup, see `startup::run`.
+fn app_data<T: 'static>(request: &HttpRequest) -> &T {
+    request
+        .app_data::<web::Data<T>>()
+        .expect("Application data is registered on startup")
+        .get_ref()
+}
+
+// Returns `None` if the request does not use the 'Bearer' scheme.
+fn bearer_token(headers: &HeaderMap) -> Result<Option<Secret<String>>, anyhow::Error> {
+    let header_value = match headers.get("Authorization") {
+        Some(header_value) => header_value,
+        None => return Ok(None),
+    };
+    let token = header_value
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?
+        .strip_prefix("Bearer ")
+        .map(|t| Secret::new(t.trim().to_owned()));
+    Ok(token)
+}
+
+#[tracing::instrument(name = "Validate API token", skip(token, pool))]
+async fn validate_api_token(
+    token: Secret<String>,
+    pool: &PgPool,
+) -> Result<AuthenticatedUser, AuthError> {
+    // Tokens carry enough entropy that a fast hash is enough, and lets us look them up by hash
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = $2
+        FROM users
+        WHERE api_tokens.user_id = users.user_id
+            AND api_tokens.token_hash = $1
+            AND api_tokens.revoked_at IS NULL
+            AND (api_tokens.expires_at IS NULL OR api_tokens.expires_at > $2)
+            AND users.disabled_at IS NULL
+        RETURNING users.user_id, users.username, api_tokens.scopes
+        "#,
+        hash_api_token(&token),
+        chrono::Utc::now(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate an API token.")?
+    .ok_or_else(|| {
+        AuthError::InvalidCredentials(anyhow::anyhow!("Unknown, expired or revoked API token."))
+    })?;
+
+    let scopes = row
+        .scopes
+        .iter()
+        .map(|s| Permission::parse_scope(s))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(anyhow::Error::msg)
+        .context("The stored scopes of the API token are invalid.")?;
+    Ok(AuthenticatedUser {
+        user_id: row.user_id,
+        username: row.username,
+        scopes: Some(scopes),
+    })
+}
+
+// The prefix makes leaked tokens easy to recognise, e.g. by secret scanners.
+pub fn generate_api_token() -> Secret<String> {
+    let token: String = std::iter::repeat_with(|| rand::thread_rng().sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect();
+    Secret::new(format!("nlt_{}", token))
+}
+
+pub fn hash_api_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+// Extract `Credentials` from the `Authorization` header of a request using the 'Basic' scheme.
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The authorization header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'")?;
+
+    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    // Split into two segments, using ':' as delimiter
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+        .to_string();
+
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: Secret::new(password),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PasswordPolicy;
+    use crate::configuration::PasswordHashingSettings;
+    use secrecy::Secret;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy::new(&PasswordHashingSettings {
+            memory_kib: 15000,
+            iterations: 2,
+            parallelism: 1,
+        })
+        .unwrap()
+    }
+
+    fn hash(phc: &str) -> Secret<String> {
+        Secret::new(phc.to_owned())
+    }
+
+    const SALT_AND_HASH: &str =
+        "gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno";
+
+    #[test]
+    fn hashes_with_the_current_parameters_are_kept() {
+        let phc = format!("$argon2id$v=19$m=15000,t=2,p=1${}", SALT_AND_HASH);
+        assert!(!policy().needs_rehash(&hash(&phc)));
+    }
+
+    #[test]
+    fn hashes_with_stronger_parameters_are_kept() {
+        let phc = format!("$argon2id$v=19$m=65536,t=3,p=4${}", SALT_AND_HASH);
+        assert!(!policy().needs_rehash(&hash(&phc)));
+    }
+
+    #[test]
+    fn hashes_with_any_weaker_parameter_are_upgraded() {
+        for params in ["m=4096,t=2,p=1", "m=15000,t=1,p=1", "m=65536,t=1,p=4"] {
+            let phc = format!("$argon2id$v=19${}${}", params, SALT_AND_HASH);
+            assert!(policy().needs_rehash(&hash(&phc)), "{}", params);
+        }
+    }
+
+    #[test]
+    fn other_argon2_variants_are_upgraded() {
+        let phc = format!("$argon2i$v=19$m=15000,t=2,p=1${}", SALT_AND_HASH);
+        assert!(policy().needs_rehash(&hash(&phc)));
+    }
+}
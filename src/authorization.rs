@@ -0,0 +1,164 @@
+use crate::authentication::AuthenticatedUser;
+use crate::domain::UserRole;
+use crate::routes::error_chain_fmt;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Something a user may or may not be allowed to do, depending on their role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ViewSubscribers,
+    // Change, import and erase subscribers
+    EditSubscribers,
+    // Bulk exports hand out the personal data of the whole list
+    ExportSubscribers,
+    PublishNewsletter,
+    ManageUsers,
+    // Who logged in and did what, see `audit_log`
+    ViewAuditLog,
+}
+
+impl Permission {
+    pub fn is_granted_to(&self, role: UserRole) -> bool {
+        match role {
+            UserRole::Owner => true,
+            UserRole::Editor => !matches!(self, Permission::ManageUsers | Permission::ViewAuditLog),
+            UserRole::Viewer => matches!(self, Permission::ViewSubscribers),
+        }
+    }
+
+    /// Permissions double as the scopes of API tokens.
+    pub fn parse_scope(s: &str) -> Result<Permission, String> {
+        match s {
+            "read-subscribers" => Ok(Permission::ViewSubscribers),
+            "write-subscribers" => Ok(Permission::EditSubscribers),
+            "export-subscribers" => Ok(Permission::ExportSubscribers),
+            "publish" => Ok(Permission::PublishNewsletter),
+            "manage-users" => Ok(Permission::ManageUsers),
+            "read-audit-log" => Ok(Permission::ViewAuditLog),
+            other => Err(format!("{} is not a valid scope", other)),
+        }
+    }
+
+    pub fn as_scope(&self) -> &'static str {
+        match self {
+            Permission::ViewSubscribers => "read-subscribers",
+            Permission::EditSubscribers => "write-subscribers",
+            Permission::ExportSubscribers => "export-subscribers",
+            Permission::PublishNewsletter => "publish",
+            Permission::ManageUsers => "manage-users",
+            Permission::ViewAuditLog => "read-audit-log",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Permission::ViewSubscribers => "view subscribers",
+            Permission::EditSubscribers => "edit subscribers",
+            Permission::ExportSubscribers => "export subscribers",
+            Permission::PublishNewsletter => "publish newsletter issues",
+            Permission::ManageUsers => "manage users",
+            Permission::ViewAuditLog => "view the audit log",
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum AuthorizationError {
+    #[error("You are not allowed to {}.", .0.description())]
+    Forbidden(Permission),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuthorizationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Check that an authenticated user holds `permission`.
+/// Every endpoint that acts on behalf of a user goes through here after authentication.
+/// API tokens are limited to their scopes, on top of what the role of their user allows.
+#[tracing::instrument(name = "Check permission", skip(user, pool), fields(user_id=%user.user_id))]
+pub async fn require_permission(
+    user: &AuthenticatedUser,
+    permission: Permission,
+    pool: &PgPool,
+) -> Result<UserRole, AuthorizationError> {
+    if let Some(scopes) = &user.scopes {
+        if !scopes.contains(&permission) {
+            return Err(AuthorizationError::Forbidden(permission));
+        }
+    }
+    let role = get_user_role(user.user_id, pool).await?;
+    if !permission.is_granted_to(role) {
+        return Err(AuthorizationError::Forbidden(permission));
+    }
+    Ok(role)
+}
+
+#[tracing::instrument(name = "Get user role", skip(pool))]
+pub async fn get_user_role(user_id: Uuid, pool: &PgPool) -> Result<UserRole, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT role FROM users WHERE user_id = $1"#, user_id,)
+        .fetch_one(pool)
+        .await
+        .context("Failed to perform a query to retrieve the role of a user.")?;
+    UserRole::parse(row.role)
+        .map_err(anyhow::Error::msg)
+        .context("The stored role of the user is invalid.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Permission;
+    use crate::domain::UserRole;
+
+    #[test]
+    fn owners_can_do_everything() {
+        for permission in [
+            Permission::ViewSubscribers,
+            Permission::EditSubscribers,
+            Permission::ExportSubscribers,
+            Permission::PublishNewsletter,
+            Permission::ManageUsers,
+            Permission::ViewAuditLog,
+        ] {
+            assert!(permission.is_granted_to(UserRole::Owner));
+        }
+    }
+
+    #[test]
+    fn editors_cannot_manage_users() {
+        assert!(Permission::PublishNewsletter.is_granted_to(UserRole::Editor));
+        assert!(Permission::EditSubscribers.is_granted_to(UserRole::Editor));
+        assert!(!Permission::ManageUsers.is_granted_to(UserRole::Editor));
+        assert!(!Permission::ViewAuditLog.is_granted_to(UserRole::Editor));
+    }
+
+    #[test]
+    fn scopes_round_trip() {
+        for permission in [
+            Permission::ViewSubscribers,
+            Permission::EditSubscribers,
+            Permission::ExportSubscribers,
+            Permission::PublishNewsletter,
+            Permission::ManageUsers,
+            Permission::ViewAuditLog,
+        ] {
+            assert_eq!(
+                Permission::parse_scope(permission.as_scope()),
+                Ok(permission)
+            );
+        }
+        assert!(Permission::parse_scope("admin").is_err());
+    }
+
+    #[test]
+    fn viewers_can_only_view_subscribers() {
+        assert!(Permission::ViewSubscribers.is_granted_to(UserRole::Viewer));
+        assert!(!Permission::ExportSubscribers.is_granted_to(UserRole::Viewer));
+        assert!(!Permission::PublishNewsletter.is_granted_to(UserRole::Viewer));
+    }
+}
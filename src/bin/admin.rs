@@ -0,0 +1,305 @@
+use anyhow::Context;
+use clap::{Parser, Subcommand, ValueEnum};
+use futures_util::StreamExt;
+use rust_newsletter::audit_log::{record_command, AuditAction};
+use rust_newsletter::authentication::{
+    change_password, create_user, get_user_id, validate_new_password, PasswordPolicy,
+};
+use rust_newsletter::configuration::get_configuration;
+use rust_newsletter::domain::{SubscriberEmail, SubscriptionStatus, UserRole};
+use rust_newsletter::issue_delivery_worker::requeue_issue;
+use rust_newsletter::routes::{
+    export_subscribers, get_subscribers_page, import_subscribers, record_export, ExportFormat,
+    ImportMode, SubscriberFilter,
+};
+use rust_newsletter::startup::get_connection_pool;
+use rust_newsletter::telemetry::{get_subscriber, init_subscriber};
+use rust_newsletter::two_factor::disable_two_factor;
+use secrecy::Secret;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// Operational tasks that would otherwise require hand-written SQL.
+// It reads the same configuration as the server (`APP_ENVIRONMENT`, `configuration/*.yaml`, `APP_*`).
+//
+// run: `cargo run --bin newsletter-admin -- --help`
+#[derive(Parser)]
+#[command(
+    name = "newsletter-admin",
+    about = "Administrative tasks for the newsletter"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a new user. The password is read from standard input.
+    CreateUser {
+        username: String,
+        #[arg(long, value_enum)]
+        role: CliRole,
+        /// Where password reset links are sent
+        #[arg(long)]
+        email: Option<String>,
+    },
+    /// Set a new password for an existing user. The password is read from standard input.
+    ResetPassword { username: String },
+    /// Turn two-factor authentication off for a user who lost both their device and their recovery codes.
+    DisableTwoFactor { username: String },
+    /// Print subscribers, one per line (tab-separated).
+    ListSubscribers {
+        #[arg(long)]
+        status: Option<String>,
+        /// Case-insensitive prefix of the email or the name
+        #[arg(long)]
+        search: Option<String>,
+    },
+    /// Import subscribers from a CSV file with `email` and `name` columns.
+    Import {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = CliImportMode::SendConfirmation)]
+        mode: CliImportMode,
+    },
+    /// Export subscribers to a file, or to standard output.
+    Export {
+        #[arg(long, value_enum, default_value_t = CliExportFormat::Csv)]
+        format: CliExportFormat,
+        #[arg(long)]
+        status: Option<String>,
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Queue a newsletter issue again for its confirmed subscribers, e.g. after a failed delivery.
+    RequeueIssue { newsletter_issue_id: Uuid },
+    /// Apply any pending database migration.
+    RunMigrations,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CliRole {
+    Owner,
+    Editor,
+    Viewer,
+}
+
+impl From<CliRole> for UserRole {
+    fn from(value: CliRole) -> Self {
+        match value {
+            CliRole::Owner => UserRole::Owner,
+            CliRole::Editor => UserRole::Editor,
+            CliRole::Viewer => UserRole::Viewer,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CliImportMode {
+    Confirmed,
+    SendConfirmation,
+}
+
+impl From<CliImportMode> for ImportMode {
+    fn from(value: CliImportMode) -> Self {
+        match value {
+            CliImportMode::Confirmed => ImportMode::Confirmed,
+            CliImportMode::SendConfirmation => ImportMode::SendConfirmation,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CliExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl From<CliExportFormat> for ExportFormat {
+    fn from(value: CliExportFormat) -> Self {
+        match value {
+            CliExportFormat::Csv => ExportFormat::Csv,
+            CliExportFormat::Ndjson => ExportFormat::Ndjson,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    // Logs go to stderr: stdout is reserved for the output of the command (e.g. an export).
+    let subscriber = get_subscriber(
+        "newsletter_admin".into(),
+        "warn".into(),
+        std::io::stderr,
+        None,
+    );
+    init_subscriber(subscriber);
+
+    let cli = Cli::parse();
+    let configuration = get_configuration().context("Failed to read configuration.")?;
+    let pool = get_connection_pool(&configuration.database);
+
+    match cli.command {
+        Command::CreateUser {
+            username,
+            role,
+            email,
+        } => {
+            let email = email
+                .map(SubscriberEmail::parse)
+                .transpose()
+                .map_err(anyhow::Error::msg)?;
+            let password = read_password()?;
+            let policy = PasswordPolicy::new(&configuration.password_hashing)?;
+            let user_id = create_user(
+                &username,
+                email.as_ref().map(|e| e.as_ref()),
+                password,
+                role.into(),
+                false,
+                &policy,
+                &pool,
+            )
+            .await?
+            .with_context(|| {
+                format!(
+                    "There already is a user named {} or with this email address",
+                    username
+                )
+            })?;
+            let details = serde_json::json!({
+                "user_id": user_id,
+                "username": username,
+                "role": UserRole::from(role).as_str(),
+            });
+            record_command(&pool, AuditAction::UserAdded, details).await?;
+            println!("Created user {} ({})", username, user_id);
+        }
+        Command::ResetPassword { username } => {
+            let password = read_password()?;
+            let policy = PasswordPolicy::new(&configuration.password_hashing)?;
+            let user_id = get_user_id(&username, &pool)
+                .await?
+                .with_context(|| format!("There is no user named {}", username))?;
+            if !change_password(&username, password, &policy, &pool).await? {
+                anyhow::bail!("There is no user named {}", username);
+            }
+            let details = serde_json::json!({ "user_id": user_id });
+            record_command(&pool, AuditAction::PasswordReset, details).await?;
+            println!("Changed the password of {}", username);
+        }
+        Command::DisableTwoFactor { username } => {
+            let user_id = get_user_id(&username, &pool)
+                .await?
+                .with_context(|| format!("There is no user named {}", username))?;
+            disable_two_factor(user_id, &pool).await?;
+            let details = serde_json::json!({ "user_id": user_id });
+            record_command(&pool, AuditAction::TotpDisabled, details).await?;
+            println!("Disabled two-factor authentication for {}", username);
+        }
+        Command::ListSubscribers { status, search } => {
+            let filter = SubscriberFilter {
+                status: parse_status(status)?,
+                search,
+                ..Default::default()
+            };
+            let mut cursor = None;
+            let mut stdout = std::io::stdout().lock();
+            loop {
+                let (subscribers, next_cursor) =
+                    get_subscribers_page(&pool, &filter, cursor.as_ref(), 1000).await?;
+                for s in subscribers {
+                    writeln!(
+                        stdout,
+                        "{}\t{}\t{}\t{}\t{}",
+                        s.id,
+                        s.email,
+                        s.name.as_ref(),
+                        s.status.as_str(),
+                        s.subscribed_at.to_rfc3339()
+                    )?;
+                }
+                match next_cursor {
+                    Some(next_cursor) => cursor = Some(next_cursor),
+                    None => break,
+                }
+            }
+        }
+        Command::Import { file, mode } => {
+            let csv = std::fs::read(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            let email_client = configuration.email_client.client();
+            let report = import_subscribers(
+                &pool,
+                &email_client,
+                &configuration.application.base_url,
+                &csv,
+                mode.into(),
+            )
+            .await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        Command::Export {
+            format,
+            status,
+            output,
+        } => {
+            let filter = SubscriberFilter {
+                status: parse_status(status)?,
+                ..Default::default()
+            };
+            // Exports from the command line are not tied to a user account
+            record_export(&pool, None, format.into(), &filter).await?;
+
+            let mut writer: Box<dyn Write> = match output {
+                Some(path) => Box::new(
+                    std::fs::File::create(&path)
+                        .with_context(|| format!("Failed to create {}", path.display()))?,
+                ),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let mut chunks = Box::pin(export_subscribers(pool, filter, format.into()));
+            while let Some(chunk) = chunks.next().await {
+                writer.write_all(&chunk?)?;
+            }
+            writer.flush()?;
+        }
+        Command::RequeueIssue {
+            newsletter_issue_id,
+        } => {
+            let queued = requeue_issue(&pool, newsletter_issue_id)
+                .await?
+                .with_context(|| format!("There is no newsletter issue {}", newsletter_issue_id))?;
+            println!("Queued {} deliveries of {}", queued, newsletter_issue_id);
+        }
+        Command::RunMigrations => {
+            sqlx::migrate!("./migrations")
+                .run(&pool)
+                .await
+                .context("Failed to migrate the database")?;
+            println!("The database is up to date");
+        }
+    }
+    Ok(())
+}
+
+fn read_password() -> Result<Secret<String>, anyhow::Error> {
+    eprintln!("Password:");
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .context("Failed to read the password from standard input.")?;
+    let password = Secret::new(password.trim_end_matches(['\r', '\n']).to_owned());
+    // The same rules as the forms, which have the user type it twice: a pipe can't make typos
+    validate_new_password(&password, &password).map_err(anyhow::Error::msg)?;
+    Ok(password)
+}
+
+fn parse_status(status: Option<String>) -> Result<Option<SubscriptionStatus>, anyhow::Error> {
+    status
+        .map(SubscriptionStatus::parse)
+        .transpose()
+        .map_err(anyhow::Error::msg)
+}
@@ -1,17 +1,33 @@
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
     ConnectOptions,
 };
 
-use crate::domain::SubscriberEmail;
+use crate::domain::{SubscriberEmail, UserRole};
+use crate::email_client::EmailClient;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub bootstrap_admin: BootstrapAdminSettings,
+    pub two_factor: TwoFactorSettings,
+    pub login_throttle: LoginThrottleSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub subscription_protection: SubscriptionProtectionSettings,
+    pub password_reset: PasswordResetSettings,
+    pub security_headers: SecurityHeadersSettings,
+    pub metrics: MetricsSettings,
+    pub opentelemetry: OpenTelemetrySettings,
+    pub health: HealthSettings,
+    pub delivery: DeliverySettings,
+    pub audit_log: AuditLogSettings,
+    pub tracking: TrackingSettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -21,6 +37,11 @@ pub struct ApplicationSettings {
     pub host: String,
     pub hmac_secret: Secret<String>,
     pub base_url: String,
+    // Once asked to stop, requests and deliveries in progress get this long to finish
+    pub shutdown_timeout_seconds: u64,
+    // The reverse proxies in front of us that append to `X-Forwarded-For`,
+    // for the settings that `trust_forwarded_headers`
+    pub trusted_proxies: usize,
 }
 
 // all fields in a type have to be deserializable in order for the type as a whole (Settings) to be deserializable.
@@ -46,6 +67,129 @@ pub struct EmailClientSettings {
     pub timeout_milliseconds: u64,
 }
 
+// The user created on the first start, when the `users` table is still empty.
+// Without a `password_hash` (an Argon2 PHC string) a one-time password is generated and logged,
+// and it has to be changed on the first login.
+#[derive(serde::Deserialize, Clone)]
+pub struct BootstrapAdminSettings {
+    pub username: String,
+    pub password_hash: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct TwoFactorSettings {
+    // Shown by authenticator apps next to the username
+    pub issuer: String,
+    // Users with these roles must set up TOTP before they can do anything else
+    pub required_for: Vec<UserRole>,
+}
+
+// Argon2id parameters for new password hashes.
+// Raising them upgrades the stored hash of each user the next time they log in.
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordHashingSettings {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+// Failed logins are counted per username and per IP address, see `login_throttle`.
+#[derive(serde::Deserialize, Clone)]
+pub struct LoginThrottleSettings {
+    // Failures allowed for a username before every new attempt has to wait, twice as long every time
+    pub free_attempts: u32,
+    pub base_delay_milliseconds: u64,
+    pub username_lockout_after: u32,
+    // An IP address may be shared by many users (NAT, proxies): it is only ever locked out, never delayed
+    pub ip_lockout_after: u32,
+    pub lockout_seconds: u64,
+    // Only behind a reverse proxy that sets `X-Forwarded-For`, see `application.trusted_proxies`
+    pub trust_forwarded_headers: bool,
+}
+
+// What keeps bots from signing strangers up, see `subscription_guard`.
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionProtectionSettings {
+    // Humans take a few seconds to fill in the form, bots don't
+    pub min_fill_seconds: u64,
+    // Older forms have to be reloaded, so that a bot cannot reuse one forever
+    pub max_form_age_seconds: u64,
+    pub per_ip_per_hour: u32,
+    pub per_email_domain_per_hour: u32,
+    // Only behind a reverse proxy that sets `X-Forwarded-For`, see `application.trusted_proxies`
+    pub trust_forwarded_headers: bool,
+}
+
+// Password reset requests are counted per IP address and per email address, see `password_reset`.
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordResetSettings {
+    pub per_ip_per_hour: u32,
+    pub per_address_per_hour: u32,
+    // Only behind a reverse proxy that sets `X-Forwarded-For`, see `application.trusted_proxies`
+    pub trust_forwarded_headers: bool,
+}
+
+// See `security_headers`.
+#[derive(serde::Deserialize, Clone)]
+pub struct SecurityHeadersSettings {
+    // HSTS is only sent if this isn't 0: browsers will then refuse plain HTTP for that long
+    pub hsts_max_age_seconds: u64,
+    // Where browsers report Content-Security-Policy violations
+    pub csp_report_uri: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct MetricsSettings {
+    // `/metrics` gets a server of its own on this port, e.g. one only reachable from inside the cluster.
+    // Without it, `/metrics` is served along with the application.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub port: Option<u16>,
+}
+
+// See `telemetry::get_tracer_provider`.
+#[derive(serde::Deserialize, Clone)]
+pub struct OpenTelemetrySettings {
+    // The OTLP/HTTP endpoint of a collector, e.g. `http://localhost:4318/v1/traces`.
+    // Spans aren't exported without one.
+    pub otlp_endpoint: Option<String>,
+    pub timeout_milliseconds: u64,
+}
+
+// See `/health/ready`.
+#[derive(serde::Deserialize, Clone)]
+pub struct HealthSettings {
+    // Each check fails if it takes longer than this
+    pub timeout_milliseconds: u64,
+    // The application can start without the email delivery service, but not confirm subscriptions
+    pub check_email_backend: bool,
+}
+
+// See `issue_delivery_worker`.
+#[derive(serde::Deserialize, Clone)]
+pub struct DeliverySettings {
+    // How often an idle worker looks for new emails to send
+    pub poll_interval_milliseconds: u64,
+    // Failed emails are sent again after this long, twice as long every time
+    pub retry_delay_seconds: u64,
+    pub max_retries: u32,
+}
+
+// See `audit_log`.
+#[derive(serde::Deserialize, Clone)]
+pub struct AuditLogSettings {
+    // Only behind a reverse proxy that sets `X-Forwarded-For`, see `application.trusted_proxies`
+    pub trust_forwarded_headers: bool,
+}
+
+// See `tracking`.
+#[derive(serde::Deserialize, Clone)]
+pub struct TrackingSettings {
+    // Adds a tracking pixel to the newsletter emails and records when it is loaded
+    pub opens: bool,
+    // Sends the links of the newsletter emails through `/t/c` and records which are followed
+    pub clicks: bool,
+}
+
 pub enum Environment {
     Local,
     Production,
@@ -100,7 +244,15 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         .build()?;
 
     // Try to convert the configuration values it read into our Settings type (which will give us the Implementations for each, and their methods)
-    settings.try_deserialize::<Settings>()
+    let settings = settings.try_deserialize::<Settings>()?;
+
+    // Along with the application, `/metrics` would be public
+    if let (Environment::Production, None) = (&environment, settings.metrics.port) {
+        return Err(config::ConfigError::Message(
+            "`metrics.port` must be set in production".into(),
+        ));
+    }
+    Ok(settings)
 }
 
 impl DatabaseSettings {
@@ -121,13 +273,24 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        EmailClient::new(
+            self.base_url,
+            self.authorization_token,
+            sender_email,
+            timeout,
+        )
+    }
+
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
@@ -136,4 +299,42 @@ impl EmailClientSettings {
         SubscriberEmail::parse(self.sender_email.clone())
     }
 }
+
+impl OpenTelemetrySettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_timeout_seconds)
+    }
+}
+
+impl DeliverySettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_milliseconds)
+    }
+
+    pub fn retry_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.retry_delay_seconds)
+    }
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+impl LoginThrottleSettings {
+    pub fn base_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.base_delay_milliseconds)
+    }
+
+    pub fn lockout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lockout_seconds)
+    }
+}
 //7.3. SKELETONANDPRINCIPLESFORAMAINTAINABLETESTSUITE
@@ -0,0 +1,211 @@
+use crate::startup::HmacSecret;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpRequest, ResponseError};
+use futures_util::future::LocalBoxFuture;
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use sha2::Sha256;
+use std::future::{ready, Ready};
+
+const COOKIE_NAME: &str = "csrf_nonce";
+const FIELD_NAME: &str = "csrf_token";
+
+/*
+Protection against cross-site form submissions, with signed double-submit tokens:
+every browser gets a random nonce in a cookie, and our forms carry an HMAC of that nonce.
+Another site can make a browser post a form to us, but it can read neither the cookie nor our pages,
+and it cannot compute the HMAC without `HmacSecret`: a matching pair can only come from one of our pages.
+It works before anybody has logged in, which is what makes login CSRF impossible.
+
+Only forms relying on cookies need this: the JSON API authenticates every request explicitly,
+and the public subscription forms do nothing that a direct request couldn't.
+*/
+
+/// The CSRF token of the browser asking for a page, to embed in the forms of that page.
+/// The response must also set `cookie()`, or the token will be rejected.
+pub struct CsrfToken {
+    nonce: String,
+    token: String,
+}
+
+impl CsrfToken {
+    /// A hidden input carrying the token, to put inside a `<form>`.
+    pub fn form_field(&self) -> String {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            FIELD_NAME, self.token
+        )
+    }
+
+    pub fn cookie(&self) -> Cookie<'static> {
+        Cookie::build(COOKIE_NAME, self.nonce.clone())
+            .path("/")
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::Strict)
+            .finish()
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<CsrfToken, Self::Error>>;
+
+    // The nonce of the browser is kept, so that pages opened in several tabs all remain valid.
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let result = hmac_secret(req).map(|secret| {
+            let nonce = match req.cookie(COOKIE_NAME) {
+                Some(cookie) if is_valid_nonce(cookie.value()) => cookie.value().to_owned(),
+                _ => generate_nonce(),
+            };
+            let token = compute_token(secret, &nonce);
+            CsrfToken { nonce, token }
+        });
+        ready(result)
+    }
+}
+
+/// A URL-encoded form that is only accepted with the CSRF token of the browser submitting it,
+/// to use instead of `web::Form` on routes that act on behalf of a cookie (session or flash messages).
+pub struct CsrfForm<T>(pub T);
+
+impl<T> CsrfForm<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for CsrfForm<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct SubmittedToken {
+    csrf_token: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("This form has expired or was not sent from this site. Reload the page and try again.")]
+pub struct CsrfError;
+
+impl ResponseError for CsrfError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+impl<T> FromRequest for CsrfForm<T>
+where
+    T: DeserializeOwned + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<CsrfForm<T>, Self::Error>>;
+
+    // The body is parsed twice: once for the token, once for the form itself.
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let body = web::Bytes::from_request(&req, payload);
+        Box::pin(async move {
+            let body = body.await?;
+            let submitted: SubmittedToken =
+                serde_urlencoded::from_bytes(&body).map_err(|_| CsrfError)?;
+            let nonce = req.cookie(COOKIE_NAME).ok_or(CsrfError)?;
+            if !verify_token(hmac_secret(&req)?, nonce.value(), &submitted.csrf_token) {
+                return Err(CsrfError.into());
+            }
+            let form =
+                serde_urlencoded::from_bytes(&body).map_err(actix_web::error::ErrorBadRequest)?;
+            Ok(CsrfForm(form))
+        })
+    }
+}
+
+fn hmac_secret(req: &HttpRequest) -> Result<&HmacSecret, actix_web::Error> {
+    req.app_data::<web::Data<HmacSecret>>()
+        .map(|secret| secret.get_ref())
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("HmacSecret is not registered"))
+}
+
+fn generate_nonce() -> String {
+    std::iter::repeat_with(|| rand::thread_rng().sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+// Anything else in the cookie was not set by us: a fresh nonce replaces it.
+fn is_valid_nonce(nonce: &str) -> bool {
+    nonce.len() == 32 && nonce.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn mac(secret: &HmacSecret, nonce: &str) -> Hmac<Sha256> {
+    let mut mac = secret.mac(b"csrf:");
+    mac.update(nonce.as_bytes());
+    mac
+}
+
+fn compute_token(secret: &HmacSecret, nonce: &str) -> String {
+    hex::encode(mac(secret, nonce).finalize().into_bytes())
+}
+
+fn verify_token(secret: &HmacSecret, nonce: &str, token: &str) -> bool {
+    let token = match hex::decode(token) {
+        Ok(token) => token,
+        Err(_) => return false,
+    };
+    // In constant time
+    mac(secret, nonce).verify_slice(&token).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn secret(s: &str) -> HmacSecret {
+        HmacSecret(Secret::new(s.to_owned()))
+    }
+
+    #[test]
+    fn a_token_is_valid_for_its_nonce() {
+        let nonce = generate_nonce();
+        let token = compute_token(&secret("secret"), &nonce);
+        assert!(verify_token(&secret("secret"), &nonce, &token));
+    }
+
+    #[test]
+    fn a_token_is_not_valid_for_another_nonce() {
+        let token = compute_token(&secret("secret"), &generate_nonce());
+        assert!(!verify_token(&secret("secret"), &generate_nonce(), &token));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let nonce = generate_nonce();
+        let token = compute_token(&secret("another secret"), &nonce);
+        assert!(!verify_token(&secret("secret"), &nonce, &token));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let nonce = generate_nonce();
+        assert!(!verify_token(&secret("secret"), &nonce, ""));
+        assert!(!verify_token(&secret("secret"), &nonce, "not hex"));
+        assert!(!verify_token(&secret("secret"), &nonce, &nonce));
+    }
+
+    #[test]
+    fn generated_nonces_are_accepted_and_others_are_not() {
+        assert!(is_valid_nonce(&generate_nonce()));
+        assert!(!is_valid_nonce("short"));
+        assert!(!is_valid_nonce(&"é".repeat(16)));
+    }
+}
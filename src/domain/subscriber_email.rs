@@ -31,7 +31,7 @@ impl AsRef<str> for SubscriberEmail {
 #[cfg(test)]
 mod tests {
     use super::SubscriberEmail;
-    use claim::{assert_err, assert_ok};
+    use claim::assert_err;
     use fake::{faker::internet::en::SafeEmail, Fake};
 
     #[derive(Debug, Clone)]
@@ -0,0 +1,60 @@
+// The lifecycle states a row in `subscriptions` can be in.
+// `status` is stored as TEXT, so this is the only place that knows which values are legal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl SubscriptionStatus {
+    pub fn parse(s: String) -> Result<SubscriptionStatus, String> {
+        match s.as_str() {
+            "pending_confirmation" => Ok(Self::PendingConfirmation),
+            "confirmed" => Ok(Self::Confirmed),
+            "unsubscribed" => Ok(Self::Unsubscribed),
+            other => Err(format!("{} is not a valid subscription status", other)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PendingConfirmation => "pending_confirmation",
+            Self::Confirmed => "confirmed",
+            Self::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+impl AsRef<str> for SubscriptionStatus {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionStatus;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn known_statuses_are_parsed_successfully() {
+        for status in [
+            SubscriptionStatus::PendingConfirmation,
+            SubscriptionStatus::Confirmed,
+            SubscriptionStatus::Unsubscribed,
+        ] {
+            assert_ok_eq!(SubscriptionStatus::parse(status.as_str().into()), status);
+        }
+    }
+
+    #[test]
+    fn unknown_status_is_rejected() {
+        assert_err!(SubscriptionStatus::parse("deleted".into()));
+    }
+
+    #[test]
+    fn status_parsing_is_case_sensitive() {
+        assert_err!(SubscriptionStatus::parse("Confirmed".into()));
+    }
+}
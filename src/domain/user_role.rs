@@ -0,0 +1,55 @@
+// What a user is allowed to do is derived from their role, see `authorization::Permission`.
+// `role` is stored as TEXT, so this is the only place that knows which values are legal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    // Everything, including managing other users
+    Owner,
+    // Manage subscribers and publish issues
+    Editor,
+    // Read-only access to subscribers
+    Viewer,
+}
+
+impl UserRole {
+    pub fn parse(s: String) -> Result<UserRole, String> {
+        match s.as_str() {
+            "owner" => Ok(Self::Owner),
+            "editor" => Ok(Self::Editor),
+            "viewer" => Ok(Self::Viewer),
+            other => Err(format!("{} is not a valid role", other)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Editor => "editor",
+            Self::Viewer => "viewer",
+        }
+    }
+}
+
+impl AsRef<str> for UserRole {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UserRole;
+    use claim::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn known_roles_are_parsed_successfully() {
+        for role in [UserRole::Owner, UserRole::Editor, UserRole::Viewer] {
+            assert_ok_eq!(UserRole::parse(role.as_str().into()), role);
+        }
+    }
+
+    #[test]
+    fn unknown_role_is_rejected() {
+        assert_err!(UserRole::parse("admin".into()));
+    }
+}
@@ -1,6 +1,11 @@
 use crate::domain::SubscriberEmail;
+use crate::request_id::{RequestId, REQUEST_ID_HEADER};
+use opentelemetry::global;
+use opentelemetry_http::HeaderInjector;
+use reqwest::header::HeaderMap;
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 pub struct EmailClient {
     http_client: Client,
@@ -35,6 +40,12 @@ impl EmailClient {
         }
     }
 
+    /// Whether the email delivery service answers at all, whatever it answers.
+    pub async fn check_reachable(&self) -> Result<(), reqwest::Error> {
+        self.http_client.head(&self.base_url).send().await?;
+        Ok(())
+    }
+
     pub async fn send_email(
         &self,
         recipient: &SubscriberEmail,
@@ -51,6 +62,19 @@ impl EmailClient {
             text_body: text_content,
         };
 
+        // The email delivery service can join our trace (`traceparent`),
+        // and its support can find our logs (`X-Request-Id`)
+        let mut trace_headers = HeaderMap::new();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(
+                &tracing::Span::current().context(),
+                &mut HeaderInjector(&mut trace_headers),
+            )
+        });
+        if let Some(request_id) = RequestId::current() {
+            trace_headers.insert(REQUEST_ID_HEADER, request_id.as_ref().parse().unwrap());
+        }
+
         // json function also changes the content type of the request for us
         self.http_client
             .post(&url)
@@ -58,6 +82,7 @@ impl EmailClient {
                 "X-Postmark-Server-Token",
                 self.authorization_token.expose_secret(),
             )
+            .headers(trace_headers)
             .json(&request_body)
             .send()
             .await?
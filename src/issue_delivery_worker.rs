@@ -0,0 +1,411 @@
+use crate::configuration::{DeliverySettings, TrackingSettings};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::metrics::{record_email, EmailKind};
+use crate::startup::HmacSecret;
+use crate::tracking::{
+    add_open_pixel, click_url, generate_tracking_token, open_pixel_url, record_delivery,
+    track_html_links, track_text_links,
+};
+use anyhow::Context;
+use chrono::Utc;
+use htmlescape::encode_minimal;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+// How long to wait before looking at the queue again after an unexpected error.
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Tells the delivery worker (and the rest of the application) to stop.
+#[derive(Clone)]
+pub struct Shutdown(Arc<watch::Sender<bool>>);
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self(Arc::new(watch::channel(false).0))
+    }
+
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once `trigger` has been called.
+    pub async fn triggered(&self) {
+        let mut receiver = self.0.subscribe();
+        while !*receiver.borrow() {
+            // Only fails if the sender is gone, and we are the sender
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sends the emails of the delivery queue, one at a time, until `shutdown` is triggered.
+/// The email being sent when it is triggered is sent (or fails) before this returns:
+/// if this is dropped instead, its task stays in the queue and is sent again later.
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: EmailClient,
+    settings: DeliverySettings,
+    base_url: String,
+    tracking: TrackingSettings,
+    hmac_secret: HmacSecret,
+    shutdown: Shutdown,
+) {
+    while !shutdown.is_triggered() {
+        let outcome = try_execute_task(
+            &pool,
+            &email_client,
+            &settings,
+            &base_url,
+            &tracking,
+            &hmac_secret,
+        )
+        .await;
+        let idle_for = match outcome {
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+            Ok(ExecutionOutcome::EmptyQueue) => settings.poll_interval(),
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, "Failed to deliver a newsletter issue.");
+                ERROR_BACKOFF
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(idle_for) => {}
+            _ = shutdown.triggered() => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id=tracing::field::Empty, subscriber_email=tracing::field::Empty)
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &DeliverySettings,
+    base_url: &str,
+    tracking: &TrackingSettings,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let (transaction, task) = match dequeue_task(pool).await? {
+        Some(task) => task,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current()
+        .record("newsletter_issue_id", display(task.issue_id))
+        .record("subscriber_email", display(&task.email));
+
+    let email = match SubscriberEmail::parse(task.email.clone()) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Skipping a confirmed subscriber. Their stored contact details are invalid",
+            );
+            delete_task(transaction, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    // A token of its own for every attempt: only the one of the email that was sent is kept
+    let token = (tracking.opens || tracking.clicks).then(generate_tracking_token);
+    let mut issue = get_issue(pool, task.issue_id).await?;
+    if let Some(token) = &token {
+        issue = issue.tracked(tracking, base_url, hmac_secret, token);
+    }
+    let issue = issue.linked_to_archive(base_url);
+    let result = email_client
+        .send_email(
+            &email,
+            &issue.title,
+            &issue.html_content,
+            &issue.text_content,
+        )
+        .await;
+    record_email(EmailKind::Newsletter, result.is_ok());
+    match result {
+        Ok(()) => {
+            delete_task(transaction, &task).await?;
+            // The email is out: failing to record it only costs its statistics,
+            // whereas keeping the task around would send it again
+            if let Some(token) = &token {
+                if let Err(e) = record_delivery(pool, token, task.issue_id, &task.email).await {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        "Failed to record a tracked delivery.",
+                    );
+                }
+            }
+        }
+        Err(e) if task.n_retries >= settings.max_retries as i32 => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                "Giving up on delivering a newsletter issue to a confirmed subscriber.",
+            );
+            delete_task(transaction, &task).await?;
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Failed to deliver a newsletter issue to a confirmed subscriber, will retry.",
+            );
+            retry_task_later(transaction, &task, settings).await?;
+        }
+    }
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct Task {
+    issue_id: Uuid,
+    email: String,
+    n_retries: i32,
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+// The row stays locked until the transaction ends: other workers skip it.
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT newsletter_issue_id AS issue_id, subscriber_email AS email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to dequeue a delivery.")?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(mut transaction: PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.issue_id,
+        task.email,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete a delivery.")?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+// Each retry waits twice as long as the previous one.
+#[tracing::instrument(skip_all)]
+async fn retry_task_later(
+    mut transaction: PgTransaction,
+    task: &Task,
+    settings: &DeliverySettings,
+) -> Result<(), anyhow::Error> {
+    let delay = settings.retry_delay() * 2u32.saturating_pow(task.n_retries as u32);
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1, execute_after = $3
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.issue_id,
+        task.email,
+        execute_after,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to postpone a delivery.")?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Queues the issue again for its confirmed subscribers, as they are now, e.g. after an outage
+/// of the email provider. Subscribers it is still queued for are skipped.
+/// Returns how many deliveries were queued, or `None` if there is no such issue.
+#[tracing::instrument(skip(pool))]
+pub async fn requeue_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<u64>, anyhow::Error> {
+    let issue = sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue.")?;
+    if issue.is_none() {
+        return Ok(None);
+    }
+    let queued = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed' AND erased_at IS NULL
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO NOTHING
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to requeue the deliveries of the newsletter issue.")?
+    .rows_affected();
+    Ok(Some(queued))
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+    slug: String,
+    subscriber_only: bool,
+}
+
+impl NewsletterIssue {
+    /// Makes the email identified by `token` trackable, as far as `tracking` allows.
+    fn tracked(
+        mut self,
+        tracking: &TrackingSettings,
+        base_url: &str,
+        hmac_secret: &HmacSecret,
+        token: &str,
+    ) -> Self {
+        if tracking.clicks {
+            let track = |link: &str| click_url(base_url, hmac_secret, token, link);
+            self.html_content = track_html_links(&self.html_content, track);
+            self.text_content = track_text_links(&self.text_content, track);
+        }
+        if tracking.opens {
+            let pixel_url = open_pixel_url(base_url, token);
+            self.html_content = add_open_pixel(&self.html_content, &pixel_url);
+        }
+        self
+    }
+
+    /// Adds a "view in browser" link at the top of public issues, pointing to the archive.
+    fn linked_to_archive(mut self, base_url: &str) -> Self {
+        if self.subscriber_only {
+            return self;
+        }
+        let url = format!("{}/archive/{}", base_url, self.slug);
+        let link = format!(
+            r#"<p><a href="{}">View this issue in your browser</a></p>"#,
+            encode_minimal(&url)
+        );
+        // Right after `<body ...>` if the issue is a full document, at the very top otherwise
+        let at = find_ignore_ascii_case(&self.html_content, "<body")
+            .and_then(|start| {
+                self.html_content[start..]
+                    .find('>')
+                    .map(|end| start + end + 1)
+            })
+            .unwrap_or(0);
+        self.html_content.insert_str(at, &link);
+        self.text_content = format!(
+            "View this issue in your browser: {}\n\n{}",
+            url, self.text_content
+        );
+        self
+    }
+}
+
+fn find_ignore_ascii_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content, slug, subscriber_only
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve a newsletter issue.")?;
+    Ok(issue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NewsletterIssue;
+
+    fn issue(html_content: &str, subscriber_only: bool) -> NewsletterIssue {
+        NewsletterIssue {
+            title: "Title".into(),
+            text_content: "Body".into(),
+            html_content: html_content.into(),
+            slug: "title".into(),
+            subscriber_only,
+        }
+    }
+
+    #[test]
+    fn public_issues_link_to_the_archive() {
+        let issue = issue("<p>Body</p>", false).linked_to_archive("https://example.com");
+        assert_eq!(
+            issue.html_content,
+            r#"<p><a href="https://example.com/archive/title">View this issue in your browser</a></p><p>Body</p>"#
+        );
+        assert_eq!(
+            issue.text_content,
+            "View this issue in your browser: https://example.com/archive/title\n\nBody"
+        );
+    }
+
+    #[test]
+    fn the_link_goes_inside_the_body_of_full_documents() {
+        let issue = issue(
+            r#"<html><BODY class="issue"><p>Body</p></BODY></html>"#,
+            false,
+        )
+        .linked_to_archive("https://example.com");
+        assert!(issue
+            .html_content
+            .starts_with(r#"<html><BODY class="issue"><p><a href="#));
+    }
+
+    #[test]
+    fn subscriber_only_issues_are_left_alone() {
+        let issue = issue("<p>Body</p>", true).linked_to_archive("https://example.com");
+        assert_eq!(issue.html_content, "<p>Body</p>");
+        assert_eq!(issue.text_content, "Body");
+    }
+}
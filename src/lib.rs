@@ -1,7 +1,21 @@
+pub mod audit_log;
 pub mod authentication;
+pub mod authorization;
 pub mod configuration;
+pub mod csrf;
 pub mod domain;
 pub mod email_client;
+pub mod issue_delivery_worker;
+pub mod login_throttle;
+pub mod metrics;
+pub mod password_reset;
+pub mod request_id;
 pub mod routes;
+pub mod security_headers;
+pub mod session_state;
 pub mod startup;
+pub mod subscription_guard;
 pub mod telemetry;
+pub mod tracking;
+pub mod two_factor;
+pub mod utils;
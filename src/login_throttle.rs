@@ -0,0 +1,237 @@
+use crate::configuration::LoginThrottleSettings;
+use crate::utils::client_ip;
+use actix_web::HttpRequest;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Above this many tracked keys, expired entries are dropped on the next failure.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Username(String),
+    Ip(IpAddr),
+}
+
+struct Failures {
+    count: u32,
+    last_at: Instant,
+    blocked_until: Option<Instant>,
+}
+
+/// Counts failed logins, to slow down password guessing.
+/// A username gets a few free attempts, then has to wait longer and longer between attempts,
+/// and is eventually locked out. An IP address is locked out after many more failures.
+/// Blocked attempts are rejected before any password is hashed, so they cost us next to nothing.
+/// The counters live in memory: every instance of the application keeps its own.
+pub struct LoginThrottle {
+    settings: LoginThrottleSettings,
+    // See `utils::client_ip`
+    trusted_proxies: usize,
+    failures: Mutex<HashMap<Key, Failures>>,
+}
+
+impl LoginThrottle {
+    pub fn new(settings: LoginThrottleSettings, trusted_proxies: usize) -> Self {
+        Self {
+            settings,
+            trusted_proxies,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The address failures are counted against.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        client_ip(
+            request,
+            self.settings.trust_forwarded_headers,
+            self.trusted_proxies,
+        )
+    }
+
+    /// Returns how long to wait if `username` or `ip` may not try again yet.
+    pub fn check(&self, username: &str, ip: Option<IpAddr>) -> Result<(), Duration> {
+        self.check_at(username, ip, Instant::now())
+    }
+
+    pub fn record_failure(&self, username: &str, ip: Option<IpAddr>) {
+        self.record_failure_at(username, ip, Instant::now())
+    }
+
+    // The IP address is not forgiven: an attacker could otherwise interleave their own account.
+    pub fn record_success(&self, username: &str) {
+        self.failures
+            .lock()
+            .unwrap()
+            .remove(&Key::Username(username.to_owned()));
+    }
+
+    fn check_at(&self, username: &str, ip: Option<IpAddr>, now: Instant) -> Result<(), Duration> {
+        let failures = self.failures.lock().unwrap();
+        let wait = keys(username, ip)
+            .filter_map(|key| failures.get(&key))
+            .filter_map(|f| f.blocked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max();
+        match wait {
+            Some(wait) => Err(wait),
+            None => Ok(()),
+        }
+    }
+
+    fn record_failure_at(&self, username: &str, ip: Option<IpAddr>, now: Instant) {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() > PRUNE_THRESHOLD {
+            let lockout = self.settings.lockout();
+            failures.retain(|_, f| !self.is_expired(f, lockout, now));
+        }
+        for key in keys(username, ip) {
+            let entry = failures.entry(key.clone()).or_insert(Failures {
+                count: 0,
+                last_at: now,
+                blocked_until: None,
+            });
+            // A clean slate once the last failure is long gone
+            if self.is_expired(entry, self.settings.lockout(), now) {
+                entry.count = 0;
+                entry.blocked_until = None;
+            }
+            entry.count += 1;
+            entry.last_at = now;
+            if let Some(delay) = self.delay(&key, entry.count) {
+                entry.blocked_until = Some(now + delay);
+            }
+        }
+    }
+
+    // How long the next attempt has to wait after `count` failures, if at all.
+    fn delay(&self, key: &Key, count: u32) -> Option<Duration> {
+        let lockout = self.settings.lockout();
+        match key {
+            Key::Username(_) if count >= self.settings.username_lockout_after => Some(lockout),
+            Key::Username(_) if count > self.settings.free_attempts => {
+                let exponent = (count - self.settings.free_attempts - 1).min(16);
+                Some((self.settings.base_delay() * 2u32.pow(exponent)).min(lockout))
+            }
+            Key::Ip(_) if count >= self.settings.ip_lockout_after => Some(lockout),
+            _ => None,
+        }
+    }
+
+    fn is_expired(&self, failures: &Failures, lockout: Duration, now: Instant) -> bool {
+        let blocked = failures.blocked_until.is_some_and(|until| until > now);
+        !blocked && now.duration_since(failures.last_at) > lockout
+    }
+}
+
+fn keys(username: &str, ip: Option<IpAddr>) -> impl Iterator<Item = Key> {
+    std::iter::once(Key::Username(username.to_owned())).chain(ip.map(Key::Ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle::new(
+            LoginThrottleSettings {
+                free_attempts: 2,
+                base_delay_milliseconds: 1000,
+                username_lockout_after: 5,
+                ip_lockout_after: 8,
+                lockout_seconds: 900,
+                trust_forwarded_headers: false,
+            },
+            0,
+        )
+    }
+
+    fn ip() -> Option<IpAddr> {
+        Some("192.0.2.1".parse().unwrap())
+    }
+
+    #[test]
+    fn the_first_failures_are_free() {
+        let throttle = throttle();
+        let now = Instant::now();
+        throttle.record_failure_at("ursula", ip(), now);
+        throttle.record_failure_at("ursula", ip(), now);
+        assert_eq!(throttle.check_at("ursula", ip(), now), Ok(()));
+    }
+
+    #[test]
+    fn delays_double_after_the_free_failures() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for _ in 0..3 {
+            throttle.record_failure_at("ursula", ip(), now);
+        }
+        assert_eq!(
+            throttle.check_at("ursula", ip(), now),
+            Err(Duration::from_secs(1))
+        );
+        throttle.record_failure_at("ursula", ip(), now);
+        assert_eq!(
+            throttle.check_at("ursula", ip(), now),
+            Err(Duration::from_secs(2))
+        );
+        assert_eq!(
+            throttle.check_at("ursula", ip(), now + Duration::from_secs(2)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn usernames_are_locked_out_after_too_many_failures() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for _ in 0..5 {
+            throttle.record_failure_at("ursula", ip(), now);
+        }
+        assert_eq!(
+            throttle.check_at("ursula", None, now),
+            Err(Duration::from_secs(900))
+        );
+        // Other users behind the same address are not affected yet
+        assert_eq!(throttle.check_at("victor", ip(), now), Ok(()));
+    }
+
+    #[test]
+    fn ip_addresses_are_locked_out_after_too_many_failures() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for i in 0..8 {
+            throttle.record_failure_at(&format!("user-{}", i), ip(), now);
+        }
+        assert!(throttle.check_at("victor", ip(), now).is_err());
+        assert_eq!(throttle.check_at("victor", None, now), Ok(()));
+    }
+
+    #[test]
+    fn a_success_forgives_the_username_but_not_the_ip_address() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for i in 0..7 {
+            throttle.record_failure_at(&format!("user-{}", i % 2), ip(), now);
+        }
+        throttle.record_success("user-0");
+        assert_eq!(throttle.check_at("user-0", None, now), Ok(()));
+        throttle.record_failure_at("user-0", ip(), now);
+        assert!(throttle.check_at("user-0", ip(), now).is_err());
+    }
+
+    #[test]
+    fn old_failures_are_forgotten() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for _ in 0..4 {
+            throttle.record_failure_at("ursula", ip(), now);
+        }
+        let later = now + Duration::from_secs(901);
+        throttle.record_failure_at("ursula", ip(), later);
+        assert_eq!(throttle.check_at("ursula", ip(), later), Ok(()));
+    }
+}
@@ -1,23 +1,37 @@
 use rust_newsletter::configuration::get_configuration;
 
-use rust_newsletter::startup::Application;
-use rust_newsletter::telemetry::{get_subscriber, init_subscriber};
+use opentelemetry::trace::TracerProvider;
+use rust_newsletter::startup::{shutdown_on_signal, Application};
+use rust_newsletter::telemetry::{get_subscriber, get_tracer_provider, init_subscriber};
 
 // run: `cargo +nightly expand --bin rust-newsletter-bin` (use nightly compiler for the 'expand' cmd only) to view macro expansion
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
     // Old logger:
     // `init` does call `set_logger`, so this is all we need to do.
     // We are falling back to printing all logs at info-level or above
     // if the RUST_LOG environment variable has not been set.
     // env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    let subscriber = get_subscriber("rust_newsletter".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
-
     // Panic if we cannot read the config
     let configuration = get_configuration().expect("Failed to read configuration.");
+
+    let tracer_provider = get_tracer_provider("rust_newsletter", &configuration.opentelemetry)?;
+    let tracer = tracer_provider
+        .as_ref()
+        .map(|provider| provider.tracer("rust_newsletter"));
+    let subscriber = get_subscriber(
+        "rust_newsletter".into(),
+        "info".into(),
+        std::io::stdout,
+        tracer,
+    );
+    init_subscriber(subscriber);
+
     let application = Application::build(configuration).await?;
+    tokio::spawn(shutdown_on_signal(application.shutdown()));
     application.run_until_stopped().await?;
+    // Export the spans that are still buffered
+    drop(tracer_provider);
     Ok(())
 }
@@ -0,0 +1,194 @@
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use futures_util::future::LocalBoxFuture;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sqlx::PgPool;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// Shared by every instance of the application in the process, like the logs.
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+#[derive(Debug, Clone, Copy)]
+pub enum EmailKind {
+    Confirmation,
+    Newsletter,
+    PasswordReset,
+}
+
+impl EmailKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EmailKind::Confirmation => "confirmation",
+            EmailKind::Newsletter => "newsletter",
+            EmailKind::PasswordReset => "password_reset",
+        }
+    }
+}
+
+/// What we measure, exposed in the Prometheus text format on `/metrics`.
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    emails: IntCounterVec,
+    password_verification_duration: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("newsletter".into()), None)
+            .expect("The metrics prefix is valid");
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to handle an HTTP request",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Connections of the Postgres pool"),
+            &["state"],
+        )
+        .unwrap();
+        let emails = IntCounterVec::new(
+            Opts::new(
+                "emails_total",
+                "Emails handed to the email delivery service",
+            ),
+            &["kind", "outcome"],
+        )
+        .unwrap();
+        // Argon2 is deliberately slow: the default buckets are too fine
+        let password_verification_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "password_verification_duration_seconds",
+                "Time to verify a password against its Argon2 hash",
+            )
+            .buckets(vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]),
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .unwrap();
+        registry.register(Box::new(emails.clone())).unwrap();
+        registry
+            .register(Box::new(password_verification_duration.clone()))
+            .unwrap();
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            emails,
+            password_verification_duration,
+        }
+    }
+}
+
+pub fn record_email(kind: EmailKind, sent: bool) {
+    let outcome = if sent { "sent" } else { "failed" };
+    METRICS
+        .emails
+        .with_label_values(&[kind.as_str(), outcome])
+        .inc();
+}
+
+pub fn record_password_verification(duration: Duration) {
+    METRICS
+        .password_verification_duration
+        .observe(duration.as_secs_f64());
+}
+
+/// All metrics in the Prometheus text format, with the current state of `pool`.
+pub fn render(pool: &PgPool) -> String {
+    let size = pool.size() as i64;
+    let idle = pool.num_idle() as i64;
+    let connections = &METRICS.db_pool_connections;
+    connections.with_label_values(&["idle"]).set(idle);
+    connections.with_label_values(&["in_use"]).set(size - idle);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut buffer)
+        .expect("Metrics can always be encoded as text");
+    String::from_utf8(buffer).expect("The text format is UTF-8")
+}
+
+/// Counts and times every request, labelled with the pattern of its route
+/// (e.g. `/admin/users/{user_id}/role`) rather than its path, to keep the number of series bounded.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started_at = Instant::now();
+        let method = req.method().to_string();
+        let service = self.service.clone();
+        Box::pin(async move {
+            let res = service.call(req).await?;
+            // Only known once the request has been routed
+            let route = res
+                .request()
+                .match_pattern()
+                .unwrap_or_else(|| "<unmatched>".to_owned());
+            let status = res.status().as_u16().to_string();
+            METRICS
+                .http_requests
+                .with_label_values(&[&method, &route, &status])
+                .inc();
+            METRICS
+                .http_request_duration
+                .with_label_values(&[&method, &route])
+                .observe(started_at.elapsed().as_secs_f64());
+            Ok(res)
+        })
+    }
+}
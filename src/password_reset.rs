@@ -0,0 +1,297 @@
+use crate::configuration::PasswordResetSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::metrics::{record_email, EmailKind};
+use crate::utils::client_ip;
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+use uuid::Uuid;
+
+// Long enough to get to the mailbox, short enough that an old email is useless.
+const TOKEN_LIFETIME_MINUTES: i64 = 60;
+// Requests are counted over fixed windows of this length.
+const WINDOW: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+// Above this many tracked keys, finished windows are dropped on the next request.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Ip(IpAddr),
+    Address(String),
+}
+
+struct Window {
+    started_at: Instant,
+    count: u32,
+}
+
+/// Keeps the forgot password form from being used to flood a mailbox, or our email provider.
+/// Requests are limited per IP address and per email address, whether or not an account uses it.
+/// The counters live in memory: every instance of the application keeps its own.
+pub struct PasswordResetThrottle {
+    settings: PasswordResetSettings,
+    // See `utils::client_ip`
+    trusted_proxies: usize,
+    windows: Mutex<HashMap<Key, Window>>,
+}
+
+impl PasswordResetThrottle {
+    pub fn new(settings: PasswordResetSettings, trusted_proxies: usize) -> Self {
+        Self {
+            settings,
+            trusted_proxies,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        client_ip(
+            request,
+            self.settings.trust_forwarded_headers,
+            self.trusted_proxies,
+        )
+    }
+
+    /// Counts a request against its IP address and email address,
+    /// unless one of them is already over its limit: then it is not allowed.
+    pub fn allow(&self, ip: Option<IpAddr>, email: &SubscriberEmail) -> bool {
+        self.allow_at(ip, email, Instant::now())
+    }
+
+    fn allow_at(&self, ip: Option<IpAddr>, email: &SubscriberEmail, now: Instant) -> bool {
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() > PRUNE_THRESHOLD {
+            windows.retain(|_, w| now.duration_since(w.started_at) < WINDOW);
+        }
+        let keys: Vec<Key> = ip
+            .map(Key::Ip)
+            .into_iter()
+            .chain(std::iter::once(Key::Address(email.as_ref().to_lowercase())))
+            .collect();
+        // Nothing is counted unless every key is under its limit
+        for key in &keys {
+            if let Some(window) = windows.get(key) {
+                let current = now.duration_since(window.started_at) < WINDOW;
+                if current && window.count >= self.limit(key) {
+                    return false;
+                }
+            }
+        }
+        for key in keys {
+            let window = windows.entry(key).or_insert(Window {
+                started_at: now,
+                count: 0,
+            });
+            if now.duration_since(window.started_at) >= WINDOW {
+                window.started_at = now;
+                window.count = 0;
+            }
+            window.count += 1;
+        }
+        true
+    }
+
+    fn limit(&self, key: &Key) -> u32 {
+        match key {
+            Key::Ip(_) => self.settings.per_ip_per_hour,
+            Key::Address(_) => self.settings.per_address_per_hour,
+        }
+    }
+}
+
+/// Email a reset link to the active user with this address, if there is one.
+/// Callers must not tell the requester whether anything was sent.
+#[tracing::instrument(name = "Send a password reset link", skip(email, pool, email_client))]
+pub async fn send_reset_link(
+    email: &SubscriberEmail,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
+    let token = match create_reset_token(email, pool).await? {
+        Some(token) => token,
+        None => return Ok(()),
+    };
+    let reset_link = format!(
+        "{}/password/reset?token={}",
+        base_url,
+        token.expose_secret()
+    );
+    let plain_body = format!(
+        "Somebody asked to reset the password of your newsletter account.\n\
+        Visit {} to choose a new one. The link expires in {} minutes.\n\
+        If it wasn't you, you can ignore this email.",
+        reset_link, TOKEN_LIFETIME_MINUTES
+    );
+    let html_body = format!(
+        "Somebody asked to reset the password of your newsletter account.<br />\
+        Click <a href=\"{}\">here</a> to choose a new one. The link expires in {} minutes.<br />\
+        If it wasn't you, you can ignore this email.",
+        reset_link, TOKEN_LIFETIME_MINUTES
+    );
+    let result = email_client
+        .send_email(email, "Reset your password", &html_body, &plain_body)
+        .await;
+    record_email(EmailKind::PasswordReset, result.is_ok());
+    result.context("Failed to send a password reset email.")
+}
+
+// Returns `None` if no active user has this email address.
+async fn create_reset_token(
+    email: &SubscriberEmail,
+    pool: &PgPool,
+) -> Result<Option<Secret<String>>, anyhow::Error> {
+    let token = generate_reset_token();
+    let now = Utc::now();
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        SELECT $1, user_id, $2, $3
+        FROM users
+        WHERE LOWER(email) = LOWER($4) AND disabled_at IS NULL
+        RETURNING user_id
+        "#,
+        hash_token(&token),
+        now,
+        now + Duration::minutes(TOKEN_LIFETIME_MINUTES),
+        email.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to store a password reset token.")?;
+    Ok(row.map(|_| token))
+}
+
+/// Whether the token can still be used, without using it.
+#[tracing::instrument(name = "Check a password reset token", skip(token, pool))]
+pub async fn is_valid_reset_token(
+    token: &Secret<String>,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT 1 AS "exists!"
+        FROM password_reset_tokens t
+        JOIN users u ON u.user_id = t.user_id
+        WHERE t.token_hash = $1 AND t.used_at IS NULL AND t.expires_at > $2
+            AND u.disabled_at IS NULL
+        "#,
+        hash_token(token),
+        Utc::now(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a password reset token.")?;
+    Ok(row.is_some())
+}
+
+/// Mark the token as used and return the user it was issued for,
+/// or `None` if it is unknown, expired or already used.
+/// Every other pending token of the user is used up at the same time.
+#[tracing::instrument(name = "Use a password reset token", skip(token, pool))]
+pub async fn consume_reset_token(
+    token: &Secret<String>,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let now = Utc::now();
+    let row = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens SET used_at = $2
+        WHERE user_id = (
+            SELECT t.user_id
+            FROM password_reset_tokens t
+            JOIN users u ON u.user_id = t.user_id
+            WHERE t.token_hash = $1 AND t.used_at IS NULL AND t.expires_at > $2
+                AND u.disabled_at IS NULL
+        ) AND used_at IS NULL
+        RETURNING user_id
+        "#,
+        hash_token(token),
+        now,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to use a password reset token.")?;
+    Ok(row.first().map(|r| r.user_id))
+}
+
+fn generate_reset_token() -> Secret<String> {
+    let token: String = std::iter::repeat_with(|| rand::thread_rng().sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect();
+    Secret::new(token)
+}
+
+// Tokens are random enough that a fast hash will do, like API tokens.
+fn hash_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> PasswordResetThrottle {
+        PasswordResetThrottle::new(
+            PasswordResetSettings {
+                per_ip_per_hour: 3,
+                per_address_per_hour: 2,
+                trust_forwarded_headers: false,
+            },
+            0,
+        )
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_owned()).unwrap()
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn an_ip_address_is_limited_whatever_the_email_address() {
+        let throttle = throttle();
+        let now = Instant::now();
+        for user in ["a", "b", "c"] {
+            let email = email(&format!("{}@example.com", user));
+            assert!(throttle.allow_at(ip("192.0.2.1"), &email, now));
+        }
+        let email = email("d@example.com");
+        assert!(!throttle.allow_at(ip("192.0.2.1"), &email, now));
+        assert!(throttle.allow_at(ip("192.0.2.2"), &email, now));
+    }
+
+    #[test]
+    fn an_email_address_is_limited_whatever_the_ip_address() {
+        let throttle = throttle();
+        let now = Instant::now();
+        assert!(throttle.allow_at(ip("192.0.2.1"), &email("ursula@example.com"), now));
+        assert!(throttle.allow_at(ip("192.0.2.2"), &email("Ursula@example.com"), now));
+        assert!(!throttle.allow_at(ip("192.0.2.3"), &email("ursula@example.com"), now));
+    }
+
+    #[test]
+    fn limits_reset_after_an_hour() {
+        let throttle = throttle();
+        let now = Instant::now();
+        let email = email("ursula@example.com");
+        for _ in 0..2 {
+            assert!(throttle.allow_at(ip("192.0.2.1"), &email, now));
+        }
+        assert!(!throttle.allow_at(ip("192.0.2.1"), &email, now));
+        assert!(throttle.allow_at(ip("192.0.2.1"), &email, now + WINDOW));
+    }
+}
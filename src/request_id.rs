@@ -0,0 +1,173 @@
+use actix_web::body::{BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Future, Ready};
+use std::rc::Rc;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    // The id of the request being handled, for code that has no access to it (e.g. `EmailClient`)
+    static CURRENT: RequestId;
+}
+
+/// Identifies a request in our logs, in the responses we send and in the calls we make
+/// to other services, so that a support ticket can be traced end-to-end.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The id set by a proxy in front of us, if it is safe to log, or a new one.
+    fn of(request: &ServiceRequest) -> Self {
+        request
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::parse)
+            .unwrap_or_else(|| Self(Uuid::new_v4().to_string()))
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let is_valid = !s.is_empty()
+            && s.len() <= MAX_LENGTH
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+        is_valid.then(|| Self(s.to_owned()))
+    }
+
+    /// The id of the request being handled by this task, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|id| id.clone()).ok()
+    }
+}
+
+impl AsRef<str> for RequestId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<RequestId, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let id =
+            req.extensions().get::<RequestId>().cloned().ok_or_else(|| {
+                actix_web::error::ErrorInternalServerError("RequestIds is not wrapped")
+            });
+        ready(id)
+    }
+}
+
+/// Keeps the id of the current request for `future`, e.g. before handing it to `tokio::spawn`.
+pub fn with_current_request_id<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let id = RequestId::current();
+    async move {
+        match id {
+            Some(id) => CURRENT.scope(id, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// Gives every request a `RequestId` and echoes it in the `X-Request-Id` header of the response.
+/// Server errors (a handler or middleware returning an `Error`) get an opaque body with the id,
+/// to be quoted when contacting us.
+/// Must be wrapped outside of `TracingLogger`, so that the id is known to the root span.
+pub struct RequestIds;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIds
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RequestIdsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestIdsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let id = RequestId::of(&req);
+        req.extensions_mut().insert(id.clone());
+        let service = self.service.clone();
+        Box::pin(async move {
+            let res = CURRENT.scope(id.clone(), service.call(req)).await?;
+            let is_server_error =
+                res.status().is_server_error() && res.response().error().is_some();
+            let mut res = if is_server_error {
+                // The error itself is in the logs, under the same id
+                let body = format!(
+                    "Something went wrong on our side. \
+                    If you contact us about it, please quote this request ID: {}",
+                    id
+                );
+                res.map_body(|head, _| {
+                    head.headers.insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("text/plain; charset=utf-8"),
+                    );
+                    EitherBody::right(BoxBody::new(body))
+                })
+            } else {
+                res.map_into_left_body()
+            };
+            // `parse` only lets through characters that are valid in a header
+            let value = HeaderValue::from_str(id.as_ref()).unwrap();
+            res.headers_mut().insert(REQUEST_ID_HEADER, value);
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_from_proxies_are_kept_if_they_are_safe_to_log() {
+        for id in ["f0e1d2c3", "req-42_a.b:c", &"a".repeat(MAX_LENGTH)] {
+            assert_eq!(RequestId::parse(id), Some(RequestId(id.to_owned())));
+        }
+    }
+
+    #[test]
+    fn ids_that_could_forge_log_records_are_rejected() {
+        for id in ["", "a b", "a\"b", "a\nb", "ü", &"a".repeat(MAX_LENGTH + 1)] {
+            assert_eq!(RequestId::parse(id), None);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::audit_log::{get_audit_entries, AuditAction, AuditEntry, AuditFilter, AuditLog};
+use crate::authorization::Permission;
+use crate::routes::admin::{authenticate_admin, AdminError};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(serde::Deserialize)]
+pub struct AuditLogParameters {
+    user_id: Option<Uuid>,
+    action: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    cursor: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditLogPage {
+    entries: Vec<AuditEntry>,
+    // `None` once the oldest entry has been reached
+    next_cursor: Option<i64>,
+}
+
+impl AuditLogParameters {
+    fn parse(self) -> Result<(AuditFilter, Option<i64>, i64), String> {
+        let action = self.action.as_deref().map(AuditAction::parse).transpose()?;
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_PAGE_SIZE));
+        }
+        let filter = AuditFilter {
+            user_id: self.user_id,
+            action,
+            since: self.since,
+            until: self.until,
+        };
+        Ok((filter, self.cursor, limit))
+    }
+}
+
+/*
+Newest entries first.
+curl -u admin:password \
+    'http://127.0.0.1:8000/admin/audit-log?action=login.failed&since=2023-07-01T00:00:00Z&limit=20'
+*/
+#[tracing::instrument(
+    name = "Query the audit log",
+    skip(parameters, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn query_audit_log(
+    parameters: web::Query<AuditLogParameters>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate_admin(&request, &pool, &audit_log, Permission::ViewAuditLog).await?;
+    let (filter, cursor, limit) = parameters
+        .into_inner()
+        .parse()
+        .map_err(AdminError::ValidationError)?;
+
+    // One extra entry tells us whether there is a next page
+    let mut entries = get_audit_entries(&pool, &filter, cursor, limit + 1).await?;
+    let has_next_page = entries.len() as i64 > limit;
+    entries.truncate(limit as usize);
+    let next_cursor = entries
+        .last()
+        .filter(|_| has_next_page)
+        .map(|entry| entry.id);
+    Ok(HttpResponse::Ok().json(AuditLogPage {
+        entries,
+        next_cursor,
+    }))
+}
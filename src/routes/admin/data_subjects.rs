@@ -0,0 +1,90 @@
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authorization::Permission;
+use crate::domain::SubscriberEmail;
+use crate::routes::admin::{authenticate_admin, AdminError};
+use crate::routes::{erase_personal_data, gather_personal_data};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct DataSubject {
+    email: String,
+}
+
+/*
+curl -u admin:password 'http://127.0.0.1:8000/admin/gdpr/export?email=ursula_le_guin%40gmail.com'
+*/
+#[tracing::instrument(
+    name = "Export the personal data of a data subject",
+    skip(parameters, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn export_data_subject(
+    parameters: web::Query<DataSubject>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id =
+        authenticate_admin(&request, &pool, &audit_log, Permission::ExportSubscribers).await?;
+    let email = SubscriberEmail::parse(parameters.into_inner().email)
+        .map_err(AdminError::ValidationError)?;
+    let package = gather_personal_data(&pool, &email)
+        .await
+        .context("Failed to gather the personal data of a data subject.")?
+        .ok_or_else(|| AdminError::NotFound("We hold no data about this email.".into()))?;
+    // Whose data left the system, by id: the email itself stays out of the log
+    let subscriber_ids: Vec<_> = package.subscriptions.iter().map(|s| s.id).collect();
+    let details = serde_json::json!({ "subscriber_ids": subscriber_ids });
+    audit_log
+        .record(
+            &request,
+            Some(user_id),
+            AuditAction::DataSubjectExported,
+            details,
+        )
+        .await;
+    Ok(HttpResponse::Ok().json(package))
+}
+
+/*
+curl -u admin:password -X POST -H 'Content-Type: application/json' \
+    -d '{"email": "ursula_le_guin@gmail.com"}' http://127.0.0.1:8000/admin/gdpr/erase
+*/
+#[tracing::instrument(
+    name = "Erase the personal data of a data subject",
+    skip(body, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn erase_data_subject(
+    body: web::Json<DataSubject>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id =
+        authenticate_admin(&request, &pool, &audit_log, Permission::EditSubscribers).await?;
+    let email =
+        SubscriberEmail::parse(body.into_inner().email).map_err(AdminError::ValidationError)?;
+    let report = erase_personal_data(&pool, &email)
+        .await
+        .context("Failed to erase the personal data of a data subject.")?;
+    if report.erased_subscriptions == 0 {
+        return Err(AdminError::NotFound(
+            "We hold no data about this email.".into(),
+        ));
+    }
+    // What was erased, not whose data it was
+    let details =
+        serde_json::to_value(&report).context("Failed to serialise an erasure report.")?;
+    audit_log
+        .record(
+            &request,
+            Some(user_id),
+            AuditAction::DataSubjectErased,
+            details,
+        )
+        .await;
+    Ok(HttpResponse::Ok().json(report))
+}
@@ -0,0 +1,225 @@
+use crate::audit_log::AuditLog;
+use crate::authorization::Permission;
+use crate::domain::SubscriptionStatus;
+use crate::routes::admin::{
+    authenticate_admin, get_stored_subscribers_page, AdminError, Cursor, StoredSubscriber,
+    SubscriberFilter,
+};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// How many rows we hold in memory at any point in time while streaming an export.
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    // One JSON object per line
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ExportParameters {
+    #[serde(default)]
+    format: ExportFormat,
+    status: Option<String>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+}
+
+/*
+curl -u admin:password -o subscribers.csv \
+    'http://127.0.0.1:8000/admin/subscribers/export?format=csv&status=confirmed'
+*/
+#[tracing::instrument(
+    name = "Export subscribers",
+    skip(parameters, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn export_subscribers_file(
+    parameters: web::Query<ExportParameters>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id =
+        authenticate_admin(&request, &pool, &audit_log, Permission::ExportSubscribers).await?;
+    let ExportParameters {
+        format,
+        status,
+        subscribed_after,
+        subscribed_before,
+    } = parameters.into_inner();
+    let filter = SubscriberFilter {
+        status: status
+            .map(SubscriptionStatus::parse)
+            .transpose()
+            .map_err(AdminError::ValidationError)?,
+        subscribed_after,
+        subscribed_before,
+        search: None,
+    };
+
+    record_export(&pool, Some(user_id), format, &filter)
+        .await
+        .context("Failed to record a subscriber export.")?;
+
+    let filename = format!("subscribers.{}", format.as_str());
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .streaming(export_subscribers(pool.get_ref().clone(), filter, format)))
+}
+
+/// Stream every subscriber matching `filter`, serialized in `format`.
+/// The table is walked one page at a time, so we never buffer the whole of it in memory.
+/// Rows are exported as stored, including the ones that would no longer pass validation.
+pub fn export_subscribers(
+    pool: PgPool,
+    filter: SubscriberFilter,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Bytes, anyhow::Error>> {
+    struct State {
+        pool: PgPool,
+        filter: SubscriberFilter,
+        cursor: Option<Cursor>,
+        is_first_page: bool,
+        is_done: bool,
+    }
+
+    let state = State {
+        pool,
+        filter,
+        cursor: None,
+        is_first_page: true,
+        is_done: false,
+    };
+    futures_util::stream::unfold(state, move |mut state| async move {
+        if state.is_done {
+            return None;
+        }
+        let page = get_stored_subscribers_page(
+            &state.pool,
+            &state.filter,
+            state.cursor.as_ref(),
+            EXPORT_PAGE_SIZE,
+        )
+        .await
+        .context("Failed to retrieve a page of subscribers to export.");
+        let (subscribers, next_cursor) = match page {
+            Ok(page) => page,
+            Err(e) => {
+                state.is_done = true;
+                return Some((Err(e), state));
+            }
+        };
+
+        let chunk = serialize_page(subscribers, format, state.is_first_page);
+        state.is_first_page = false;
+        state.is_done = next_cursor.is_none();
+        state.cursor = next_cursor;
+        Some((chunk.map(Bytes::from), state))
+    })
+}
+
+fn serialize_page(
+    subscribers: Vec<StoredSubscriber>,
+    format: ExportFormat,
+    include_header: bool,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut buffer = Vec::new();
+    match format {
+        ExportFormat::Csv => {
+            let is_empty = subscribers.is_empty();
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(include_header)
+                .from_writer(&mut buffer);
+            for mut subscriber in subscribers {
+                subscriber.email = escape_formula(subscriber.email);
+                subscriber.name = escape_formula(subscriber.name);
+                writer
+                    .serialize(subscriber)
+                    .context("Failed to serialize a subscriber as CSV.")?;
+            }
+            // The header row is derived from the first serialized record:
+            // an empty export has to write it by hand.
+            if include_header && is_empty {
+                writer
+                    .write_record(["id", "email", "name", "status", "subscribed_at"])
+                    .context("Failed to write the CSV header.")?;
+            }
+            writer.flush().context("Failed to flush the CSV writer.")?;
+        }
+        ExportFormat::Ndjson => {
+            for subscriber in subscribers {
+                serde_json::to_writer(&mut buffer, &subscriber)
+                    .context("Failed to serialize a subscriber as JSON.")?;
+                buffer.push(b'\n');
+            }
+        }
+    }
+    Ok(buffer)
+}
+
+/// Spreadsheets evaluate cells starting with `=`, `+`, `-` or `@` as formulas:
+/// a leading `'` makes them plain text again.
+fn escape_formula(cell: String) -> String {
+    if cell.starts_with(['=', '+', '-', '@']) {
+        format!("'{cell}")
+    } else {
+        cell
+    }
+}
+
+#[tracing::instrument(name = "Record a subscriber export", skip(pool, filter))]
+pub async fn record_export(
+    pool: &PgPool,
+    user_id: Option<Uuid>,
+    format: ExportFormat,
+    filter: &SubscriberFilter,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_exports
+            (id, user_id, format, status, subscribed_after, subscribed_before, exported_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        format.as_str(),
+        filter.status.map(|s| s.as_str()),
+        filter.subscribed_after,
+        filter.subscribed_before,
+        Utc::now(),
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,319 @@
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authorization::Permission;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus};
+use crate::email_client::EmailClient;
+use crate::routes::admin::{authenticate_admin, AdminError};
+use crate::routes::{generate_subscription_token, send_confirmation_email};
+use crate::startup::ApplicationBaseUrl;
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+// Each chunk of rows is inserted in its own transaction:
+// a failure halfway through a large file does not roll back what was already imported.
+const CHUNK_SIZE: usize = 500;
+
+/// What happens to the subscribers we import.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// They already opted in with the previous tool: store them as confirmed, send nothing.
+    Confirmed,
+    /// Store them as pending and send the usual confirmation email.
+    #[default]
+    SendConfirmation,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportParameters {
+    #[serde(default)]
+    mode: ImportMode,
+}
+
+// The CSV file must have a header row with (at least) an `email` and a `name` column.
+#[derive(serde::Deserialize)]
+struct CsvRow {
+    email: String,
+    name: String,
+}
+
+impl TryFrom<CsvRow> for NewSubscriber {
+    type Error = String;
+
+    fn try_from(value: CsvRow) -> Result<Self, Self::Error> {
+        let name = SubscriberName::parse(value.name.trim().to_owned())?;
+        let email = SubscriberEmail::parse(value.email.trim().to_owned())?;
+        Ok(Self { email, name })
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct RowError {
+    // 1-based line number in the uploaded file, header included
+    pub line: u64,
+    pub error: String,
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub duplicates: usize,
+    pub invalid: usize,
+    pub errors: Vec<RowError>,
+}
+
+/*
+curl -u admin:password -H 'Content-Type: text/csv' --data-binary @subscribers.csv \
+    'http://127.0.0.1:8000/admin/subscribers/import?mode=confirmed'
+*/
+#[tracing::instrument(
+    name = "Import subscribers from CSV",
+    skip(body, parameters, pool, email_client, base_url, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn import_subscribers_csv(
+    body: web::Bytes,
+    parameters: web::Query<ImportParameters>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id =
+        authenticate_admin(&request, &pool, &audit_log, Permission::EditSubscribers).await?;
+    let mode = parameters.mode;
+    let report = import_subscribers(&pool, &email_client, &base_url.0, &body, parameters.mode)
+        .await
+        .map_err(|e| match e {
+            ImportError::InvalidFile(e) => AdminError::ValidationError(e),
+            ImportError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+        })?;
+    // Counts only: the rows hold the personal data of subscribers
+    let details = serde_json::json!({
+        "mode": mode,
+        "imported": report.imported,
+        "duplicates": report.duplicates,
+        "invalid": report.invalid,
+    });
+    audit_log
+        .record(
+            &request,
+            Some(user_id),
+            AuditAction::SubscribersImported,
+            details,
+        )
+        .await;
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImportError {
+    #[error("{0}")]
+    InvalidFile(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Validate every row of a CSV file and store the valid ones, in chunks.
+/// Invalid rows and rows whose email is already subscribed are reported back rather than failing the import.
+#[tracing::instrument(name = "Import subscribers", skip(pool, email_client, base_url, csv))]
+pub async fn import_subscribers(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    csv: &[u8],
+    mode: ImportMode,
+) -> Result<ImportReport, ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::Headers)
+        .from_reader(csv);
+    let headers = reader
+        .headers()
+        .map_err(|e| ImportError::InvalidFile(format!("Failed to read the CSV header: {}", e)))?
+        .clone();
+    for column in ["email", "name"] {
+        if !headers.iter().any(|h| h == column) {
+            return Err(ImportError::InvalidFile(format!(
+                "The CSV header is missing the `{}` column",
+                column
+            )));
+        }
+    }
+
+    let mut report = ImportReport::default();
+    let mut seen = HashSet::new();
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    for record in reader.records() {
+        let (line, row) = match record {
+            Ok(record) => (
+                record.position().map(|p| p.line()).unwrap_or_default(),
+                record.deserialize::<CsvRow>(Some(&headers)),
+            ),
+            Err(e) => (e.position().map(|p| p.line()).unwrap_or_default(), Err(e)),
+        };
+        let new_subscriber = row
+            .map_err(|e| e.to_string())
+            .and_then(NewSubscriber::try_from);
+        match new_subscriber {
+            Ok(new_subscriber) => {
+                // The file itself may contain the same address twice
+                if !seen.insert(new_subscriber.email.as_ref().to_owned()) {
+                    report.duplicates += 1;
+                    report.errors.push(RowError {
+                        line,
+                        error: format!("{} appears earlier in the file", new_subscriber.email),
+                    });
+                    continue;
+                }
+                chunk.push((line, new_subscriber));
+            }
+            Err(error) => {
+                report.invalid += 1;
+                report.errors.push(RowError { line, error });
+            }
+        }
+        if chunk.len() == CHUNK_SIZE {
+            import_chunk(pool, email_client, base_url, &mut chunk, mode, &mut report).await?;
+        }
+    }
+    import_chunk(pool, email_client, base_url, &mut chunk, mode, &mut report).await?;
+
+    report.errors.sort_by_key(|e| e.line);
+    Ok(report)
+}
+
+async fn import_chunk(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    chunk: &mut Vec<(u64, NewSubscriber)>,
+    mode: ImportMode,
+    report: &mut ImportReport,
+) -> Result<(), anyhow::Error> {
+    if chunk.is_empty() {
+        return Ok(());
+    }
+    let status = match mode {
+        ImportMode::Confirmed => SubscriptionStatus::Confirmed,
+        ImportMode::SendConfirmation => SubscriptionStatus::PendingConfirmation,
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let inserted = insert_subscribers(&mut transaction, chunk, status)
+        .await
+        .context("Failed to insert a chunk of imported subscribers in the database.")?;
+
+    // Rows that did not make it into the table hit the unique constraint on `email`.
+    let mut imported = Vec::with_capacity(inserted.len());
+    for (line, new_subscriber) in chunk.drain(..) {
+        match inserted.get(new_subscriber.email.as_ref()) {
+            Some(subscriber_id) => imported.push((line, *subscriber_id, new_subscriber)),
+            None => {
+                report.duplicates += 1;
+                report.errors.push(RowError {
+                    line,
+                    error: format!("{} is already subscribed", new_subscriber.email),
+                });
+            }
+        }
+    }
+
+    let tokens: Vec<(Uuid, String)> = match mode {
+        ImportMode::Confirmed => Vec::new(),
+        ImportMode::SendConfirmation => imported
+            .iter()
+            .map(|(_, subscriber_id, _)| (*subscriber_id, generate_subscription_token()))
+            .collect(),
+    };
+    store_tokens(&mut transaction, &tokens)
+        .await
+        .context("Failed to store the confirmation tokens of imported subscribers.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store imported subscribers.")?;
+    report.imported += imported.len();
+
+    // Emails go out only once the rows are committed, the same way `subscribe` does it.
+    for ((line, _, new_subscriber), (_, token)) in imported.into_iter().zip(tokens) {
+        let email = new_subscriber.email.to_string();
+        if let Err(e) =
+            send_confirmation_email(email_client, new_subscriber, base_url, &token).await
+        {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Failed to send a confirmation email to an imported subscriber"
+            );
+            report.errors.push(RowError {
+                line,
+                error: format!("Failed to send a confirmation email to {}", email),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns the ids of the rows that were actually inserted, keyed by email.
+#[tracing::instrument(name = "Saving imported subscribers to the database", skip_all)]
+async fn insert_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    chunk: &[(u64, NewSubscriber)],
+    status: SubscriptionStatus,
+) -> Result<HashMap<String, Uuid>, sqlx::Error> {
+    let ids: Vec<Uuid> = chunk.iter().map(|_| Uuid::new_v4()).collect();
+    let emails: Vec<String> = chunk
+        .iter()
+        .map(|(_, s)| s.email.as_ref().to_owned())
+        .collect();
+    let names: Vec<String> = chunk
+        .iter()
+        .map(|(_, s)| s.name.as_ref().to_owned())
+        .collect();
+    let rows = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT id, email, name, $4, $5
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[]) AS imported(id, email, name)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id, email
+        "#,
+        &ids,
+        &emails,
+        &names,
+        Utc::now(),
+        status.as_str(),
+    )
+    .fetch_all(transaction)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.email, r.id)).collect())
+}
+
+#[tracing::instrument(name = "Store imported subscription tokens in the database", skip_all)]
+async fn store_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    tokens: &[(Uuid, String)],
+) -> Result<(), sqlx::Error> {
+    if tokens.is_empty() {
+        return Ok(());
+    }
+    let subscriber_ids: Vec<Uuid> = tokens.iter().map(|(id, _)| *id).collect();
+    let subscription_tokens: Vec<String> = tokens.iter().map(|(_, t)| t.clone()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        SELECT * FROM UNNEST($1::text[], $2::uuid[])
+        "#,
+        &subscription_tokens,
+        &subscriber_ids,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,153 @@
+mod audit_log;
+mod data_subjects;
+mod export;
+mod import;
+mod newsletters;
+mod password;
+mod subscribers;
+mod tokens;
+mod totp;
+mod users;
+
+pub use audit_log::*;
+pub use data_subjects::*;
+pub use export::*;
+pub use import::*;
+pub use newsletters::*;
+pub use password::*;
+pub use subscribers::*;
+pub use tokens::*;
+pub use totp::*;
+pub use users::*;
+
+use crate::audit_log::AuditLog;
+use crate::authentication::{
+    authenticate, authenticate_with_password, AuthError, AuthenticatedUser,
+};
+use crate::authorization::{require_permission, AuthorizationError, Permission};
+use crate::routes::error_chain_fmt;
+use actix_web::http::header::HeaderValue;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use sqlx::PgPool;
+
+// Shared error type for the JSON endpoints living under `/admin`.
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[derive(serde::Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AdminError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AdminError::UnexpectedError(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            AdminError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="admin""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+            // Validation and lookup failures are safe to show to the caller,
+            // so we echo them back in the JSON body.
+            AdminError::Forbidden(message) => {
+                HttpResponse::Forbidden().json(ErrorBody { error: message })
+            }
+            AdminError::ValidationError(message) => {
+                HttpResponse::BadRequest().json(ErrorBody { error: message })
+            }
+            AdminError::NotFound(message) => {
+                HttpResponse::NotFound().json(ErrorBody { error: message })
+            }
+            AdminError::Conflict(message) => {
+                HttpResponse::Conflict().json(ErrorBody { error: message })
+            }
+        }
+    }
+}
+
+/// Authenticate the caller of an admin endpoint, with 'Basic' credentials or an API token,
+/// then check that they hold `permission`.
+/// The authenticated user is recorded on the current span, rejected credentials in `audit_log`.
+pub(crate) async fn authenticate_admin(
+    request: &HttpRequest,
+    pool: &PgPool,
+    audit_log: &AuditLog,
+    permission: Permission,
+) -> Result<uuid::Uuid, AdminError> {
+    let user = match authenticate(request, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            audit_log.record_api_auth_failure(request, &e).await;
+            return Err(auth_error(e));
+        }
+    };
+    record_user(&user);
+
+    require_permission(&user, permission, pool)
+        .await
+        .map_err(|e| match e {
+            AuthorizationError::Forbidden(_) => AdminError::Forbidden(e.to_string()),
+            AuthorizationError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+        })?;
+    Ok(user.user_id)
+}
+
+/// Same as `authenticate_admin`, but only 'Basic' credentials are accepted:
+/// a leaked API token must not be enough to create new ones.
+pub(crate) async fn authenticate_admin_with_password(
+    request: &HttpRequest,
+    pool: &PgPool,
+    audit_log: &AuditLog,
+) -> Result<AuthenticatedUser, AdminError> {
+    let user = match authenticate_with_password(request, pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            audit_log.record_api_auth_failure(request, &e).await;
+            return Err(auth_error(e));
+        }
+    };
+    record_user(&user);
+    Ok(user)
+}
+
+fn record_user(user: &AuthenticatedUser) {
+    tracing::Span::current()
+        .record("username", tracing::field::display(&user.username))
+        .record("user_id", tracing::field::display(&user.user_id));
+}
+
+fn auth_error(e: AuthError) -> AdminError {
+    match e {
+        AuthError::InvalidCredentials(_) | AuthError::Throttled(_) => {
+            AdminError::AuthError(e.into())
+        }
+        AuthError::PasswordChangeRequired(_) | AuthError::TwoFactorEnrollmentRequired(_) => {
+            AdminError::Forbidden(e.to_string())
+        }
+        AuthError::UnexpectedError(_) => AdminError::UnexpectedError(e.into()),
+    }
+}
@@ -0,0 +1,29 @@
+use crate::audit_log::AuditLog;
+use crate::authorization::Permission;
+use crate::routes::admin::{authenticate_admin, AdminError};
+use crate::tracking::get_issue_statistics;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/*
+Only emails sent while tracking is enabled are counted.
+curl -u admin:password http://127.0.0.1:8000/admin/newsletters/{newsletter_issue_id}/stats
+*/
+#[tracing::instrument(
+    name = "Get the statistics of a newsletter issue",
+    skip(pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn newsletter_issue_stats(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate_admin(&request, &pool, &audit_log, Permission::ViewSubscribers).await?;
+    let statistics = get_issue_statistics(&pool, newsletter_issue_id.into_inner())
+        .await?
+        .ok_or_else(|| AdminError::NotFound("Newsletter issue not found.".into()))?;
+    Ok(HttpResponse::Ok().json(statistics))
+}
@@ -0,0 +1,103 @@
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authentication::{
+    self, get_username, validate_credentials, validate_new_password, AuthError, Credentials,
+    PasswordPolicy,
+};
+use crate::csrf::CsrfForm;
+use crate::login_throttle::LoginThrottle;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+/*
+Users created with a one-time password are sent here by the login form,
+and cannot use the rest of the application until they have picked a new password.
+*/
+#[tracing::instrument(
+    name = "Change password",
+    skip(form, session, pool, throttle, policy, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn change_password(
+    form: CsrfForm<FormData>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottle>,
+    policy: web::Data<PasswordPolicy>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let FormData {
+        current_password,
+        new_password,
+        new_password_check,
+    } = form.into_inner();
+
+    if let Err(e) = validate_new_password(&new_password, &new_password_check) {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/password"));
+    }
+    if new_password.expose_secret() == current_password.expose_secret() {
+        FlashMessage::error("The new password must be different from the current one.").send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    tracing::Span::current().record("username", tracing::field::display(&username));
+    let credentials = Credentials {
+        username: username.clone(),
+        password: current_password,
+    };
+    let client_ip = throttle.client_ip(&request);
+    match validate_credentials(credentials, client_ip, &throttle, &policy, &pool).await {
+        // Replacing a one-time password is the whole point of this page
+        Ok(_) | Err(AuthError::PasswordChangeRequired(_)) => {}
+        Err(AuthError::InvalidCredentials(_)) => {
+            FlashMessage::error("The current password is incorrect.").send();
+            return Ok(see_other("/admin/password"));
+        }
+        Err(AuthError::Throttled(_)) => {
+            let details = serde_json::json!({ "step": "password_change" });
+            audit_log
+                .record(
+                    &request,
+                    Some(user_id),
+                    AuditAction::LoginThrottled,
+                    details,
+                )
+                .await;
+            FlashMessage::error("The current password is incorrect.").send();
+            return Ok(see_other("/admin/password"));
+        }
+        Err(e) => return Err(e500(e)),
+    }
+
+    authentication::change_password(&username, new_password, &policy, &pool)
+        .await
+        .map_err(e500)?;
+    audit_log
+        .record(
+            &request,
+            Some(user_id),
+            AuditAction::PasswordChanged,
+            serde_json::json!({}),
+        )
+        .await;
+    FlashMessage::info("Your password has been changed.").send();
+    Ok(see_other("/admin/password"))
+}
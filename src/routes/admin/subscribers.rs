@@ -0,0 +1,377 @@
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authorization::Permission;
+use crate::domain::{SubscriberEmail, SubscriberName, SubscriptionStatus};
+use crate::routes::admin::{authenticate_admin, AdminError};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// A row of `subscriptions` that went through our domain types on the way out of the database.
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: SubscriberEmail,
+    pub name: SubscriberName,
+    pub status: SubscriptionStatus,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+impl Subscriber {
+    fn parse(
+        id: Uuid,
+        email: String,
+        name: String,
+        status: String,
+        subscribed_at: DateTime<Utc>,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            id,
+            email: SubscriberEmail::parse(email)?,
+            name: SubscriberName::parse(name)?,
+            status: SubscriptionStatus::parse(status)?,
+            subscribed_at,
+        })
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberResponse {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: &'static str,
+    subscribed_at: DateTime<Utc>,
+}
+
+impl From<Subscriber> for SubscriberResponse {
+    fn from(subscriber: Subscriber) -> Self {
+        Self {
+            id: subscriber.id,
+            email: subscriber.email.as_ref().to_owned(),
+            name: subscriber.name.as_ref().to_owned(),
+            status: subscriber.status.as_str(),
+            subscribed_at: subscriber.subscribed_at,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberPage {
+    subscribers: Vec<SubscriberResponse>,
+    // `None` once the last page has been reached
+    next_cursor: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListParameters {
+    status: Option<String>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    search: Option<String>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Filters shared by every query that walks the `subscriptions` table.
+#[derive(Default)]
+pub struct SubscriberFilter {
+    pub status: Option<SubscriptionStatus>,
+    pub subscribed_after: Option<DateTime<Utc>>,
+    pub subscribed_before: Option<DateTime<Utc>>,
+    // Matched as a case-insensitive prefix of either the email or the name
+    pub search: Option<String>,
+}
+
+/// Position of the last row returned, so that the next page can resume from there.
+/// Rows are ordered by `(subscribed_at, id)`, which is unique and stable under inserts.
+#[derive(Debug, PartialEq)]
+pub struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    pub fn parse(s: &str) -> Result<Cursor, String> {
+        let invalid = || format!("{} is not a valid cursor", s);
+        let decoded = base64::decode_config(s, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (subscribed_at, id) = decoded.split_once(',').ok_or_else(invalid)?;
+        Ok(Self {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at)
+                .map_err(|_| invalid())?
+                .with_timezone(&Utc),
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        let raw = format!("{},{}", self.subscribed_at.to_rfc3339(), self.id);
+        base64::encode_config(raw, base64::URL_SAFE_NO_PAD)
+    }
+}
+
+impl ListParameters {
+    fn parse(self) -> Result<(SubscriberFilter, Option<Cursor>, i64), String> {
+        let status = self.status.map(SubscriptionStatus::parse).transpose()?;
+        let cursor = self.cursor.as_deref().map(Cursor::parse).transpose()?;
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_PAGE_SIZE));
+        }
+        let filter = SubscriberFilter {
+            status,
+            subscribed_after: self.subscribed_after,
+            subscribed_before: self.subscribed_before,
+            search: self.search.filter(|s| !s.trim().is_empty()),
+        };
+        Ok((filter, cursor, limit))
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct StatusChange {
+    status: String,
+}
+
+/*
+curl -u admin:password \
+    'http://127.0.0.1:8000/admin/subscribers?status=confirmed&search=ursula&limit=20'
+*/
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(parameters, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_subscribers(
+    parameters: web::Query<ListParameters>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate_admin(&request, &pool, &audit_log, Permission::ViewSubscribers).await?;
+    let (filter, cursor, limit) = parameters
+        .into_inner()
+        .parse()
+        .map_err(AdminError::ValidationError)?;
+
+    let (subscribers, next_cursor) = get_subscribers_page(&pool, &filter, cursor.as_ref(), limit)
+        .await
+        .context("Failed to retrieve a page of subscribers.")?;
+
+    Ok(HttpResponse::Ok().json(SubscriberPage {
+        subscribers: subscribers.into_iter().map(Into::into).collect(),
+        next_cursor: next_cursor.map(|c| c.encode()),
+    }))
+}
+
+#[tracing::instrument(
+    name = "Get a subscriber",
+    skip(subscriber_id, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn subscriber_details(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate_admin(&request, &pool, &audit_log, Permission::ViewSubscribers).await?;
+    let subscriber = get_stored_subscriber_by_id(&pool, *subscriber_id)
+        .await?
+        .ok_or_else(|| AdminError::NotFound("Subscriber not found.".into()))?;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[tracing::instrument(
+    name = "Change the status of a subscriber",
+    skip(subscriber_id, body, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn change_subscriber_status(
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<StatusChange>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id =
+        authenticate_admin(&request, &pool, &audit_log, Permission::EditSubscribers).await?;
+    let status = SubscriptionStatus::parse(body.0.status).map_err(AdminError::ValidationError)?;
+
+    let updated = update_subscriber_status(&pool, *subscriber_id, status)
+        .await
+        .context("Failed to update the status of a subscriber.")?;
+    if !updated {
+        return Err(AdminError::NotFound("Subscriber not found.".into()));
+    }
+
+    let subscriber = get_stored_subscriber_by_id(&pool, *subscriber_id)
+        .await?
+        .context("The subscriber disappeared while its status was being updated.")?;
+    let details = serde_json::json!({
+        "subscriber_id": *subscriber_id,
+        "status": status.as_str(),
+    });
+    audit_log
+        .record(
+            &request,
+            Some(user_id),
+            AuditAction::SubscriberStatusChanged,
+            details,
+        )
+        .await;
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+/// Fetch up to `limit` subscribers matching `filter`, starting after `cursor`.
+/// Rows whose stored details no longer pass validation are skipped, but still move the cursor forward.
+#[tracing::instrument(name = "Get a page of subscribers", skip(pool, filter))]
+pub async fn get_subscribers_page(
+    pool: &PgPool,
+    filter: &SubscriberFilter,
+    cursor: Option<&Cursor>,
+    limit: i64,
+) -> Result<(Vec<Subscriber>, Option<Cursor>), anyhow::Error> {
+    let (rows, next_cursor) = get_stored_subscribers_page(pool, filter, cursor, limit).await?;
+    let subscribers = rows
+        .into_iter()
+        .filter_map(
+            |r| match Subscriber::parse(r.id, r.email, r.name, r.status, r.subscribed_at) {
+                Ok(subscriber) => Some(subscriber),
+                Err(error) => {
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        subscriber_id = %r.id,
+                        "Skipping a subscriber. Their stored details are invalid"
+                    );
+                    None
+                }
+            },
+        )
+        .collect();
+    Ok((subscribers, next_cursor))
+}
+
+/// A row of `subscriptions` as it is stored, whether or not it would pass validation today.
+#[derive(serde::Serialize)]
+pub struct StoredSubscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+/// Same as [`get_subscribers_page`], without validation: nothing is skipped.
+#[tracing::instrument(name = "Get a page of stored subscribers", skip(pool, filter))]
+pub async fn get_stored_subscribers_page(
+    pool: &PgPool,
+    filter: &SubscriberFilter,
+    cursor: Option<&Cursor>,
+    limit: i64,
+) -> Result<(Vec<StoredSubscriber>, Option<Cursor>), anyhow::Error> {
+    // We fetch one extra row to find out whether there is a next page.
+    let mut rows = sqlx::query_as!(
+        StoredSubscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE erased_at IS NULL
+            AND ($1::text IS NULL OR status = $1)
+            AND ($2::timestamptz IS NULL OR subscribed_at >= $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+            AND ($4::text IS NULL
+                OR starts_with(lower(email), lower($4))
+                OR starts_with(lower(name), lower($4)))
+            AND ($5::timestamptz IS NULL OR (subscribed_at, id) > ($5, $6::uuid))
+        ORDER BY subscribed_at, id
+        LIMIT $7
+        "#,
+        filter.status.map(|s| s.as_str()),
+        filter.subscribed_after,
+        filter.subscribed_before,
+        filter.search.as_deref(),
+        cursor.map(|c| c.subscribed_at),
+        cursor.map(|c| c.id),
+        limit + 1,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let has_next_page = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+    let next_cursor = rows.last().filter(|_| has_next_page).map(|r| Cursor {
+        subscribed_at: r.subscribed_at,
+        id: r.id,
+    });
+
+    Ok((rows, next_cursor))
+}
+
+/// The subscriber as it is stored: a row that no longer passes validation is still returned,
+/// like in [`get_stored_subscribers_page`].
+#[tracing::instrument(name = "Get subscriber by id", skip(pool))]
+pub async fn get_stored_subscriber_by_id(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<StoredSubscriber>, anyhow::Error> {
+    sqlx::query_as!(
+        StoredSubscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1 AND erased_at IS NULL
+        "#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a subscriber.")
+}
+
+/// Returns `false` if no subscriber with the given id exists.
+#[tracing::instrument(name = "Update subscriber status", skip(pool))]
+pub async fn update_subscriber_status(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    status: SubscriptionStatus,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET status = $1 WHERE id = $2 AND erased_at IS NULL"#,
+        status.as_str(),
+        subscriber_id,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cursor;
+    use chrono::{TimeZone, Utc};
+    use claim::{assert_err, assert_ok_eq};
+    use uuid::Uuid;
+
+    #[test]
+    fn a_cursor_survives_a_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc.timestamp(1_684_000_000, 123_456_000),
+            id: Uuid::new_v4(),
+        };
+        assert_ok_eq!(Cursor::parse(&cursor.encode()), cursor);
+    }
+
+    #[test]
+    fn garbage_cursors_are_rejected() {
+        assert_err!(Cursor::parse("not-a-cursor"));
+        assert_err!(Cursor::parse(&base64::encode_config(
+            "2023-01-01T00:00:00Z",
+            base64::URL_SAFE_NO_PAD
+        )));
+    }
+}
@@ -0,0 +1,256 @@
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authentication::{generate_api_token, hash_api_token};
+use crate::authorization::{get_user_role, Permission};
+use crate::routes::admin::{authenticate_admin_with_password, AdminError};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct NewApiToken {
+    // What the token is for, e.g. "CI release notes"
+    name: String,
+    scopes: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ApiTokenResponse {
+    id: Uuid,
+    name: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct NewApiTokenResponse {
+    #[serde(flatten)]
+    details: ApiTokenResponse,
+    // Shown only once: we keep nothing but its hash
+    token: String,
+}
+
+/*
+Tokens can only be managed with a password, never with another token.
+Users with two-factor authentication add their current code with -H 'X-TOTP-Code: 123456'.
+curl -u admin:password -X POST -H 'Content-Type: application/json' \
+    -d '{"name": "CI", "scopes": ["publish"], "expires_at": "2024-01-01T00:00:00Z"}' \
+    http://127.0.0.1:8000/admin/tokens
+*/
+#[tracing::instrument(
+    name = "Create an API token",
+    skip(body, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn create_api_token(
+    body: web::Json<NewApiToken>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticate_admin_with_password(&request, &pool, &audit_log).await?;
+    let NewApiToken {
+        name,
+        scopes,
+        expires_at,
+    } = body.into_inner();
+
+    let name = name.trim().to_owned();
+    if name.is_empty() || name.len() > 256 {
+        return Err(AdminError::ValidationError(
+            "The name must be between 1 and 256 characters long.".into(),
+        ));
+    }
+    if scopes.is_empty() {
+        return Err(AdminError::ValidationError(
+            "A token needs at least one scope.".into(),
+        ));
+    }
+    if expires_at.is_some_and(|e| e <= Utc::now()) {
+        return Err(AdminError::ValidationError(
+            "The expiry date must be in the future.".into(),
+        ));
+    }
+    // A token can't do more than the user who created it
+    let role = get_user_role(user.user_id, &pool).await?;
+    let mut permissions = Vec::with_capacity(scopes.len());
+    for scope in &scopes {
+        let permission = Permission::parse_scope(scope).map_err(AdminError::ValidationError)?;
+        if !permission.is_granted_to(role) {
+            return Err(AdminError::Forbidden(format!(
+                "Your role does not allow the {} scope.",
+                scope
+            )));
+        }
+        if !permissions.contains(&permission) {
+            permissions.push(permission);
+        }
+    }
+
+    let token = generate_api_token();
+    let details = insert_api_token(
+        &pool,
+        user.user_id,
+        &name,
+        &permissions,
+        expires_at,
+        &hash_api_token(&token),
+    )
+    .await
+    .context("Failed to store a new API token.")?;
+    let audit_details = serde_json::json!({
+        "token_id": details.id,
+        "name": details.name,
+        "scopes": details.scopes,
+    });
+    audit_log
+        .record(
+            &request,
+            Some(user.user_id),
+            AuditAction::ApiTokenCreated,
+            audit_details,
+        )
+        .await;
+    Ok(HttpResponse::Created().json(NewApiTokenResponse {
+        details,
+        token: token.expose_secret().to_owned(),
+    }))
+}
+
+/*
+curl -u admin:password http://127.0.0.1:8000/admin/tokens
+*/
+#[tracing::instrument(
+    name = "List API tokens",
+    skip(pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_api_tokens(
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticate_admin_with_password(&request, &pool, &audit_log).await?;
+    let tokens = get_api_tokens(&pool, user.user_id)
+        .await
+        .context("Failed to retrieve the API tokens of a user.")?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/*
+Owners can revoke the tokens of any user, everybody else only their own.
+curl -u admin:password -X DELETE http://127.0.0.1:8000/admin/tokens/<token_id>
+*/
+#[tracing::instrument(
+    name = "Revoke an API token",
+    skip(token_id, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn revoke_api_token(
+    token_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user = authenticate_admin_with_password(&request, &pool, &audit_log).await?;
+    let role = get_user_role(user.user_id, &pool).await?;
+    let revoked = revoke_token(
+        &pool,
+        *token_id,
+        user.user_id,
+        Permission::ManageUsers.is_granted_to(role),
+    )
+    .await
+    .context("Failed to revoke an API token.")?;
+    if !revoked {
+        return Err(AdminError::NotFound("API token not found.".into()));
+    }
+    let details = serde_json::json!({ "token_id": *token_id });
+    audit_log
+        .record(
+            &request,
+            Some(user.user_id),
+            AuditAction::ApiTokenRevoked,
+            details,
+        )
+        .await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(name = "Store a new API token", skip(pool, token_hash))]
+async fn insert_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    permissions: &[Permission],
+    expires_at: Option<DateTime<Utc>>,
+    token_hash: &str,
+) -> Result<ApiTokenResponse, sqlx::Error> {
+    let scopes: Vec<String> = permissions.iter().map(|p| p.as_scope().into()).collect();
+    sqlx::query_as!(
+        ApiTokenResponse,
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, scopes, created_at, expires_at, last_used_at, revoked_at
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        token_hash,
+        &scopes,
+        Utc::now(),
+        expires_at,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get API tokens", skip(pool))]
+pub async fn get_api_tokens(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<ApiTokenResponse>, sqlx::Error> {
+    sqlx::query_as!(
+        ApiTokenResponse,
+        r#"
+        SELECT id, name, scopes, created_at, expires_at, last_used_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns `false` if there is no such token, or it belongs to somebody else and `any_user` is not set.
+#[tracing::instrument(name = "Revoke API token", skip(pool))]
+pub async fn revoke_token(
+    pool: &PgPool,
+    token_id: Uuid,
+    user_id: Uuid,
+    any_user: bool,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = COALESCE(revoked_at, $3)
+        WHERE id = $1 AND (user_id = $2 OR $4)
+        "#,
+        token_id,
+        user_id,
+        Utc::now(),
+        any_user,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
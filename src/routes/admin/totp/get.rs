@@ -0,0 +1,79 @@
+use crate::authentication::get_username;
+use crate::configuration::TwoFactorSettings;
+use crate::csrf::CsrfToken;
+use crate::session_state::TypedSession;
+use crate::two_factor::{otpauth_uri, qr_code_svg, start_enrollment};
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+// Set up an authenticator app, or turn two-factor authentication off again.
+pub async fn totp_form(
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    settings: web::Data<TwoFactorSettings>,
+    flash_messages: IncomingFlashMessages,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let csrf_field = csrf.form_field();
+    let body_html = match start_enrollment(user_id, &pool).await.map_err(e500)? {
+        // Already enabled
+        None => format!(
+            r#"<p>Two-factor authentication is enabled.</p>
+    <form action="/admin/totp/disable" method="post">
+        {csrf_field}
+        <label>Current code
+            <input type="text" autocomplete="one-time-code" placeholder="123456" name="code">
+        </label>
+        <button type="submit">Disable two-factor authentication</button>
+    </form>"#
+        ),
+        Some(secret) => {
+            let username = get_username(user_id, &pool).await.map_err(e500)?;
+            let uri = otpauth_uri(&settings.issuer, &username, &secret);
+            let qr_code = qr_code_svg(&uri).map_err(e500)?;
+            format!(
+                r#"<p>Scan this code with your authenticator app:</p>
+    {qr_code}
+    <p>Or add this link to it by hand: <code>{uri}</code></p>
+    <form action="/admin/totp/enable" method="post">
+        {csrf_field}
+        <label>Code shown by the app
+            <input type="text" autocomplete="one-time-code" placeholder="123456" name="code">
+        </label>
+        <button type="submit">Enable two-factor authentication</button>
+    </form>"#
+            )
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .cookie(csrf.cookie())
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {msg_html}
+    {body_html}
+</body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,140 @@
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authorization::get_user_role;
+use crate::configuration::TwoFactorSettings;
+use crate::csrf::CsrfForm;
+use crate::session_state::TypedSession;
+use crate::two_factor::{
+    confirm_enrollment, disable_two_factor, second_factor_status, verify_second_factor,
+    SecondFactor,
+};
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: String,
+}
+
+/*
+The recovery codes are only shown in the response to this request: we keep nothing but their hashes.
+*/
+#[tracing::instrument(
+    name = "Enable two-factor authentication",
+    skip(form, session, pool, audit_log, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn enable_totp(
+    form: CsrfForm<FormData>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let recovery_codes = match confirm_enrollment(user_id, &form.code, &pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(recovery_codes) => recovery_codes,
+        None => {
+            FlashMessage::error("The code is invalid.").send();
+            return Ok(see_other("/admin/totp"));
+        }
+    };
+    audit_log
+        .record(
+            &request,
+            Some(user_id),
+            AuditAction::TotpEnabled,
+            serde_json::json!({}),
+        )
+        .await;
+
+    let mut codes_html = String::new();
+    for code in recovery_codes {
+        writeln!(codes_html, "<li><code>{}</code></li>", code).unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    <p>Two-factor authentication is enabled.</p>
+    <p>Keep these recovery codes somewhere safe: each of them can be used once instead of a code from your app.
+    They will not be shown again.</p>
+    <ul>
+    {codes_html}
+    </ul>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(
+    name = "Disable two-factor authentication",
+    skip(form, session, pool, settings, audit_log, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn disable_totp(
+    form: CsrfForm<FormData>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    settings: web::Data<TwoFactorSettings>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    if second_factor_status(user_id, &pool, &settings)
+        .await
+        .map_err(e500)?
+        != SecondFactor::Enabled
+    {
+        return Ok(see_other("/admin/totp"));
+    }
+    if get_user_role(user_id, &pool)
+        .await
+        .map(|role| settings.required_for.contains(&role))
+        .map_err(e500)?
+    {
+        FlashMessage::error("Your role requires two-factor authentication.").send();
+        return Ok(see_other("/admin/totp"));
+    }
+    if !verify_second_factor(user_id, &form.code, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("The code is invalid.").send();
+        return Ok(see_other("/admin/totp"));
+    }
+
+    disable_two_factor(user_id, &pool).await.map_err(e500)?;
+    audit_log
+        .record(
+            &request,
+            Some(user_id),
+            AuditAction::TotpDisabled,
+            serde_json::json!({}),
+        )
+        .await;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/totp"))
+}
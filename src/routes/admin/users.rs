@@ -0,0 +1,409 @@
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authentication::{create_user, generate_one_time_password, PasswordPolicy};
+use crate::authorization::Permission;
+use crate::domain::{SubscriberEmail, UserRole};
+use crate::routes::admin::{authenticate_admin, AdminError};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::ExposeSecret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct UserResponse {
+    user_id: Uuid,
+    username: String,
+    email: Option<String>,
+    role: &'static str,
+    disabled: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct NewUserResponse {
+    #[serde(flatten)]
+    user: UserResponse,
+    // Shown only once: the new user is asked to replace it on their first login
+    one_time_password: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct NewUser {
+    username: String,
+    role: String,
+    // Needed to reset a forgotten password
+    email: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleChange {
+    role: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct DisabledChange {
+    disabled: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailChange {
+    email: Option<String>,
+}
+
+/// What an owner can change about another user.
+#[derive(Debug)]
+pub enum UserChange {
+    Role(UserRole),
+    Disabled(bool),
+    Email(Option<SubscriberEmail>),
+}
+
+impl UserChange {
+    // What the audit log keeps of the change
+    fn details(&self, user_id: Uuid) -> serde_json::Value {
+        match self {
+            UserChange::Role(role) => {
+                serde_json::json!({ "user_id": user_id, "role": role.as_str() })
+            }
+            UserChange::Disabled(disabled) => {
+                serde_json::json!({ "user_id": user_id, "disabled": disabled })
+            }
+            UserChange::Email(email) => serde_json::json!({
+                "user_id": user_id,
+                "email": email.as_ref().map(|e| e.as_ref()),
+            }),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ChangeUserError {
+    #[error("There must always be at least one active owner.")]
+    LastOwner,
+    #[error("Another user already has this email address.")]
+    EmailTaken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/*
+curl -u admin:password http://127.0.0.1:8000/admin/users
+*/
+#[tracing::instrument(
+    name = "List users",
+    skip(pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn list_users(
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    authenticate_admin(&request, &pool, &audit_log, Permission::ManageUsers).await?;
+    let users = get_users(&pool).await?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/*
+curl -u admin:password -X POST -H 'Content-Type: application/json' \
+    -d '{"username": "ursula", "role": "editor", "email": "ursula@example.com"}' http://127.0.0.1:8000/admin/users
+*/
+#[tracing::instrument(
+    name = "Add a user",
+    skip(body, pool, policy, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn add_user(
+    body: web::Json<NewUser>,
+    pool: web::Data<PgPool>,
+    policy: web::Data<PasswordPolicy>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let actor_id = authenticate_admin(&request, &pool, &audit_log, Permission::ManageUsers).await?;
+    let NewUser {
+        username,
+        role,
+        email,
+    } = body.into_inner();
+    let username = username.trim().to_owned();
+    if username.is_empty() || username.len() > 256 {
+        return Err(AdminError::ValidationError(
+            "The username must be between 1 and 256 characters long.".into(),
+        ));
+    }
+    let role = UserRole::parse(role).map_err(AdminError::ValidationError)?;
+    let email = email
+        .map(SubscriberEmail::parse)
+        .transpose()
+        .map_err(AdminError::ValidationError)?;
+
+    let password = generate_one_time_password();
+    let user_id = create_user(
+        &username,
+        email.as_ref().map(|e| e.as_ref()),
+        password.clone(),
+        role,
+        true,
+        &policy,
+        &pool,
+    )
+    .await?
+    .ok_or_else(|| {
+        AdminError::Conflict(format!(
+            "{} or this email address is already taken.",
+            username
+        ))
+    })?;
+    let details = serde_json::json!({
+        "user_id": user_id,
+        "username": username,
+        "role": role.as_str(),
+    });
+    audit_log
+        .record(&request, Some(actor_id), AuditAction::UserAdded, details)
+        .await;
+    Ok(HttpResponse::Created().json(NewUserResponse {
+        user: UserResponse {
+            user_id,
+            username,
+            email: email.map(|e| e.as_ref().to_owned()),
+            role: role.as_str(),
+            disabled: false,
+        },
+        one_time_password: password.expose_secret().to_owned(),
+    }))
+}
+
+/*
+curl -u admin:password -X PUT -H 'Content-Type: application/json' \
+    -d '{"role": "viewer"}' http://127.0.0.1:8000/admin/users/<user_id>/role
+*/
+#[tracing::instrument(
+    name = "Change the role of a user",
+    skip(target_id, body, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn change_user_role(
+    target_id: web::Path<Uuid>,
+    body: web::Json<RoleChange>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let actor_id = authenticate_admin(&request, &pool, &audit_log, Permission::ManageUsers).await?;
+    let role = UserRole::parse(body.into_inner().role).map_err(AdminError::ValidationError)?;
+    apply_user_change(
+        &pool,
+        &audit_log,
+        &request,
+        actor_id,
+        *target_id,
+        UserChange::Role(role),
+    )
+    .await
+}
+
+/*
+curl -u admin:password -X PUT -H 'Content-Type: application/json' \
+    -d '{"disabled": true}' http://127.0.0.1:8000/admin/users/<user_id>/disabled
+*/
+#[tracing::instrument(
+    name = "Disable or enable a user",
+    skip(target_id, body, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn change_user_disabled(
+    target_id: web::Path<Uuid>,
+    body: web::Json<DisabledChange>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let actor_id = authenticate_admin(&request, &pool, &audit_log, Permission::ManageUsers).await?;
+    let change = UserChange::Disabled(body.into_inner().disabled);
+    apply_user_change(&pool, &audit_log, &request, actor_id, *target_id, change).await
+}
+
+/*
+curl -u admin:password -X PUT -H 'Content-Type: application/json' \
+    -d '{"email": "ursula@example.com"}' http://127.0.0.1:8000/admin/users/<user_id>/email
+*/
+#[tracing::instrument(
+    name = "Change the email address of a user",
+    skip(target_id, body, pool, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn change_user_email(
+    target_id: web::Path<Uuid>,
+    body: web::Json<EmailChange>,
+    pool: web::Data<PgPool>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let actor_id = authenticate_admin(&request, &pool, &audit_log, Permission::ManageUsers).await?;
+    // `null` removes the address
+    let email = body
+        .into_inner()
+        .email
+        .map(SubscriberEmail::parse)
+        .transpose()
+        .map_err(AdminError::ValidationError)?;
+    apply_user_change(
+        &pool,
+        &audit_log,
+        &request,
+        actor_id,
+        *target_id,
+        UserChange::Email(email),
+    )
+    .await
+}
+
+async fn apply_user_change(
+    pool: &PgPool,
+    audit_log: &AuditLog,
+    request: &HttpRequest,
+    actor_id: Uuid,
+    target_id: Uuid,
+    change: UserChange,
+) -> Result<HttpResponse, AdminError> {
+    let details = change.details(target_id);
+    let user = change_user(pool, target_id, change)
+        .await
+        .map_err(|e| match e {
+            ChangeUserError::LastOwner | ChangeUserError::EmailTaken => {
+                AdminError::Conflict(e.to_string())
+            }
+            ChangeUserError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+        })?
+        .ok_or_else(|| AdminError::NotFound("User not found.".into()))?;
+    audit_log
+        .record(request, Some(actor_id), AuditAction::UserChanged, details)
+        .await;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+// The Postgres error code for a duplicate key
+const UNIQUE_VIOLATION: &str = "23505";
+
+#[tracing::instrument(name = "Get users", skip(pool))]
+pub async fn get_users(pool: &PgPool) -> Result<Vec<UserResponse>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT user_id, username, email, role, disabled_at
+        FROM users
+        ORDER BY username
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the list of users.")?;
+    rows.into_iter()
+        .map(|r| {
+            let role = UserRole::parse(r.role).map_err(anyhow::Error::msg)?;
+            Ok(UserResponse {
+                user_id: r.user_id,
+                username: r.username,
+                email: r.email,
+                role: role.as_str(),
+                disabled: r.disabled_at.is_some(),
+            })
+        })
+        .collect()
+}
+
+/// Returns `None` if there is no such user.
+/// Fails if the change would leave nobody able to manage users.
+#[tracing::instrument(name = "Change a user", skip(pool))]
+pub async fn change_user(
+    pool: &PgPool,
+    user_id: Uuid,
+    change: UserChange,
+) -> Result<Option<UserResponse>, ChangeUserError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // Two owners demoting each other at the same time must not both succeed:
+    // we hold a lock on every active owner until the transaction is over.
+    sqlx::query!(
+        r#"SELECT user_id FROM users WHERE role = 'owner' AND disabled_at IS NULL FOR UPDATE"#,
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to lock the active owners.")?;
+
+    let (role, disabled, email) = match change {
+        UserChange::Role(role) => (Some(role), None, None),
+        UserChange::Disabled(disabled) => (None, Some(disabled), None),
+        UserChange::Email(email) => (None, None, Some(email)),
+    };
+    let (set_email, email) = (email.is_some(), email.flatten());
+    let row = sqlx::query!(
+        r#"
+        UPDATE users
+        SET role = COALESCE($2, role),
+            disabled_at = CASE
+                WHEN $3::boolean IS NULL THEN disabled_at
+                WHEN $3 THEN COALESCE(disabled_at, $4)
+                ELSE NULL
+            END,
+            email = CASE WHEN $5 THEN $6 ELSE email END
+        WHERE user_id = $1
+        RETURNING user_id, username, email, role, disabled_at
+        "#,
+        user_id,
+        role.map(|r| r.as_str()),
+        disabled,
+        Utc::now(),
+        set_email,
+        email.as_ref().map(|e| e.as_ref()),
+    )
+    .fetch_optional(&mut transaction)
+    .await;
+    let row = match row {
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+            return Err(ChangeUserError::EmailTaken)
+        }
+        row => row.context("Failed to update a user.")?,
+    };
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    if count_active_owners(&mut transaction).await? == 0 {
+        // Dropping the transaction rolls the change back
+        return Err(ChangeUserError::LastOwner);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change a user.")?;
+
+    let role = UserRole::parse(row.role).map_err(anyhow::Error::msg)?;
+    Ok(Some(UserResponse {
+        user_id: row.user_id,
+        username: row.username,
+        email: row.email,
+        role: role.as_str(),
+        disabled: row.disabled_at.is_some(),
+    }))
+}
+
+async fn count_active_owners(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<i64, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM users
+        WHERE role = 'owner' AND disabled_at IS NULL
+        "#,
+    )
+    .fetch_one(transaction)
+    .await
+    .context("Failed to count the active owners.")?;
+    Ok(row.count)
+}
@@ -0,0 +1,213 @@
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::e500;
+use actix_web::http::header::{
+    self, ETag, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, TimeZone, Utc};
+use htmlescape::encode_minimal;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::time::SystemTime;
+
+// Feed readers only care about what is new: older issues are in the archive.
+const FEED_SIZE: i64 = 20;
+// Feed readers poll: they are told to come back, and to ask whether anything changed.
+const FEED_CACHE_CONTROL: &str = "public, max-age=300";
+
+struct FeedIssue {
+    slug: String,
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+// curl http://127.0.0.1:8000/archive/rss.xml
+#[tracing::instrument(name = "Serve the RSS feed", skip(request, pool, base_url))]
+pub async fn rss_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_feed_issues(&pool).await.map_err(e500)?;
+    let body = render_rss(&issues, &base_url.0);
+    Ok(feed_response(
+        &request,
+        "application/rss+xml; charset=utf-8",
+        body,
+        &issues,
+    ))
+}
+
+// curl http://127.0.0.1:8000/archive/atom.xml
+#[tracing::instrument(name = "Serve the Atom feed", skip(request, pool, base_url))]
+pub async fn atom_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_feed_issues(&pool).await.map_err(e500)?;
+    let body = render_atom(&issues, &base_url.0);
+    Ok(feed_response(
+        &request,
+        "application/atom+xml; charset=utf-8",
+        body,
+        &issues,
+    ))
+}
+
+fn permalink(base_url: &str, issue: &FeedIssue) -> String {
+    format!("{}/archive/{}", base_url, issue.slug)
+}
+
+fn render_rss(issues: &[FeedIssue], base_url: &str) -> String {
+    let items: String = issues
+        .iter()
+        .map(|issue| {
+            let link = encode_minimal(&permalink(base_url, issue));
+            format!(
+                "<item><title>{}</title><link>{link}</link><guid isPermaLink=\"true\">{link}</guid>\
+                <pubDate>{}</pubDate><description>{}</description></item>",
+                encode_minimal(&issue.title),
+                issue.published_at.to_rfc2822(),
+                encode_minimal(&issue.html_content),
+            )
+        })
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <rss version=\"2.0\"><channel><title>Newsletter</title><link>{}/archive</link>\
+        <description>Past issues of the newsletter</description>{}</channel></rss>\n",
+        encode_minimal(base_url),
+        items,
+    )
+}
+
+fn render_atom(issues: &[FeedIssue], base_url: &str) -> String {
+    let entries: String = issues
+        .iter()
+        .map(|issue| {
+            let link = encode_minimal(&permalink(base_url, issue));
+            let published_at = issue.published_at.to_rfc3339();
+            format!(
+                "<entry><title>{}</title><link href=\"{link}\"/><id>{link}</id>\
+                <published>{published_at}</published><updated>{published_at}</updated>\
+                <content type=\"html\">{}</content></entry>",
+                encode_minimal(&issue.title),
+                encode_minimal(&issue.html_content),
+            )
+        })
+        .collect();
+    // A feed without entries hasn't been updated since the beginning of time
+    let updated = last_published_at(issues).unwrap_or_else(|| Utc.timestamp(0, 0));
+    let base_url = encode_minimal(base_url);
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\"><title>Newsletter</title>\
+        <link href=\"{base_url}/archive\"/><link rel=\"self\" href=\"{base_url}/archive/atom.xml\"/>\
+        <id>{base_url}/archive</id><updated>{}</updated>{}</feed>\n",
+        updated.to_rfc3339(),
+        entries,
+    )
+}
+
+fn last_published_at(issues: &[FeedIssue]) -> Option<DateTime<Utc>> {
+    issues.iter().map(|issue| issue.published_at).max()
+}
+
+// `304 Not Modified` if the reader has the feed already, according to `If-None-Match`,
+// or to `If-Modified-Since` when it doesn't send the former.
+fn feed_response(
+    request: &HttpRequest,
+    content_type: &str,
+    body: String,
+    issues: &[FeedIssue],
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(hex::encode(&Sha256::digest(body.as_bytes())[..16]));
+    // HTTP dates are to the second
+    let last_modified = last_published_at(issues)
+        .map(|at| HttpDate::from(SystemTime::from(Utc.timestamp(at.timestamp(), 0))));
+    let not_modified = if request.headers().contains_key(header::IF_NONE_MATCH) {
+        match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            Err(_) => false,
+        }
+    } else {
+        match (IfModifiedSince::parse(request), last_modified) {
+            (Ok(IfModifiedSince(since)), Some(last_modified)) => last_modified <= since,
+            _ => false,
+        }
+    };
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(ETag(etag))
+        .insert_header((header::CACHE_CONTROL, FEED_CACHE_CONTROL));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+    if not_modified {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body)
+    }
+}
+
+// The newest public issues, newest first.
+#[tracing::instrument(skip_all)]
+async fn get_feed_issues(pool: &PgPool) -> Result<Vec<FeedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        FeedIssue,
+        r#"
+        SELECT slug, title, html_content, published_at
+        FROM newsletter_issues
+        WHERE NOT subscriber_only
+        ORDER BY published_at DESC
+        LIMIT $1
+        "#,
+        FEED_SIZE,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the newsletter issues of the feed.")?;
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_atom, render_rss, FeedIssue};
+    use chrono::{TimeZone, Utc};
+
+    fn issue() -> FeedIssue {
+        FeedIssue {
+            slug: "fish-chips".into(),
+            title: "Fish & chips".into(),
+            html_content: "<p>Body</p>".into(),
+            published_at: Utc.timestamp(1_690_000_000, 0),
+        }
+    }
+
+    #[test]
+    fn titles_and_content_are_escaped() {
+        let rss = render_rss(&[issue()], "https://example.com");
+        assert!(rss.contains("<title>Fish &amp; chips</title>"));
+        assert!(rss.contains("<description>&lt;p&gt;Body&lt;/p&gt;</description>"));
+        assert!(rss.contains("<link>https://example.com/archive/fish-chips</link>"));
+        let atom = render_atom(&[issue()], "https://example.com");
+        assert!(atom.contains("<content type=\"html\">&lt;p&gt;Body&lt;/p&gt;</content>"));
+    }
+
+    #[test]
+    fn feeds_are_updated_when_their_newest_issue_was_published() {
+        let atom = render_atom(&[issue()], "https://example.com");
+        assert!(atom.contains("<updated>2023-07-22T04:26:40+00:00</updated>"));
+        let empty = render_atom(&[], "https://example.com");
+        assert!(empty.contains("<updated>1970-01-01T00:00:00+00:00</updated>"));
+    }
+}
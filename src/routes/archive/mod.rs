@@ -0,0 +1,115 @@
+mod feeds;
+
+pub use feeds::*;
+
+use crate::utils::e500;
+use actix_web::http::header::{self, ContentType};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+
+// Issues are written for inboxes: inline styles and remote images are common, scripts are not.
+const ISSUE_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src * data:; \
+    style-src 'unsafe-inline'; base-uri 'none'; form-action 'none'; frame-ancestors 'none'";
+
+struct ArchivedIssue {
+    slug: String,
+    title: String,
+    published_at: DateTime<Utc>,
+}
+
+// curl http://127.0.0.1:8000/archive
+#[tracing::instrument(name = "List archived newsletter issues", skip(pool))]
+pub async fn archive(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_public_issues(&pool).await.map_err(e500)?;
+    let items = if issues.is_empty() {
+        "<li>Nothing has been published yet.</li>".to_owned()
+    } else {
+        issues
+            .iter()
+            .map(|issue| {
+                format!(
+                    r#"<li><a href="/archive/{}">{}</a> ({})</li>"#,
+                    encode_minimal(&issue.slug),
+                    encode_minimal(&issue.title),
+                    issue.published_at.format("%Y-%m-%d"),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let body = include_str!("archive.html").replace("{issues}", &items);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+struct IssuePage {
+    title: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+// curl http://127.0.0.1:8000/archive/hello-world
+// Subscriber-only issues are as unknown as the ones that don't exist.
+#[tracing::instrument(name = "Show an archived newsletter issue", skip(pool))]
+pub async fn archived_issue(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_public_issue(&pool, &slug).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    // The content is the one that was sent, written by a trusted publisher: it isn't escaped
+    let body = include_str!("issue.html")
+        .replace("{title}", &encode_minimal(&issue.title))
+        .replace(
+            "{published_on}",
+            &issue.published_at.format("%Y-%m-%d").to_string(),
+        )
+        .replace("{content}", &issue.html_content);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .insert_header((
+            header::CONTENT_SECURITY_POLICY,
+            ISSUE_CONTENT_SECURITY_POLICY,
+        ))
+        .body(body))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_public_issues(pool: &PgPool) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT slug, title, published_at
+        FROM newsletter_issues
+        WHERE NOT subscriber_only
+        ORDER BY published_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the archived newsletter issues.")?;
+    Ok(issues)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_public_issue(pool: &PgPool, slug: &str) -> Result<Option<IssuePage>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssuePage,
+        r#"
+        SELECT title, html_content, published_at
+        FROM newsletter_issues
+        WHERE slug = $1 AND NOT subscriber_only
+        "#,
+        slug,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve an archived newsletter issue.")?;
+    Ok(issue)
+}
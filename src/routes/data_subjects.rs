@@ -0,0 +1,291 @@
+use crate::domain::SubscriberEmail;
+use crate::routes::{error_chain_fmt, get_subscriber_id_from_token};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Everything we hold about one email address, as handed over on a data subject access request.
+#[derive(serde::Serialize)]
+pub struct PersonalDataPackage {
+    pub email: String,
+    pub generated_at: DateTime<Utc>,
+    pub subscriptions: Vec<SubscriptionRecord>,
+    pub subscription_tokens: Vec<SubscriptionTokenRecord>,
+    pub tracked_deliveries: Vec<TrackedDeliveryRecord>,
+    pub link_clicks: Vec<LinkClickRecord>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriptionTokenRecord {
+    pub subscription_token: String,
+    pub subscriber_id: Uuid,
+}
+
+// What was recorded about a newsletter email sent to the subscriber, see `tracking`.
+#[derive(serde::Serialize)]
+pub struct TrackedDeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub delivered_at: DateTime<Utc>,
+    pub first_opened_at: Option<DateTime<Utc>>,
+    pub last_opened_at: Option<DateTime<Utc>>,
+    pub open_count: i32,
+}
+
+// A link followed from a newsletter email sent to the subscriber, see `tracking`.
+#[derive(serde::Serialize)]
+pub struct LinkClickRecord {
+    pub newsletter_issue_id: Uuid,
+    pub url: String,
+    pub first_clicked_at: DateTime<Utc>,
+    pub last_clicked_at: DateTime<Utc>,
+    pub click_count: i32,
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct ErasureReport {
+    pub erased_subscriptions: u64,
+    pub deleted_subscription_tokens: u64,
+    pub cancelled_deliveries: u64,
+}
+
+#[derive(thiserror::Error)]
+pub enum DataSubjectError {
+    #[error("The subscription token is unknown.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DataSubjectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for DataSubjectError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            // Same behaviour as `confirm` when the token doesn't exist
+            DataSubjectError::UnknownToken => StatusCode::UNAUTHORIZED,
+            DataSubjectError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct TokenParameters {
+    subscription_token: String,
+}
+
+/*
+Subscribers prove who they are with the token they received in their confirmation email.
+curl 'http://127.0.0.1:8000/subscriptions/data?subscription_token=...'
+*/
+#[tracing::instrument(
+    name = "Export the personal data of a subscriber",
+    skip(parameters, pool)
+)]
+pub async fn subscriber_data(
+    parameters: web::Query<TokenParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, DataSubjectError> {
+    let email = get_email_from_token(&pool, &parameters.subscription_token).await?;
+    let package = gather_personal_data(&pool, &email)
+        .await
+        .context("Failed to gather the personal data of a subscriber.")?
+        .ok_or(DataSubjectError::UnknownToken)?;
+    Ok(HttpResponse::Ok().json(package))
+}
+
+/*
+curl -i -X POST -d 'subscription_token=...' http://127.0.0.1:8000/subscriptions/erase
+*/
+#[tracing::instrument(name = "Erase the personal data of a subscriber", skip(form, pool))]
+pub async fn erase_subscriber(
+    form: web::Form<TokenParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, DataSubjectError> {
+    let email = get_email_from_token(&pool, &form.subscription_token).await?;
+    erase_personal_data(&pool, &email)
+        .await
+        .context("Failed to erase the personal data of a subscriber.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn get_email_from_token(
+    pool: &PgPool,
+    subscription_token: &str,
+) -> Result<SubscriberEmail, DataSubjectError> {
+    let subscriber_id = get_subscriber_id_from_token(pool, subscription_token)
+        .await
+        .context("Failed to retrieve the subscriber associated with the provided token.")?
+        .ok_or(DataSubjectError::UnknownToken)?;
+    let row = sqlx::query!(
+        r#"SELECT email FROM subscriptions WHERE id = $1 AND erased_at IS NULL"#,
+        subscriber_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the email of a subscriber.")?
+    .ok_or(DataSubjectError::UnknownToken)?;
+    SubscriberEmail::parse(row.email)
+        .map_err(anyhow::Error::msg)
+        .context("The stored email of the subscriber is invalid.")
+        .map_err(Into::into)
+}
+
+/// Collect every row that references `email`.
+/// Returns `None` if we hold nothing about that address.
+#[tracing::instrument(name = "Gather personal data", skip(pool, email))]
+pub async fn gather_personal_data(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<PersonalDataPackage>, anyhow::Error> {
+    let subscriptions = sqlx::query_as!(
+        SubscriptionRecord,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE email = $1 AND erased_at IS NULL
+        "#,
+        email.as_ref(),
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscriptions of a data subject.")?;
+    if subscriptions.is_empty() {
+        return Ok(None);
+    }
+
+    let subscriber_ids: Vec<Uuid> = subscriptions.iter().map(|s| s.id).collect();
+    let subscription_tokens = sqlx::query_as!(
+        SubscriptionTokenRecord,
+        r#"
+        SELECT subscription_token, subscriber_id
+        FROM subscription_tokens
+        WHERE subscriber_id = ANY($1)
+        "#,
+        &subscriber_ids,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscription tokens of a data subject.")?;
+    let tracked_deliveries = sqlx::query_as!(
+        TrackedDeliveryRecord,
+        r#"
+        SELECT newsletter_issue_id, subscriber_id, delivered_at, first_opened_at,
+            last_opened_at, open_count
+        FROM tracked_deliveries
+        WHERE subscriber_id = ANY($1)
+        ORDER BY delivered_at
+        "#,
+        &subscriber_ids,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the tracked deliveries of a data subject.")?;
+    let link_clicks = sqlx::query_as!(
+        LinkClickRecord,
+        r#"
+        SELECT d.newsletter_issue_id, c.url, c.first_clicked_at, c.last_clicked_at,
+            c.click_count
+        FROM link_clicks c
+        JOIN tracked_deliveries d ON d.token = c.delivery_token
+        WHERE d.subscriber_id = ANY($1)
+        ORDER BY c.first_clicked_at
+        "#,
+        &subscriber_ids,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the link clicks of a data subject.")?;
+
+    Ok(Some(PersonalDataPackage {
+        email: email.as_ref().to_owned(),
+        generated_at: Utc::now(),
+        subscriptions,
+        subscription_tokens,
+        tracked_deliveries,
+        link_clicks,
+    }))
+}
+
+/// Irreversibly remove the personal data attached to `email`.
+/// Tokens are deleted outright; subscription rows, their tracked deliveries and link clicks
+/// are kept with their status, dates and counts, so that aggregate statistics don't change,
+/// but their email and name are overwritten with values that cannot be traced back to the subscriber.
+#[tracing::instrument(name = "Erase personal data", skip(pool, email))]
+pub async fn erase_personal_data(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<ErasureReport, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let deleted_tokens = sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE subscriber_id IN (SELECT id FROM subscriptions WHERE email = $1)
+        "#,
+        email.as_ref(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the subscription tokens of a data subject.")?;
+
+    // Issues that haven't been sent yet won't be
+    let cancelled_deliveries = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE subscriber_email = $1
+        "#,
+        email.as_ref(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to cancel the newsletter deliveries of a data subject.")?;
+
+    // Tracked deliveries and their link clicks are kept for the issue statistics: they only point
+    // to the subscription, which is pseudonymised here, and stop counting once it is.
+    // The replacement email is random, not derived from the original address,
+    // so it can't be reversed by hashing candidate addresses.
+    let erased_subscriptions = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET email = $2, name = 'erased', erased_at = $3
+        WHERE email = $1 AND erased_at IS NULL
+        "#,
+        email.as_ref(),
+        format!("erased-{}", Uuid::new_v4()),
+        Utc::now(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to pseudonymise the subscriptions of a data subject.")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase personal data.")?;
+
+    Ok(ErasureReport {
+        erased_subscriptions: erased_subscriptions.rows_affected(),
+        deleted_subscription_tokens: deleted_tokens.rows_affected(),
+        cancelled_deliveries: cancelled_deliveries.rows_affected(),
+    })
+}
@@ -1,6 +1,166 @@
-use actix_web::HttpResponse;
+use crate::configuration::HealthSettings;
+use crate::email_client::EmailClient;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+use std::future::Future;
+use std::time::Instant;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
 // curl -v http://127.0.0.1:8000/health_check
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+// curl -v http://127.0.0.1:8000/health/live
+// The process is up and serving requests: restarting it wouldn't help.
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Up,
+    Down,
+    Skipped,
+}
+
+#[derive(serde::Serialize)]
+struct Check {
+    status: Status,
+    // Only the outermost context of the error, the whole chain is in the logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    duration_ms: u128,
+}
+
+impl Check {
+    fn skipped() -> Self {
+        Self {
+            status: Status::Skipped,
+            error: None,
+            duration_ms: 0,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Checks {
+    database: Check,
+    migrations: Check,
+    email: Check,
+}
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    ready: bool,
+    checks: Checks,
+}
+
+// curl -v http://127.0.0.1:8000/health/ready
+// Whether the application can handle requests: 503, with the checks that failed, if it can't.
+#[tracing::instrument(name = "Check readiness", skip(pool, email_client, settings))]
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<HealthSettings>,
+) -> HttpResponse {
+    let database = async {
+        let database = run_check(&settings, "database", check_database(&pool)).await;
+        // Pointless if the database can't be reached
+        let migrations = if database.status == Status::Up {
+            run_check(&settings, "migrations", check_migrations(&pool)).await
+        } else {
+            Check::skipped()
+        };
+        (database, migrations)
+    };
+    let email = async {
+        if settings.check_email_backend {
+            let check = async {
+                email_client
+                    .check_reachable()
+                    .await
+                    .context("The email delivery service can't be reached.")
+            };
+            run_check(&settings, "email", check).await
+        } else {
+            Check::skipped()
+        }
+    };
+    let ((database, migrations), email) = tokio::join!(database, email);
+
+    let checks = Checks {
+        database,
+        migrations,
+        email,
+    };
+    let ready = [&checks.database, &checks.migrations, &checks.email]
+        .iter()
+        .all(|check| check.status != Status::Down);
+    let readiness = Readiness { ready, checks };
+    if ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+async fn run_check(
+    settings: &HealthSettings,
+    name: &str,
+    check: impl Future<Output = Result<(), anyhow::Error>>,
+) -> Check {
+    let started_at = Instant::now();
+    let outcome = tokio::time::timeout(settings.timeout(), check).await;
+    let duration_ms = started_at.elapsed().as_millis();
+    let error = match outcome {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            tracing::warn!(error.cause_chain = ?e, "The {} check failed.", name);
+            Some(e.to_string())
+        }
+        Err(_) => {
+            tracing::warn!("The {} check timed out.", name);
+            Some("Timed out.".to_owned())
+        }
+    };
+    Check {
+        status: if error.is_none() {
+            Status::Up
+        } else {
+            Status::Down
+        },
+        error,
+        duration_ms,
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!("SELECT 1 AS one")
+        .fetch_one(pool)
+        .await
+        .context("The database can't be reached.")?;
+    Ok(())
+}
+
+// Every migration this version of the application was built with has been applied.
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    // Not checked at compile time: the table belongs to sqlx, not to our schema
+    let applied: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await
+            .context("The applied migrations can't be listed.")?;
+    let pending = MIGRATOR
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .count();
+    if pending > 0 {
+        anyhow::bail!("{} migration(s) to apply.", pending);
+    }
+    Ok(())
+}
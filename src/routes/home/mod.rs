@@ -1,8 +1,19 @@
+use crate::security_headers::CspNonce;
+use crate::startup::HmacSecret;
+use crate::subscription_guard::SubscriptionGuard;
 use actix_web::http::header::ContentType;
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
 
-pub async fn home() -> HttpResponse {
+pub async fn home(
+    guard: web::Data<SubscriptionGuard>,
+    hmac_secret: web::Data<HmacSecret>,
+    nonce: CspNonce,
+) -> HttpResponse {
+    // A fresh timestamp every time the page is loaded
+    let body = include_str!("home.html")
+        .replace("{csp_nonce}", &nonce.attribute())
+        .replace("{bot_protection_fields}", &guard.form_fields(&hmac_secret));
     HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+        .body(body)
 }
@@ -1,17 +1,21 @@
+use crate::csrf::CsrfToken;
 use actix_web::http::header::ContentType;
 use actix_web::HttpResponse;
-use actix_web_flash_messages::{IncomingFlashMessages, Level};
+use actix_web_flash_messages::IncomingFlashMessages;
 use std::fmt::Write;
 
 // contains our "POST" action to /login
 // also contains our FlashMessage framework that we included as a middleware on startup
-pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
-    let mut error_html = String::new();
-    for m in flash_messages.iter().filter(|m| m.level() == Level::Error) {
-        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
+pub async fn login_form(flash_messages: IncomingFlashMessages, csrf: CsrfToken) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
 
+    let csrf_field = csrf.form_field();
+
     HttpResponse::Ok()
+        .cookie(csrf.cookie())
         .content_type(ContentType::html())
         .body(format!(
             r#"<!DOCTYPE html>
@@ -21,8 +25,9 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
                     <title>Login</title>
                 </head>
                 <body>
-                    {error_html}
+                    {msg_html}
                     <form action="/login" method="post">
+                        {csrf_field}
                         <label>Username
                             <input
                 type="text"
@@ -39,6 +44,7 @@ pub async fn login_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
                 </label>
                         <button type="submit">Login</button>
                     </form>
+                    <p><a href="/password/forgot">Forgot your password?</a></p>
                 </body>
                 </html>"#,
         ))
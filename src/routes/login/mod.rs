@@ -1,5 +1,7 @@
 mod get;
 mod post;
+mod totp;
 
 pub use get::login_form;
 pub use post::login;
+pub use totp::{login_totp, login_totp_form};
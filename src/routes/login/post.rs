@@ -1,14 +1,20 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authentication::{validate_credentials, AuthError, Credentials, PasswordPolicy};
+use crate::configuration::TwoFactorSettings;
+use crate::csrf::CsrfForm;
+use crate::login_throttle::LoginThrottle;
 use crate::routes::error_chain_fmt;
-use crate::startup::HmacSecret;
+use crate::session_state::TypedSession;
+use crate::two_factor::{second_factor_status, SecondFactor};
 use actix_web::cookie::Cookie;
 use actix_web::error::InternalError;
 use actix_web::http::header::LOCATION;
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use secrecy::Secret;
 use serde::Deserialize;
 use sqlx::PgPool;
+use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct FormData {
@@ -31,45 +37,122 @@ impl std::fmt::Debug for LoginError {
 }
 
 #[tracing::instrument(
-skip(form, pool),
+skip(form, pool, two_factor, throttle, policy, audit_log, session, request),
 fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 // We are now injecting `PgPool` to retrieve stored credentials from the database
+// One extractor per parameter, like every other handler, even if there are many of them
+#[allow(clippy::too_many_arguments)]
 pub async fn login(
-    form: web::Form<FormData>,
+    form: CsrfForm<FormData>,
     pool: web::Data<PgPool>,
-    secret: web::Data<HmacSecret>,
+    two_factor: web::Data<TwoFactorSettings>,
+    throttle: web::Data<LoginThrottle>,
+    policy: web::Data<PasswordPolicy>,
+    audit_log: web::Data<AuditLog>,
+    session: TypedSession,
+    request: HttpRequest,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
+    let username = form.0.username;
     let credentials = Credentials {
-        username: form.0.username,
+        username: username.clone(),
         password: form.0.password,
     };
 
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&username));
 
-    match validate_credentials(credentials, &pool).await {
-        Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-            Ok(HttpResponse::SeeOther()
-                .insert_header((LOCATION, "/"))
-                .finish())
-        }
-        Err(e) => {
-            let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
-                AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
-            };
-            FlashMessage::error(e.to_string()).send();
+    let client_ip = throttle.client_ip(&request);
+    let (user_id, password_change_required) =
+        match validate_credentials(credentials, client_ip, &throttle, &policy, &pool).await {
+            Ok(user_id) => (user_id, false),
+            // A one-time password is only good for choosing a new one
+            Err(AuthError::PasswordChangeRequired(user_id)) => (user_id, true),
+            Err(e) => {
+                let action = match e {
+                    AuthError::InvalidCredentials(_) => AuditAction::LoginFailed,
+                    AuthError::Throttled(_) => AuditAction::LoginThrottled,
+                    _ => return Err(login_redirect(LoginError::UnexpectedError(e.into()))),
+                };
+                let details = serde_json::json!({ "step": "password" });
+                audit_log
+                    .record_failed_authentication(&request, &username, action, details)
+                    .await;
+                let e = LoginError::AuthError(e.into());
+                return Err(login_redirect(e));
+            }
+        };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
 
-            // redirects to /login
-            // login route should pickup QueryParams (errors w/ tag) if any
-            let response = HttpResponse::SeeOther()
-                .insert_header((LOCATION, "/login"))
-                .cookie(Cookie::new("_flash", e.to_string()))
-                .finish();
-            // propagate upstream, to the middleware chain
-            // InternalError returned as an error from a request handler (it implements ResponseError)
-            Err(InternalError::from_response(e, response))
-        }
+    let second_factor = second_factor_status(user_id, &pool, &two_factor)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+    if second_factor == SecondFactor::Enabled {
+        // The user is not logged in until they have typed a code as well
+        session.renew();
+        session.clear();
+        session
+            .insert_pending_user_id(user_id)
+            .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+        return Ok(HttpResponse::SeeOther()
+            .insert_header((LOCATION, "/login/totp"))
+            .finish());
     }
+
+    throttle.record_success(&username);
+    start_session(&session, user_id).map_err(login_redirect)?;
+    let details = serde_json::json!({ "second_factor": false });
+    audit_log
+        .record(
+            &request,
+            Some(user_id),
+            AuditAction::LoginSucceeded,
+            details,
+        )
+        .await;
+    Ok(after_login(password_change_required, second_factor))
+}
+
+// Where to go once the user is logged in: they may have to secure their account first.
+pub(crate) fn after_login(
+    password_change_required: bool,
+    second_factor: SecondFactor,
+) -> HttpResponse {
+    let location = if password_change_required {
+        FlashMessage::info("You must change your one-time password before you can continue.")
+            .send();
+        "/admin/password"
+    } else if second_factor == SecondFactor::EnrollmentRequired {
+        FlashMessage::info("You must set up two-factor authentication before you can continue.")
+            .send();
+        "/admin/totp"
+    } else {
+        "/"
+    };
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+pub(crate) fn start_session(session: &TypedSession, user_id: Uuid) -> Result<(), LoginError> {
+    // A new session id on every login prevents session fixation
+    session.renew();
+    session.remove_pending_user_id();
+    session
+        .insert_user_id(user_id)
+        .map_err(|e| LoginError::UnexpectedError(e.into()))
+}
+
+// Redirect to the login page with an error message.
+pub(crate) fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+
+    // redirects to /login
+    // login route should pickup QueryParams (errors w/ tag) if any
+    let response = HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .cookie(Cookie::new("_flash", e.to_string()))
+        .finish();
+    // propagate upstream, to the middleware chain
+    // InternalError returned as an error from a request handler (it implements ResponseError)
+    InternalError::from_response(e, response)
 }
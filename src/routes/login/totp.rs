@@ -0,0 +1,144 @@
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authentication::{get_username, password_change_required};
+use crate::csrf::{CsrfForm, CsrfToken};
+use crate::login_throttle::LoginThrottle;
+use crate::routes::login::post::{after_login, login_redirect, start_session, LoginError};
+use crate::session_state::TypedSession;
+use crate::two_factor::{verify_second_factor, SecondFactor};
+use crate::utils::{e500, see_other};
+use actix_web::error::InternalError;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages, Level};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    // Either the current TOTP code or one of the recovery codes
+    code: String,
+}
+
+// The second step of the login form, for users with two-factor authentication.
+pub async fn login_totp_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_pending_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+
+    let mut error_html = String::new();
+    for m in flash_messages.iter().filter(|m| m.level() == Level::Error) {
+        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let csrf_field = csrf.form_field();
+
+    Ok(HttpResponse::Ok()
+        .cookie(csrf.cookie())
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Two-factor authentication</title>
+</head>
+<body>
+    {error_html}
+    <form action="/login/totp" method="post">
+        {csrf_field}
+        <label>Code from your authenticator app, or a recovery code
+            <input
+                type="text"
+                autocomplete="one-time-code"
+                placeholder="123456"
+                name="code"
+            >
+        </label>
+        <button type="submit">Login</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(
+    name = "Log in with a second factor",
+    skip(form, pool, throttle, audit_log, session, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn login_totp(
+    form: CsrfForm<FormData>,
+    pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottle>,
+    audit_log: web::Data<AuditLog>,
+    session: TypedSession,
+    request: HttpRequest,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let user_id = match session
+        .get_pending_user_id()
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?
+    {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let username = get_username(user_id, &pool)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+    tracing::Span::current().record("username", tracing::field::display(&username));
+
+    // Codes are short: guessing them is throttled just like guessing passwords
+    let client_ip = throttle.client_ip(&request);
+    if throttle.check(&username, client_ip).is_err() {
+        let details = serde_json::json!({ "step": "second_factor" });
+        audit_log
+            .record_failed_authentication_of(
+                &request,
+                Some(user_id),
+                AuditAction::LoginThrottled,
+                details,
+            )
+            .await;
+        session.clear();
+        return Err(login_redirect(LoginError::AuthError(anyhow::anyhow!(
+            "Too many failed attempts."
+        ))));
+    }
+    let valid = verify_second_factor(user_id, &form.code, &pool)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+    if !valid {
+        throttle.record_failure(&username, client_ip);
+        let details = serde_json::json!({ "step": "second_factor" });
+        audit_log
+            .record_failed_authentication_of(
+                &request,
+                Some(user_id),
+                AuditAction::LoginFailed,
+                details,
+            )
+            .await;
+        FlashMessage::error("The code is invalid.").send();
+        return Ok(see_other("/login/totp"));
+    }
+    throttle.record_success(&username);
+
+    let password_change_required = password_change_required(user_id, &pool)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+    start_session(&session, user_id).map_err(login_redirect)?;
+    let details = serde_json::json!({ "second_factor": true });
+    audit_log
+        .record(
+            &request,
+            Some(user_id),
+            AuditAction::LoginSucceeded,
+            details,
+        )
+        .await;
+    Ok(after_login(password_change_required, SecondFactor::Enabled))
+}
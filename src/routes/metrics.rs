@@ -0,0 +1,11 @@
+use crate::metrics::render;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+// curl http://127.0.0.1:8000/metrics
+// Served on `metrics.port` instead, if one is configured.
+pub async fn prometheus_metrics(pool: web::Data<PgPool>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(render(&pool))
+}
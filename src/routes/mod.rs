@@ -1,13 +1,25 @@
+mod admin;
+mod archive;
+mod data_subjects;
 mod health_check;
 mod home;
 mod login;
+mod metrics;
 mod newsletter;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod tracking;
 
+pub use admin::*;
+pub use archive::*;
+pub use data_subjects::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
+pub use metrics::*;
 pub use newsletter::*;
+pub use password_reset::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use tracking::*;
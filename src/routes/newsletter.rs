@@ -1,21 +1,24 @@
-use crate::authentication::{validate_credentials, AuthError, Credentials};
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authentication::{authenticate, AuthError};
+use crate::authorization::{require_permission, AuthorizationError, Permission};
 use crate::routes::error_chain_fmt;
-use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::header::HeaderValue;
 use actix_web::http::{header, StatusCode};
 use actix_web::HttpResponse;
 use actix_web::ResponseError;
 use actix_web::{web, HttpRequest};
 use anyhow::Context;
-use secrecy::{ExposeSecret, Secret};
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use tokio::task::JoinHandle;
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    // Subscriber-only issues are left out of the public archive
+    #[serde(default)]
+    subscriber_only: bool,
 }
 
 #[derive(serde::Deserialize)]
@@ -24,14 +27,12 @@ pub struct Content {
     text: String,
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
-}
-
 #[derive(thiserror::Error)]
 pub enum PublishError {
     #[error("Authentication failed.")]
     AuthError(#[source] anyhow::Error),
+    #[error("The user is not allowed to publish.")]
+    Forbidden(#[source] anyhow::Error),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -56,6 +57,8 @@ impl ResponseError for PublishError {
                     .insert(header::WWW_AUTHENTICATE, header_value);
                 response
             }
+            // The credentials are fine, so asking for them again would not help
+            PublishError::Forbidden(_) => HttpResponse::new(StatusCode::FORBIDDEN),
         }
     }
 }
@@ -69,119 +72,147 @@ impl std::fmt::Debug for PublishError {
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request),
+    skip(body, pool, audit_log, request),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    audit_log: web::Data<AuditLog>,
     request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-    let credentials = basic_authentication(request.headers())
-        // Bubble up the error, performing the necessary conversion
-        .map_err(PublishError::AuthError)?;
+    let user = match authenticate(&request, &pool).await {
+        Ok(user) => user,
+        Err(e) => {
+            audit_log.record_api_auth_failure(&request, &e).await;
+            // We match on `AuthError`'s variants, but we pass the **whole** error
+            // into the constructors for `PublishError` variants. This ensures that
+            // the context of the top-level wrapper is preserved when the error is
+            // logged by our middleware.
+            return Err(match e {
+                AuthError::InvalidCredentials(_) | AuthError::Throttled(_) => {
+                    PublishError::AuthError(e.into())
+                }
+                AuthError::PasswordChangeRequired(_)
+                | AuthError::TwoFactorEnrollmentRequired(_) => PublishError::Forbidden(e.into()),
+                AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+            });
+        }
+    };
 
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current()
+        .record("username", tracing::field::display(&user.username))
+        .record("user_id", tracing::field::display(&user.user_id));
 
-    let user_id = validate_credentials(credentials, &pool)
+    require_permission(&user, Permission::PublishNewsletter, &pool)
         .await
-        // We match on `AuthError`'s variants, but we pass the **whole** error
-        // into the constructors for `PublishError` variants. This ensures that
-        // the context of the top-level wrapper is preserved when the error is
-        // logged by our middleware.
         .map_err(|e| match e {
-            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
-            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+            AuthorizationError::Forbidden(_) => PublishError::Forbidden(e.into()),
+            AuthorizationError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
         })?;
 
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    // a close relative of context, to convert the error variant of Result into anyhow::Error
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    // We record the error chain as a structured field // on the log record.
-                    error.cause_chain = ?error,
-                     "Skipping a confirmed subscriber. \
-                    Their stored contact details are invalid"
-                )
-            }
-        }
-    }
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let (issue_id, slug) = insert_newsletter_issue(&mut transaction, &body)
+        .await
+        .context("Failed to store newsletter issue details")?;
+    // Sent in the background by the delivery worker, which survives restarts
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a newsletter issue")?;
+    let details = serde_json::json!({
+        "newsletter_issue_id": issue_id,
+        "title": body.title,
+        "slug": slug,
+        "subscriber_only": body.subscriber_only,
+    });
+    audit_log
+        .record(
+            &request,
+            Some(user.user_id),
+            AuditAction::NewsletterPublished,
+            details,
+        )
+        .await;
     Ok(HttpResponse::Ok().finish())
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
-        r#"
-    SELECT email
-            FROM subscriptions
-            WHERE status = 'confirmed'
+// The slug comes from the title. Should another issue have it already,
+// a piece of the issue id is appended to tell them apart.
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    body: &BodyData,
+) -> Result<(Uuid, String), anyhow::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let slug = slugify(&body.title);
+    let suffix = &newsletter_issue_id.simple().to_string()[..8];
+    let candidates = [slug.clone(), format!("{}-{}", slug, suffix)];
+    for slug in candidates {
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO newsletter_issues (
+                newsletter_issue_id, title, text_content, html_content, published_at,
+                slug, subscriber_only
+            )
+            VALUES ($1, $2, $3, $4, now(), $5, $6)
+            ON CONFLICT (slug) DO NOTHING
             "#,
-    )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
-    Ok(confirmed_subscribers)
+            newsletter_issue_id,
+            body.title,
+            body.content.text,
+            body.content.html,
+            slug,
+            body.subscriber_only,
+        )
+        .execute(&mut *transaction)
+        .await?;
+        if inserted.rows_affected() == 1 {
+            return Ok((newsletter_issue_id, slug));
+        }
+    }
+    anyhow::bail!("No slug left for the newsletter issue.")
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    let header_value = headers
-        .get("Authorization")
-        .context("The authorization header was missing")?
-        .to_str()
-        .context("The 'Authorization' header was not a valid UTF8 string.")?;
-
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'")?;
-
-    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
-        .context("Failed to base64-decode 'Basic' credentials.")?;
-
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not valid UTF8.")?;
-
-    // Split into two segments, using ':' as delimiter
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
-        .to_string();
-
-    let password = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
-        .to_string();
-
-    Ok(Credentials {
-        username,
-        password: Secret::new(password),
-    })
+/// Lowercase ASCII letters and digits, with a single `-` between words.
+fn slugify(title: &str) -> String {
+    let slug = title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        "issue".to_owned()
+    } else {
+        slug
+    }
+}
+
+// One task per confirmed subscriber, as they are now: later subscribers don't get this issue.
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed' AND erased_at IS NULL
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
 
 pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
@@ -192,3 +223,24 @@ where
     let current_span = tracing::Span::current();
     tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::slugify;
+
+    #[test]
+    fn slugs_are_lowercase_words_joined_by_hyphens() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Issue #12 -- July 2023 "), "issue-12-july-2023");
+    }
+
+    #[test]
+    fn characters_outside_ascii_are_dropped() {
+        assert_eq!(slugify("Café résumé"), "caf-r-sum");
+    }
+
+    #[test]
+    fn titles_without_letters_nor_digits_get_a_placeholder() {
+        assert_eq!(slugify("¿¡!?"), "issue");
+    }
+}
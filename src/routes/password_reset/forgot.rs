@@ -0,0 +1,100 @@
+use crate::csrf::{CsrfForm, CsrfToken};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::password_reset::{send_reset_link, PasswordResetThrottle};
+use crate::request_id::with_current_request_id;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::see_other;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use sqlx::PgPool;
+use std::fmt::Write;
+use tracing::Instrument;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+pub async fn forgot_password_form(
+    flash_messages: IncomingFlashMessages,
+    csrf: CsrfToken,
+) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let csrf_field = csrf.form_field();
+
+    HttpResponse::Ok()
+        .cookie(csrf.cookie())
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Forgot password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/password/forgot" method="post">
+        {csrf_field}
+        <label>Email address of your account
+            <input
+                type="email"
+                placeholder="Enter your email address"
+                name="email"
+            >
+        </label>
+        <button type="submit">Send me a reset link</button>
+    </form>
+</body>
+</html>"#,
+        ))
+}
+
+/*
+The answer is the same whether or not an account uses the address,
+and the email is sent in the background so that the response time doesn't tell either.
+Requests are limited per IP address and per email address, see `PasswordResetThrottle`.
+*/
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, pool, email_client, base_url, throttle, request)
+)]
+pub async fn forgot_password(
+    form: CsrfForm<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    throttle: web::Data<PasswordResetThrottle>,
+    request: HttpRequest,
+) -> HttpResponse {
+    if let Ok(email) = SubscriberEmail::parse(form.0.email) {
+        // Counted whether or not an account uses the address, so that it doesn't tell either
+        if !throttle.allow(throttle.client_ip(&request), &email) {
+            tracing::warn!("Too many password reset requests.");
+            FlashMessage::error("Too many password reset requests. Please try again later.").send();
+            return see_other("/password/forgot");
+        }
+        tokio::spawn(with_current_request_id(
+            async move {
+                if let Err(e) = send_reset_link(&email, &pool, &email_client, &base_url.0).await {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        "Failed to send a password reset link."
+                    );
+                }
+            }
+            .instrument(tracing::Span::current()),
+        ));
+    }
+    FlashMessage::info(
+        "If an account uses this email address, a link to reset its password is on its way.",
+    )
+    .send();
+    see_other("/password/forgot")
+}
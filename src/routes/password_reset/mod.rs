@@ -0,0 +1,5 @@
+mod forgot;
+mod reset;
+
+pub use forgot::{forgot_password, forgot_password_form};
+pub use reset::{reset_password, reset_password_form};
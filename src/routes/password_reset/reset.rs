@@ -0,0 +1,141 @@
+use crate::audit_log::{AuditAction, AuditLog};
+use crate::authentication::{change_password, get_username, validate_new_password, PasswordPolicy};
+use crate::csrf::{CsrfForm, CsrfToken};
+use crate::login_throttle::LoginThrottle;
+use crate::password_reset::{consume_reset_token, is_valid_reset_token};
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: Secret<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    token: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+const INVALID_LINK: &str = "This link is invalid or has expired. You can ask for a new one.";
+
+// Reached from the link in the reset email.
+pub async fn reset_password_form(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    csrf: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let token = &parameters.token;
+    if !is_valid_reset_token(token, &pool).await.map_err(e500)? {
+        FlashMessage::error(INVALID_LINK).send();
+        return Ok(see_other("/password/forgot"));
+    }
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let token = htmlescape::encode_attribute(token.expose_secret());
+
+    let csrf_field = csrf.form_field();
+
+    Ok(HttpResponse::Ok()
+        .cookie(csrf.cookie())
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Reset Password</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/password/reset" method="post">
+        {csrf_field}
+        <input type="hidden" name="token" value="{token}">
+        <label>New password
+            <input
+                type="password"
+                placeholder="Enter new password"
+                name="new_password"
+            >
+        </label>
+        <br>
+        <label>Confirm new password
+            <input
+                type="password"
+                placeholder="Type the new password again"
+                name="new_password_check"
+            >
+        </label>
+        <br>
+        <button type="submit">Reset password</button>
+    </form>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(
+    name = "Reset password",
+    skip(form, pool, policy, throttle, audit_log, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn reset_password(
+    form: CsrfForm<FormData>,
+    pool: web::Data<PgPool>,
+    policy: web::Data<PasswordPolicy>,
+    throttle: web::Data<LoginThrottle>,
+    audit_log: web::Data<AuditLog>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData {
+        token,
+        new_password,
+        new_password_check,
+    } = form.into_inner();
+
+    if let Err(e) = validate_new_password(&new_password, &new_password_check) {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&format!(
+            "/password/reset?token={}",
+            urlencoding::encode(token.expose_secret())
+        )));
+    }
+
+    // Only now that the new password is acceptable: a typo must not burn the link
+    let user_id = match consume_reset_token(&token, &pool).await.map_err(e500)? {
+        Some(user_id) => user_id,
+        None => {
+            FlashMessage::error(INVALID_LINK).send();
+            return Ok(see_other("/password/forgot"));
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    tracing::Span::current().record("username", tracing::field::display(&username));
+
+    change_password(&username, new_password, &policy, &pool)
+        .await
+        .map_err(e500)?;
+    // Whoever was locked out by failed logins can log in with the new password right away
+    throttle.record_success(&username);
+    audit_log
+        .record(
+            &request,
+            Some(user_id),
+            AuditAction::PasswordReset,
+            serde_json::json!({}),
+        )
+        .await;
+    FlashMessage::info("Your password has been reset. You can now log in.").send();
+    Ok(see_other("/login"))
+}
@@ -1,51 +1,57 @@
-use actix_web::{web, HttpResponse, ResponseError};
 use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 
 use anyhow::Context;
+use chrono::Utc;
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
-use chrono::Utc;
 
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
-use crate::{domain::{NewSubscriber, SubscriberName, SubscriberEmail}, email_client::EmailClient, startup::ApplicationBaseUrl};
+use crate::metrics::{record_email, EmailKind};
+use crate::subscription_guard::{Rejection, SubscriptionGuard};
+use crate::{
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    email_client::EmailClient,
+    startup::{ApplicationBaseUrl, HmacSecret},
+};
 
 impl TryFrom<FormData> for NewSubscriber {
     type Error = String;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?; 
-        let email = SubscriberEmail::parse(value.email)?; 
+        let name = SubscriberName::parse(value.name)?;
+        let email = SubscriberEmail::parse(value.email)?;
         Ok(Self { email, name })
     }
 }
 
-
-// #[from] automatically derives an implementation of From for the type it has been applied to into 
+// #[from] automatically derives an implementation of From for the type it has been applied to into
 // the top-level error type (e.g. impl From<StoreTokenError> for SubscribeError {/* */}).
-#[derive(thiserror::Error)] 
+#[derive(thiserror::Error)]
 pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
+    #[error("Too many subscriptions, please try again later.")]
+    TooManyRequests,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
 // We are still using a bespoke implementation of `Debug` // to get a nice report using the error source chain
 impl std::fmt::Debug for SubscribeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { 
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
-    } 
+    }
 }
 
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
-            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST, 
-            SubscribeError::UnexpectedError(_) => {
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
+            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
@@ -74,7 +80,10 @@ impl std::fmt::Display for StoreTokenError {
 }
 
 // chain errors (currently not part of the std lib)
-pub fn error_chain_fmt(e: &impl std::error::Error, f: &mut std::fmt::Formatter<'_>,) -> std::fmt::Result {
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
     writeln!(f, "{}\n", e)?;
     let mut current = e.source();
     while let Some(cause) = current {
@@ -91,9 +100,15 @@ pub fn error_chain_fmt(e: &impl std::error::Error, f: &mut std::fmt::Formatter<'
 pub struct FormData {
     email: String,
     name: String,
+    // The bot protection fields of the form on the home page, see `SubscriptionGuard`
+    #[serde(default)]
+    website: String,
+    form_issued_at: Option<String>,
 }
 /* Test
-curl -i -X POST -d 'email=thomas_mann@hotmail.com&name=Tom' \
+The `form_issued_at` field comes from the form on the home page (http://127.0.0.1:8000/):
+without it, the request is taken for a bot's and silently ignored.
+curl -i -X POST -d 'email=thomas_mann@hotmail.com&name=Tom&form_issued_at=<from the form>' \
     http://127.0.0.1:8000/subscriptions
 */
 // All arguments in the signature of a route handler must implement the FromRequest trait: actix-web will invoke from_request for each argument.
@@ -110,9 +125,9 @@ the function declaration, while the function body focuses on the actual business
 */
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pg_pool, email_client, base_url),
+    skip(form, pg_pool, email_client, base_url, guard, hmac_secret, request),
     fields(
-        subscriber_email = %form.email, 
+        subscriber_email = %form.email,
         subscriber_name= %form.name
     )
 )]
@@ -122,83 +137,125 @@ pub async fn subscribe(
     pg_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    guard: web::Data<SubscriptionGuard>,
+    hmac_secret: web::Data<HmacSecret>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, SubscribeError> {
     // `web::Form` is a wrapper around `FormData`
     // `form.0` gives us access to the underlying `FormData`
 
+    // Bots get the same answer as everybody else, but nothing is stored and no email is sent
+    match guard.check_form(&hmac_secret, &form.website, form.form_issued_at.as_deref()) {
+        Ok(()) => {}
+        Err(Rejection::SuspectedBot(reason)) => {
+            tracing::info!(reason, "Ignored a subscription from a suspected bot.");
+            return Ok(HttpResponse::Ok().finish());
+        }
+        Err(Rejection::Expired) => {
+            return Err(SubscribeError::ValidationError(
+                "The form has expired, please reload the page and try again.".into(),
+            ))
+        }
+        Err(Rejection::TooManyRequests) => return Err(SubscribeError::TooManyRequests),
+    }
+
     // We no longer have `#[from]` for `ValidationError`, (see thiserror macro) so we need to
     // map the error explicitly
     // That is because String does not implement the Error trait, therefore it can- not be returned in Error::source
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    // Only valid subscriptions count against the limits
+    guard
+        .check_rate(guard.client_ip(&request), &new_subscriber.email)
+        .map_err(|_| SubscribeError::TooManyRequests)?;
 
     // A mutable reference to a Transaction implements sqlx’s Executor trait therefore it can be used to run queries
-    let mut transaction = pg_pool.begin()
+    let mut transaction = pg_pool
+        .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id =insert_subscriber(&mut transaction, &new_subscriber)
+    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to insert new subscriber in the database.")?;
 
     let subscription_token = generate_subscription_token();
-    
+
     // exit early if anything fails
     store_token(&mut transaction, subscriber_id, &subscription_token)
         .await
         .context("Failed to store the confirmation token for a new subscriber.")?;
 
     // we need to manually commit the transaction to close the connection and stop rollbacks
-    transaction.commit()
+    transaction
+        .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
 
-    send_confirmation_email(&email_client, new_subscriber, &base_url.0, &subscription_token)
-        .await
-        .context("Failed to send a confirmation email.")?;
-    
+    send_confirmation_email(
+        &email_client,
+        new_subscriber,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send a confirmation email.")?;
+
     Ok(HttpResponse::Ok().finish())
 }
 
 #[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber", skip(email_client, new_subscriber)
+    name = "Send a confirmation email to a new subscriber",
+    skip(email_client, new_subscriber)
 )]
-pub async fn send_confirmation_email(email_client: &EmailClient, 
-    new_subscriber: NewSubscriber, 
+pub async fn send_confirmation_email(
+    email_client: &EmailClient,
+    new_subscriber: NewSubscriber,
     base_url: &str,
     subscption_token: &str,
-) -> Result<(), reqwest::Error>{
-    let confirmation_link =
-        format!("{}/subscriptions/confirm?subscription_token={}", base_url, subscption_token);
-
-        let plain_body = format!(
-            "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-            confirmation_link
-        );
-        let html_body = format!(
-            "Welcome to our newsletter!<br />\
+) -> Result<(), reqwest::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscption_token
+    );
+
+    let plain_body = format!(
+        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
+        confirmation_link
+    );
+    let html_body = format!(
+        "Welcome to our newsletter!<br />\
             Click <a href=\"{}\">here</a> to confirm your subscription.",
-                  confirmation_link
-        );
+        confirmation_link
+    );
     // send an email to subscriber
-     email_client.send_email(
-        &new_subscriber.email, "welcome title",
-        &html_body,
-        &plain_body,
-    ).await
+    let result = email_client
+        .send_email(
+            &new_subscriber.email,
+            "welcome title",
+            &html_body,
+            &plain_body,
+        )
+        .await;
+    record_email(EmailKind::Confirmation, result.is_ok());
+    result
 }
 
 pub fn parse_subscriber(form: FormData) -> Result<NewSubscriber, String> {
     let name = SubscriberName::parse(form.name)?;
     let email = SubscriberEmail::parse(form.email)?;
 
-    Ok(NewSubscriber {email, name})
+    Ok(NewSubscriber { email, name })
 }
 
 #[tracing::instrument(
     name = "Saving new subscriber details to the database",
     skip(new_subscriber, transaction)
 )]
-pub async fn insert_subscriber(transaction: &mut Transaction<'_, Postgres>, new_subscriber: &NewSubscriber) -> Result<Uuid, sqlx::Error> {
+pub async fn insert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Uuid, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
     sqlx::query!(
         r#"
@@ -218,18 +275,19 @@ pub async fn insert_subscriber(transaction: &mut Transaction<'_, Postgres>, new_
     Ok(subscriber_id)
 }
 
-
 /// Generate a random 25-characters-long case-sensitive subscription token.
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
-    .map(char::from)
-    .take(25)
-    .collect()
+        .map(char::from)
+        .take(25)
+        .collect()
 }
 
 #[tracing::instrument(
-name = "Store subscription token in the database", skip(subscription_token, transaction))]
+    name = "Store subscription token in the database",
+    skip(subscription_token, transaction)
+)]
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
@@ -247,9 +305,9 @@ pub async fn store_token(
     Ok(())
 }
 
-    /*
-    The interesting thing about our PgConnection extractor, or extractors in general,
-    is actix-web uses a type-map to represent its application state: a HashMap that stores
-     arbitrary data (using the Any type) against their unique type identifier (obtained via TypeId::of).
-    (Think of dependency injection technique from other languages)
-    */
\ No newline at end of file
+/*
+The interesting thing about our PgConnection extractor, or extractors in general,
+is actix-web uses a type-map to represent its application state: a HashMap that stores
+ arbitrary data (using the Any type) against their unique type identifier (obtained via TypeId::of).
+(Think of dependency injection technique from other languages)
+*/
@@ -0,0 +1,67 @@
+use crate::configuration::TrackingSettings;
+use crate::startup::HmacSecret;
+use crate::tracking::{record_click, record_open, verify_click, PIXEL_GIF};
+use actix_web::http::header;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+/*
+Loaded by the tracking pixel of newsletter emails, see `tracking`.
+curl http://127.0.0.1:8000/t/o/{token}
+*/
+// The pixel is served whatever happens: email clients show a broken image otherwise,
+// and unknown tokens shouldn't be told apart from known ones.
+#[tracing::instrument(name = "Track an open", skip(token, pool, tracking))]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracking: web::Data<TrackingSettings>,
+) -> HttpResponse {
+    if tracking.opens {
+        if let Err(e) = record_open(&pool, &token).await {
+            tracing::warn!(error.cause_chain = ?e, "Failed to record an open.");
+        }
+    }
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        // Every open has to reach us
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(PIXEL_GIF)
+}
+
+#[derive(serde::Deserialize)]
+pub struct ClickParameters {
+    url: String,
+    sig: String,
+}
+
+/*
+The links of newsletter emails go through here, see `tracking`.
+curl -i 'http://127.0.0.1:8000/t/c/{token}?url=https%3A%2F%2Fexample.com&sig=...'
+*/
+// Only links we have signed are followed: anything else could send readers anywhere.
+#[tracing::instrument(
+    name = "Track a click",
+    skip(token, parameters, pool, tracking, hmac_secret)
+)]
+pub async fn track_click(
+    token: web::Path<String>,
+    parameters: web::Query<ClickParameters>,
+    pool: web::Data<PgPool>,
+    tracking: web::Data<TrackingSettings>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    if !verify_click(&hmac_secret, &token, &parameters.url, &parameters.sig) {
+        return HttpResponse::BadRequest().finish();
+    }
+    if tracking.clicks {
+        if let Err(e) = record_click(&pool, &token, &parameters.url).await {
+            tracing::warn!(error.cause_chain = ?e, "Failed to record a click.");
+        }
+    }
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, parameters.url.as_str()))
+        // Every click has to reach us
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .finish()
+}
@@ -0,0 +1,272 @@
+use crate::configuration::SecurityHeadersSettings;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+
+// Pages to log in, to recover an account and to administer the newsletter.
+const ADMIN_PREFIXES: [&str; 3] = ["/admin", "/login", "/password"];
+
+/// Routes with the same security policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteGroup {
+    /// HTML pages anybody can see.
+    PublicPages,
+    /// Everything behind a login, or part of logging in, whether HTML or JSON.
+    /// Never cached, and their URLs never leak through `Referer` (they may carry a token).
+    Admin,
+    /// Anything else that isn't HTML: JSON, feeds, health checks...
+    Api,
+}
+
+impl RouteGroup {
+    pub fn of(path: &str, is_html: bool) -> Self {
+        let is_admin = ADMIN_PREFIXES
+            .iter()
+            .any(|prefix| path == *prefix || path.starts_with(&format!("{}/", prefix)));
+        match (is_admin, is_html) {
+            (true, _) => RouteGroup::Admin,
+            (false, true) => RouteGroup::PublicPages,
+            (false, false) => RouteGroup::Api,
+        }
+    }
+}
+
+/// The nonce of the Content-Security-Policy of this response:
+/// only inline `<script>` and `<style>` elements carrying it are allowed to run.
+#[derive(Clone, Debug)]
+pub struct CspNonce(String);
+
+impl CspNonce {
+    fn generate() -> Self {
+        let nonce = std::iter::repeat_with(|| rand::thread_rng().sample(Alphanumeric))
+            .map(char::from)
+            .take(22)
+            .collect();
+        Self(nonce)
+    }
+
+    /// To put in the tag, e.g. `<style {}>`.
+    pub fn attribute(&self) -> String {
+        format!(r#"nonce="{}""#, self.0)
+    }
+}
+
+impl AsRef<str> for CspNonce {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRequest for CspNonce {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<CspNonce, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let nonce = req.extensions().get::<CspNonce>().cloned().ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError("SecurityHeaders is not wrapped")
+        });
+        ready(nonce)
+    }
+}
+
+/// Sets the security headers of every response, according to its `RouteGroup`.
+/// Headers already set by a handler are left alone.
+#[derive(Clone)]
+pub struct SecurityHeaders {
+    settings: Arc<SecurityHeadersSettings>,
+}
+
+impl SecurityHeaders {
+    pub fn new(settings: SecurityHeadersSettings) -> Self {
+        Self {
+            settings: Arc::new(settings),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = SecurityHeadersMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SecurityHeadersMiddleware {
+            service: Rc::new(service),
+            settings: self.settings.clone(),
+        }))
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: Rc<S>,
+    settings: Arc<SecurityHeadersSettings>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let nonce = CspNonce::generate();
+        req.extensions_mut().insert(nonce.clone());
+        let service = self.service.clone();
+        let settings = self.settings.clone();
+        Box::pin(async move {
+            let mut res = service.call(req).await?;
+            let is_html = res
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.starts_with("text/html"));
+            let group = RouteGroup::of(res.request().path(), is_html);
+            let headers = res.headers_mut();
+            for (name, value) in security_headers(&settings, group, is_html, &nonce) {
+                if !headers.contains_key(&name) {
+                    headers.insert(name, value);
+                }
+            }
+            Ok(res)
+        })
+    }
+}
+
+fn security_headers(
+    settings: &SecurityHeadersSettings,
+    group: RouteGroup,
+    is_html: bool,
+    nonce: &CspNonce,
+) -> Vec<(HeaderName, HeaderValue)> {
+    let mut csp = if is_html {
+        format!(
+            "default-src 'self'; script-src 'nonce-{0}'; style-src 'self' 'nonce-{0}'; \
+            object-src 'none'; base-uri 'none'; form-action 'self'; frame-ancestors 'none'",
+            nonce.as_ref()
+        )
+    } else {
+        // Nothing to render, nothing to load
+        "default-src 'none'; frame-ancestors 'none'".to_owned()
+    };
+    if let Some(report_uri) = &settings.csp_report_uri {
+        csp.push_str(&format!("; report-uri {}", report_uri));
+    }
+    let referrer_policy = match group {
+        RouteGroup::PublicPages => "strict-origin-when-cross-origin",
+        RouteGroup::Admin | RouteGroup::Api => "no-referrer",
+    };
+
+    let mut headers = vec![
+        (header::CONTENT_SECURITY_POLICY, csp),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_owned()),
+        (header::X_FRAME_OPTIONS, "DENY".to_owned()),
+        (header::REFERRER_POLICY, referrer_policy.to_owned()),
+    ];
+    if group == RouteGroup::Admin {
+        headers.push((header::CACHE_CONTROL, "no-store".to_owned()));
+    }
+    if settings.hsts_max_age_seconds > 0 {
+        headers.push((
+            header::STRICT_TRANSPORT_SECURITY,
+            format!(
+                "max-age={}; includeSubDomains",
+                settings.hsts_max_age_seconds
+            ),
+        ));
+    }
+    headers
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, HeaderValue::from_str(&value).ok()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(hsts_max_age_seconds: u64) -> SecurityHeadersSettings {
+        SecurityHeadersSettings {
+            hsts_max_age_seconds,
+            csp_report_uri: None,
+        }
+    }
+
+    fn header(headers: &[(HeaderName, HeaderValue)], name: HeaderName) -> Option<&str> {
+        headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.to_str().unwrap())
+    }
+
+    #[test]
+    fn routes_are_grouped_by_path_and_content() {
+        assert_eq!(RouteGroup::of("/", true), RouteGroup::PublicPages);
+        assert_eq!(RouteGroup::of("/login", true), RouteGroup::Admin);
+        assert_eq!(RouteGroup::of("/login/totp", true), RouteGroup::Admin);
+        assert_eq!(RouteGroup::of("/password/reset", true), RouteGroup::Admin);
+        assert_eq!(RouteGroup::of("/admin/users", false), RouteGroup::Admin);
+        assert_eq!(
+            RouteGroup::of("/administrators", true),
+            RouteGroup::PublicPages
+        );
+        assert_eq!(RouteGroup::of("/health_check", false), RouteGroup::Api);
+    }
+
+    #[test]
+    fn html_pages_only_allow_inline_content_with_the_nonce() {
+        let nonce = CspNonce::generate();
+        let headers = security_headers(&settings(0), RouteGroup::PublicPages, true, &nonce);
+        let csp = header(&headers, header::CONTENT_SECURITY_POLICY).unwrap();
+        assert!(csp.contains(&format!("script-src 'nonce-{}'", nonce.as_ref())));
+        assert!(csp.contains(&format!("style-src 'self' 'nonce-{}'", nonce.as_ref())));
+        assert!(csp.contains("frame-ancestors 'none'"));
+    }
+
+    #[test]
+    fn admin_responses_are_never_cached_nor_referred() {
+        let headers = security_headers(
+            &settings(0),
+            RouteGroup::Admin,
+            false,
+            &CspNonce::generate(),
+        );
+        assert_eq!(header(&headers, header::CACHE_CONTROL), Some("no-store"));
+        assert_eq!(
+            header(&headers, header::REFERRER_POLICY),
+            Some("no-referrer")
+        );
+        assert_eq!(
+            header(&headers, header::CONTENT_SECURITY_POLICY),
+            Some("default-src 'none'; frame-ancestors 'none'")
+        );
+    }
+
+    #[test]
+    fn hsts_is_only_sent_when_configured() {
+        let nonce = CspNonce::generate();
+        let headers = security_headers(&settings(0), RouteGroup::Api, false, &nonce);
+        assert_eq!(header(&headers, header::STRICT_TRANSPORT_SECURITY), None);
+        let headers = security_headers(&settings(31536000), RouteGroup::Api, false, &nonce);
+        assert_eq!(
+            header(&headers, header::STRICT_TRANSPORT_SECURITY),
+            Some("max-age=31536000; includeSubDomains")
+        );
+    }
+}
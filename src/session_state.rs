@@ -0,0 +1,54 @@
+use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use std::future::{ready, Ready};
+use uuid::Uuid;
+
+// A typed wrapper around `Session`, so that handlers can't get the keys or their types wrong.
+pub struct TypedSession(Session);
+
+impl TypedSession {
+    const USER_ID_KEY: &'static str = "user_id";
+    // Set once the password has been checked, while we wait for the second factor
+    const PENDING_USER_ID_KEY: &'static str = "pending_second_factor_user_id";
+
+    pub fn renew(&self) {
+        self.0.renew();
+    }
+
+    // Forget whoever was logged in before
+    pub fn clear(&self) {
+        self.0.clear();
+    }
+
+    pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::USER_ID_KEY, user_id)
+    }
+
+    pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::USER_ID_KEY)
+    }
+
+    pub fn insert_pending_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::PENDING_USER_ID_KEY)
+    }
+
+    pub fn remove_pending_user_id(&self) {
+        self.0.remove(Self::PENDING_USER_ID_KEY);
+    }
+}
+
+impl FromRequest for TypedSession {
+    // We return the same error returned by the `FromRequest` implementation for `Session`.
+    type Error = <Session as FromRequest>::Error;
+    // `Session` is extracted synchronously, so there is nothing to await.
+    type Future = Ready<Result<TypedSession, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(TypedSession(req.get_session())))
+    }
+}
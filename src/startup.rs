@@ -1,25 +1,60 @@
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::audit_log::AuditLog;
+use crate::authentication::{bootstrap_admin, PasswordPolicy};
+use crate::configuration::{
+    ApplicationSettings, DatabaseSettings, HealthSettings, Settings, TrackingSettings,
+    TwoFactorSettings,
+};
+use crate::issue_delivery_worker::{run_worker_until_stopped, Shutdown};
+use crate::login_throttle::LoginThrottle;
+use crate::metrics::RequestMetrics;
+use crate::password_reset::PasswordResetThrottle;
+use crate::request_id::RequestIds;
+use crate::security_headers::SecurityHeaders;
+use crate::subscription_guard::SubscriptionGuard;
+use crate::telemetry::RequestSpanBuilder;
 
+use actix_session::storage::CookieSessionStore;
+use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
 use actix_web::web::Data;
 use actix_web::{dev::Server, web, App, HttpServer};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use hmac::{Hmac, Mac};
 use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use std::time::Duration;
 use tracing_actix_web::TracingLogger;
 
 use crate::email_client::EmailClient;
 use crate::routes::{
-    confirm, health_check, home, login, login_form, publish_newsletter, subscribe,
+    add_user, archive, archived_issue, atom_feed, change_password, change_password_form,
+    change_subscriber_status, change_user_disabled, change_user_email, change_user_role, confirm,
+    create_api_token, disable_totp, enable_totp, erase_data_subject, erase_subscriber,
+    export_data_subject, export_subscribers_file, forgot_password, forgot_password_form,
+    health_check, home, import_subscribers_csv, list_api_tokens, list_subscribers, list_users,
+    liveness, login, login_form, login_totp, login_totp_form, newsletter_issue_stats,
+    prometheus_metrics, publish_newsletter, query_audit_log, readiness, reset_password,
+    reset_password_form, revoke_api_token, rss_feed, subscribe, subscriber_data,
+    subscriber_details, totp_form, track_click, track_open,
 };
 
 // a new type to hold the newly built Actix server and it's port
 pub struct Application {
     port: u16,
     server: Server,
+    // Only when `/metrics` is served on a port of its own
+    metrics_port: Option<u16>,
+    metrics_server: Option<Server>,
+    // Sends the newsletter issues that have been published, see `issue_delivery_worker`
+    delivery_worker: BoxFuture<'static, ()>,
+    shutdown: Shutdown,
+    shutdown_timeout: Duration,
 }
 
 // We wrap our hmac secret in an HmacSecret type, so we can inject it into our application for re-use.
@@ -27,28 +62,78 @@ pub struct Application {
 #[derive(Clone, Debug)]
 pub struct HmacSecret(pub Secret<String>);
 
+impl HmacSecret {
+    /// An HMAC keyed with the secret, which has already taken in `domain`.
+    /// Every use of the secret signs under a domain of its own,
+    /// so that what is signed for one use can't be passed off for another.
+    pub fn mac(&self, domain: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.0.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(domain);
+        mac
+    }
+}
+
 pub struct ApplicationBaseUrl(pub String);
 
+// What the authentication code needs, besides the database.
+pub struct Authentication {
+    pub two_factor: TwoFactorSettings,
+    pub login_throttle: LoginThrottle,
+    pub password_policy: PasswordPolicy,
+}
+
+// What protects the application from browsers being tricked and from bots.
+pub struct Protection {
+    pub subscription_guard: SubscriptionGuard,
+    pub password_reset_throttle: PasswordResetThrottle,
+    pub security_headers: SecurityHeaders,
+}
+
+// What the operators of the application get, besides the logs.
+pub struct Operations {
+    pub health: HealthSettings,
+    // `false` if `/metrics` is served on a port of its own
+    pub serve_metrics: bool,
+    pub audit_log: AuditLog,
+    // What is recorded about the newsletter emails, see `tracking`
+    pub tracking: TrackingSettings,
+}
+
+// 10 MiB
+const IMPORT_PAYLOAD_LIMIT: usize = 10 * 1024 * 1024;
+
 impl Application {
     // the build function is now a constructor for the Application type
-    pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         // get a connection pool for multiple connections
         let connection = get_connection_pool(&configuration.database);
+        // a fresh deployment has no users: create one, so that somebody can log in
+        let password_policy = PasswordPolicy::new(&configuration.password_hashing)?;
+        bootstrap_admin(
+            &configuration.bootstrap_admin,
+            &password_policy,
+            &connection,
+        )
+        .await?;
 
         // build an email client using configuration
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender address");
-
-        let timeout = configuration.email_client.timeout();
-
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            configuration.email_client.authorization_token,
-            sender_email,
-            timeout,
-        );
+        let email_client = configuration.email_client.clone().client();
+
+        let shutdown = Shutdown::new();
+        let shutdown_timeout = configuration.application.shutdown_timeout();
+        // A pool of its own: connections opened by the HTTP workers stop working along with them,
+        // while the delivery worker outlives them on shutdown
+        let delivery_worker = run_worker_until_stopped(
+            get_connection_pool(&configuration.database),
+            configuration.email_client.client(),
+            configuration.delivery,
+            configuration.application.base_url.clone(),
+            configuration.tracking.clone(),
+            HmacSecret(configuration.application.hmac_secret.clone()),
+            shutdown.clone(),
+        )
+        .boxed();
 
         let address = format!(
             "{}:{}",
@@ -59,25 +144,119 @@ impl Application {
         let listener = TcpListener::bind(address)?;
 
         let port = listener.local_addr().unwrap().port();
+
+        // `/metrics` is left out of the application if it has a port of its own
+        let (metrics_port, metrics_server) = match configuration.metrics.port {
+            Some(metrics_port) => {
+                let address = format!("{}:{}", configuration.application.host, metrics_port);
+                let listener = TcpListener::bind(address)?;
+                let metrics_port = listener.local_addr().unwrap().port();
+                let server = run_metrics(listener, connection.clone())?;
+                (Some(metrics_port), Some(server))
+            }
+            None => (None, None),
+        };
+
+        let trusted_proxies = configuration.application.trusted_proxies;
+        let audit_log = AuditLog::new(configuration.audit_log, trusted_proxies, connection.clone());
         let server = run(
             listener,
             connection,
             email_client,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
+            configuration.application,
+            Authentication {
+                two_factor: configuration.two_factor,
+                login_throttle: LoginThrottle::new(configuration.login_throttle, trusted_proxies),
+                password_policy,
+            },
+            Protection {
+                subscription_guard: SubscriptionGuard::new(
+                    configuration.subscription_protection,
+                    trusted_proxies,
+                ),
+                password_reset_throttle: PasswordResetThrottle::new(
+                    configuration.password_reset,
+                    trusted_proxies,
+                ),
+                security_headers: SecurityHeaders::new(configuration.security_headers),
+            },
+            Operations {
+                health: configuration.health,
+                serve_metrics: metrics_server.is_none(),
+                audit_log,
+                tracking: configuration.tracking,
+            },
         )?;
 
         // we "save" the bound port in one of Application's fields
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            metrics_port,
+            metrics_server,
+            delivery_worker,
+            shutdown,
+            shutdown_timeout,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
+
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
+    // Stops the application, e.g. on SIGTERM, see `shutdown_on_signal`.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
     // A more expressive name that makes it clear that
     // this function only returns when the application is stopped.
+    // Once shutdown is triggered, new connections are refused while the requests in progress
+    // and the email being delivered get `shutdown_timeout` to finish. Deliveries still queued
+    // stay in the database, for the next start.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let shutdown = self.shutdown;
+        let server_handle = self.server.handle();
+        let metrics_handle = self.metrics_server.as_ref().map(Server::handle);
+        tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown.triggered().await;
+                tracing::info!("Shutting down.");
+                let stop_metrics = async {
+                    if let Some(metrics_handle) = metrics_handle {
+                        metrics_handle.stop(true).await;
+                    }
+                };
+                tokio::join!(server_handle.stop(true), stop_metrics);
+            }
+        });
+
+        let metrics_server = async {
+            match self.metrics_server {
+                Some(metrics_server) => metrics_server.await,
+                None => Ok(()),
+            }
+        };
+        let delivery_worker = self.delivery_worker.map(Ok);
+        let running =
+            async { tokio::try_join!(self.server, metrics_server, delivery_worker).map(|_| ()) };
+        let deadline = async {
+            shutdown.triggered().await;
+            tokio::time::sleep(self.shutdown_timeout).await;
+        };
+        tokio::select! {
+            result = running => result,
+            _ = deadline => {
+                // The email being sent, if any, wasn't removed from the queue: it will be sent again
+                tracing::warn!("Shutdown timed out, queued deliveries will resume on the next start.");
+                Ok(())
+            }
+        }
     }
 }
 
@@ -90,17 +269,32 @@ pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
+    application: ApplicationSettings,
+    authentication: Authentication,
+    protection: Protection,
+    operations: Operations,
 ) -> Result<Server, std::io::Error> {
     // Instead of getting a raw copy of a PgConnection, will get a (Arc) pointer to one
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let base_url = web::Data::new(ApplicationBaseUrl(application.base_url));
+    let hmac_secret = application.hmac_secret;
+    let two_factor = web::Data::new(authentication.two_factor);
+    // Shared by all workers, so that failures are counted once per instance
+    let login_throttle = web::Data::new(authentication.login_throttle);
+    let password_policy = web::Data::new(authentication.password_policy);
+    let subscription_guard = web::Data::new(protection.subscription_guard);
+    let password_reset_throttle = web::Data::new(protection.password_reset_throttle);
+    let security_headers = protection.security_headers;
+    let health = web::Data::new(operations.health);
+    let serve_metrics = operations.serve_metrics;
+    let audit_log = web::Data::new(operations.audit_log);
+    let tracking = web::Data::new(operations.tracking);
+    let shutdown_timeout = application.shutdown_timeout_seconds;
 
     // enforce using signed cookies only
-    let message_store =
-        CookieMessageStore::builder(Key::from(hmac_secret.expose_secret().as_bytes())).build();
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
 
     /*
@@ -110,30 +304,164 @@ pub fn run(
      Data<T> is an example of such a sharable object.
      */
     let server = HttpServer::new(move || {
-        App::new()
+        let app = App::new()
             // Middlewares are added using the `wrap` method on `App`
             .wrap(message_framework.clone())
-            .wrap(TracingLogger::default())
+            // The session only holds the id of the logged-in user: a (private) cookie is enough
+            .wrap(SessionMiddleware::new(
+                CookieSessionStore::default(),
+                secret_key.clone(),
+            ))
+            .wrap(TracingLogger::<RequestSpanBuilder>::new())
+            .wrap(RequestMetrics)
+            .wrap(RequestIds)
+            // The last `wrap` is the outermost middleware:
+            // the responses of all the others get the headers too
+            .wrap(security_headers.clone())
             .route("/", web::get().to(home))
+            .route("/archive", web::get().to(archive))
+            .route("/archive/rss.xml", web::get().to(rss_feed))
+            .route("/archive/atom.xml", web::get().to(atom_feed))
+            .route("/archive/{slug}", web::get().to(archived_issue))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
+            .route("/login/totp", web::get().to(login_totp_form))
+            .route("/login/totp", web::post().to(login_totp))
+            .route("/password/forgot", web::get().to(forgot_password_form))
+            .route("/password/forgot", web::post().to(forgot_password))
+            .route("/password/reset", web::get().to(reset_password_form))
+            .route("/password/reset", web::post().to(reset_password))
             .route("/health_check", web::get().to(health_check))
+            .route("/health/live", web::get().to(liveness))
+            .route("/health/ready", web::get().to(readiness))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/subscriptions/data", web::get().to(subscriber_data))
+            .route("/subscriptions/erase", web::post().to(erase_subscriber))
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/t/o/{token}", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
+            .service(
+                web::scope("/admin")
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/totp", web::get().to(totp_form))
+                    .route("/totp/enable", web::post().to(enable_totp))
+                    .route("/totp/disable", web::post().to(disable_totp))
+                    .route("/subscribers", web::get().to(list_subscribers))
+                    .route(
+                        "/subscribers/export",
+                        web::get().to(export_subscribers_file),
+                    )
+                    .service(
+                        web::resource("/subscribers/import")
+                            // Lists exported from other tools are larger than the default payload limit
+                            .app_data(web::PayloadConfig::new(IMPORT_PAYLOAD_LIMIT))
+                            .route(web::post().to(import_subscribers_csv)),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(subscriber_details),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/status",
+                        web::put().to(change_subscriber_status),
+                    )
+                    .route("/audit-log", web::get().to(query_audit_log))
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/stats",
+                        web::get().to(newsletter_issue_stats),
+                    )
+                    .route("/gdpr/export", web::get().to(export_data_subject))
+                    .route("/gdpr/erase", web::post().to(erase_data_subject))
+                    .route("/tokens", web::get().to(list_api_tokens))
+                    .route("/tokens", web::post().to(create_api_token))
+                    .route("/tokens/{token_id}", web::delete().to(revoke_api_token))
+                    .route("/users", web::get().to(list_users))
+                    .route("/users", web::post().to(add_user))
+                    .route("/users/{user_id}/role", web::put().to(change_user_role))
+                    .route(
+                        "/users/{user_id}/disabled",
+                        web::put().to(change_user_disabled),
+                    )
+                    .route("/users/{user_id}/email", web::put().to(change_user_email)),
+            )
             // Register the connection as part of the application state,
             // and get a pointer copy and attach it to the application state
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
-            .app_data(Data::new(HmacSecret(hmac_secret.clone())))
+            .app_data(two_factor.clone())
+            .app_data(login_throttle.clone())
+            .app_data(password_policy.clone())
+            .app_data(subscription_guard.clone())
+            .app_data(password_reset_throttle.clone())
+            .app_data(health.clone())
+            .app_data(audit_log.clone())
+            .app_data(tracking.clone())
+            .app_data(Data::new(HmacSecret(hmac_secret.clone())));
+        if serve_metrics {
+            app.route("/metrics", web::get().to(prometheus_metrics))
+        } else {
+            app
+        }
     })
+    // Stopped by `Application::run_until_stopped`, along with the delivery worker
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
     .listen(listener)?
     .run();
     //no more .await
     Ok(server)
 }
 
+// Serves nothing but `/metrics`, for a port that is only reachable by whatever scrapes it.
+pub fn run_metrics(listener: TcpListener, db_pool: PgPool) -> Result<Server, std::io::Error> {
+    let db_pool = web::Data::new(db_pool);
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(TracingLogger::default())
+            .route("/metrics", web::get().to(prometheus_metrics))
+            .app_data(db_pool.clone())
+    })
+    .workers(1)
+    .disable_signals()
+    .listen(listener)?
+    .run();
+    Ok(server)
+}
+
+// Triggers `shutdown` on SIGTERM (sent by orchestrators) or Ctrl-C.
+pub async fn shutdown_on_signal(shutdown: Shutdown) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error.cause_chain = ?e, "Failed to listen for Ctrl-C.");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, "Failed to listen for SIGTERM.");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+        // Triggered by other means
+        _ = shutdown.triggered() => return,
+    }
+    shutdown.trigger();
+}
+
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
         .acquire_timeout(std::time::Duration::from_secs(2))
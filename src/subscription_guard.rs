@@ -0,0 +1,336 @@
+use crate::configuration::SubscriptionProtectionSettings;
+use crate::domain::SubscriberEmail;
+use crate::startup::HmacSecret;
+use crate::utils::client_ip;
+use actix_web::HttpRequest;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Limits are counted over fixed windows of this length.
+const WINDOW: Duration = Duration::from_secs(60 * 60);
+// Above this many tracked keys, finished windows are dropped on the next subscription.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Ip(IpAddr),
+    EmailDomain(String),
+}
+
+struct Window {
+    started_at: Instant,
+    count: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The answer must look like a success, so that the bot learns nothing from it.
+    SuspectedBot(&'static str),
+    /// The form was loaded too long ago: a human can reload it.
+    Expired,
+    TooManyRequests,
+}
+
+/// Keeps bots from using the subscription form to send confirmation emails to strangers.
+/// The form has a honeypot field, hidden from humans, that bots fill in,
+/// and a signed timestamp that tells us how long it took to fill in the form.
+/// On top of that, subscriptions are limited per IP address and per email domain.
+/// The counters live in memory: every instance of the application keeps its own.
+pub struct SubscriptionGuard {
+    settings: SubscriptionProtectionSettings,
+    // See `utils::client_ip`
+    trusted_proxies: usize,
+    windows: Mutex<HashMap<Key, Window>>,
+}
+
+impl SubscriptionGuard {
+    pub const HONEYPOT_FIELD: &'static str = "website";
+    pub const ISSUED_AT_FIELD: &'static str = "form_issued_at";
+
+    pub fn new(settings: SubscriptionProtectionSettings, trusted_proxies: usize) -> Self {
+        Self {
+            settings,
+            trusted_proxies,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        client_ip(
+            request,
+            self.settings.trust_forwarded_headers,
+            self.trusted_proxies,
+        )
+    }
+
+    /// The fields to add to the subscription form.
+    /// The honeypot is hidden by the `honeypot` class of the page's stylesheet:
+    /// bots know to leave fields with a `hidden` attribute alone.
+    pub fn form_fields(&self, secret: &HmacSecret) -> String {
+        let issued_at = Utc::now().timestamp();
+        format!(
+            r#"<div class="honeypot" aria-hidden="true">
+            <label>Leave this field empty
+                <input type="text" name="{}" tabindex="-1" autocomplete="off">
+            </label>
+        </div>
+        <input type="hidden" name="{}" value="{}.{}">"#,
+            Self::HONEYPOT_FIELD,
+            Self::ISSUED_AT_FIELD,
+            issued_at,
+            sign(secret, issued_at)
+        )
+    }
+
+    /// Whether the form was filled in by a human, judging by the fields of `form_fields`.
+    pub fn check_form(
+        &self,
+        secret: &HmacSecret,
+        honeypot: &str,
+        issued_at: Option<&str>,
+    ) -> Result<(), Rejection> {
+        self.check_form_at(secret, honeypot, issued_at, Utc::now().timestamp())
+    }
+
+    /// Counts a subscription against its IP address and email domain,
+    /// unless one of them is already over its limit.
+    pub fn check_rate(&self, ip: Option<IpAddr>, email: &SubscriberEmail) -> Result<(), Rejection> {
+        self.check_rate_at(ip, email, Instant::now())
+    }
+
+    fn check_form_at(
+        &self,
+        secret: &HmacSecret,
+        honeypot: &str,
+        issued_at: Option<&str>,
+        now: i64,
+    ) -> Result<(), Rejection> {
+        if !honeypot.is_empty() {
+            return Err(Rejection::SuspectedBot("The honeypot field was filled in."));
+        }
+        let issued_at = match issued_at.and_then(|value| verify(secret, value)) {
+            Some(issued_at) => issued_at,
+            None => {
+                return Err(Rejection::SuspectedBot(
+                    "The form was not loaded from our site.",
+                ))
+            }
+        };
+        let elapsed = now - issued_at;
+        if elapsed < self.settings.min_fill_seconds as i64 {
+            return Err(Rejection::SuspectedBot(
+                "The form was filled in too quickly.",
+            ));
+        }
+        if elapsed > self.settings.max_form_age_seconds as i64 {
+            return Err(Rejection::Expired);
+        }
+        Ok(())
+    }
+
+    fn check_rate_at(
+        &self,
+        ip: Option<IpAddr>,
+        email: &SubscriberEmail,
+        now: Instant,
+    ) -> Result<(), Rejection> {
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() > PRUNE_THRESHOLD {
+            windows.retain(|_, w| now.duration_since(w.started_at) < WINDOW);
+        }
+        let keys: Vec<Key> = ip
+            .map(Key::Ip)
+            .into_iter()
+            .chain(email_domain(email).map(Key::EmailDomain))
+            .collect();
+        // Nothing is counted unless every key is under its limit
+        for key in &keys {
+            if let Some(window) = windows.get(key) {
+                let current = now.duration_since(window.started_at) < WINDOW;
+                if current && window.count >= self.limit(key) {
+                    return Err(Rejection::TooManyRequests);
+                }
+            }
+        }
+        for key in keys {
+            let window = windows.entry(key).or_insert(Window {
+                started_at: now,
+                count: 0,
+            });
+            if now.duration_since(window.started_at) >= WINDOW {
+                window.started_at = now;
+                window.count = 0;
+            }
+            window.count += 1;
+        }
+        Ok(())
+    }
+
+    fn limit(&self, key: &Key) -> u32 {
+        match key {
+            Key::Ip(_) => self.settings.per_ip_per_hour,
+            Key::EmailDomain(_) => self.settings.per_email_domain_per_hour,
+        }
+    }
+}
+
+fn email_domain(email: &SubscriberEmail) -> Option<String> {
+    email
+        .as_ref()
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase())
+}
+
+fn mac(secret: &HmacSecret, issued_at: i64) -> Hmac<Sha256> {
+    let mut mac = secret.mac(b"subscription-form:");
+    mac.update(issued_at.to_string().as_bytes());
+    mac
+}
+
+fn sign(secret: &HmacSecret, issued_at: i64) -> String {
+    hex::encode(mac(secret, issued_at).finalize().into_bytes())
+}
+
+// Returns the timestamp if the signature matches.
+fn verify(secret: &HmacSecret, value: &str) -> Option<i64> {
+    let (issued_at, signature) = value.split_once('.')?;
+    let issued_at = issued_at.parse().ok()?;
+    let signature = hex::decode(signature).ok()?;
+    mac(secret, issued_at)
+        .verify_slice(&signature)
+        .ok()
+        .map(|_| issued_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::Secret;
+
+    fn guard() -> SubscriptionGuard {
+        SubscriptionGuard::new(
+            SubscriptionProtectionSettings {
+                min_fill_seconds: 3,
+                max_form_age_seconds: 3600,
+                per_ip_per_hour: 3,
+                per_email_domain_per_hour: 5,
+                trust_forwarded_headers: false,
+            },
+            0,
+        )
+    }
+
+    fn secret() -> HmacSecret {
+        HmacSecret(Secret::new("secret".to_owned()))
+    }
+
+    fn issued_at(at: i64) -> String {
+        format!("{}.{}", at, sign(&secret(), at))
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_owned()).unwrap()
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn a_form_filled_in_at_human_speed_is_accepted() {
+        let value = issued_at(1000);
+        assert_eq!(
+            guard().check_form_at(&secret(), "", Some(&value), 1010),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn a_filled_in_honeypot_gives_a_bot_away() {
+        let value = issued_at(1000);
+        assert!(matches!(
+            guard().check_form_at(&secret(), "https://spam.example", Some(&value), 1010),
+            Err(Rejection::SuspectedBot(_))
+        ));
+    }
+
+    #[test]
+    fn a_form_filled_in_too_quickly_gives_a_bot_away() {
+        let value = issued_at(1000);
+        assert!(matches!(
+            guard().check_form_at(&secret(), "", Some(&value), 1002),
+            Err(Rejection::SuspectedBot(_))
+        ));
+    }
+
+    #[test]
+    fn a_missing_or_forged_timestamp_gives_a_bot_away() {
+        let forged = format!("900.{}", sign(&secret(), 1000));
+        for value in [None, Some("900"), Some("900.abc"), Some(forged.as_str())] {
+            assert!(matches!(
+                guard().check_form_at(&secret(), "", value, 1010),
+                Err(Rejection::SuspectedBot(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn an_old_form_has_expired() {
+        let value = issued_at(1000);
+        assert_eq!(
+            guard().check_form_at(&secret(), "", Some(&value), 1000 + 3601),
+            Err(Rejection::Expired)
+        );
+    }
+
+    #[test]
+    fn an_ip_address_is_limited_whatever_the_domain() {
+        let guard = guard();
+        let now = Instant::now();
+        for domain in ["a.com", "b.com", "c.com"] {
+            let email = email(&format!("ursula@{}", domain));
+            assert_eq!(guard.check_rate_at(ip("192.0.2.1"), &email, now), Ok(()));
+        }
+        let email = email("ursula@d.com");
+        assert_eq!(
+            guard.check_rate_at(ip("192.0.2.1"), &email, now),
+            Err(Rejection::TooManyRequests)
+        );
+        assert_eq!(guard.check_rate_at(ip("192.0.2.2"), &email, now), Ok(()));
+    }
+
+    #[test]
+    fn an_email_domain_is_limited_whatever_the_ip_address() {
+        let guard = guard();
+        let now = Instant::now();
+        for i in 0..5 {
+            let address = format!("192.0.2.{}", i);
+            let email = email(&format!("user{}@Example.com", i));
+            assert_eq!(guard.check_rate_at(ip(&address), &email, now), Ok(()));
+        }
+        assert_eq!(
+            guard.check_rate_at(ip("192.0.2.100"), &email("x@example.com"), now),
+            Err(Rejection::TooManyRequests)
+        );
+    }
+
+    #[test]
+    fn limits_reset_after_an_hour() {
+        let guard = guard();
+        let now = Instant::now();
+        let email = email("ursula@example.com");
+        for _ in 0..3 {
+            guard.check_rate_at(ip("192.0.2.1"), &email, now).unwrap();
+        }
+        assert!(guard.check_rate_at(ip("192.0.2.1"), &email, now).is_err());
+        assert_eq!(
+            guard.check_rate_at(ip("192.0.2.1"), &email, now + WINDOW),
+            Ok(())
+        );
+    }
+}
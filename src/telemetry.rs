@@ -1,6 +1,20 @@
-use tracing::{subscriber::set_global_default, Subscriber};
+use crate::configuration::OpenTelemetrySettings;
+use crate::request_id::RequestId;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap;
+use actix_web::HttpMessage;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, Tracer, TracerProvider};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{TraceContextExt, TraceError, TraceId};
+use opentelemetry::{global, runtime, KeyValue};
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use tracing::{subscriber::set_global_default, Span, Subscriber};
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
@@ -14,10 +28,13 @@ use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 /// We need to explicitly call out that the returned subscriber is
 /// `Send` and `Sync` to make it possible to pass it to `init_subscriber`
 /// later on.
+///
+/// With a `tracer`, spans are also handed over to OpenTelemetry (see `get_tracer_provider`).
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Option<Tracer>,
 ) -> impl Subscriber + Sync + Send
 where
     // This "weird" syntax is a higher-ranked trait bound (HRTB)
@@ -33,10 +50,13 @@ where
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
 
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let opentelemetry_layer =
+        tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
 
     // The `with` method is provided by `SubscriberExt`, an extension trait for `Subscriber` exposed by `tracing_subscriber`
     Registry::default()
         .with(env_filter)
+        .with(opentelemetry_layer)
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
@@ -47,4 +67,152 @@ where
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
+    // Incoming `traceparent` headers become the parents of our request spans (see `TracingLogger`),
+    // and outgoing requests carry ours (see `EmailClient`)
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+/// Exports spans to the OpenTelemetry collector of `settings`, in batches, over OTLP/HTTP.
+/// `None` if no collector is configured.
+///
+/// Must be called from within a Tokio runtime.
+/// The provider must be kept until the application stops: dropping it exports the spans left.
+pub fn get_tracer_provider(
+    name: &str,
+    settings: &OpenTelemetrySettings,
+) -> Result<Option<TracerProvider>, TraceError> {
+    let endpoint = match &settings.otlp_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+    let exporter = SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(endpoint)
+            .with_timeout(settings.timeout()),
+    )
+    .build_span_exporter()?;
+    let resource = Resource::new(vec![KeyValue::new("service.name", name.to_owned())]);
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_config(trace::config().with_resource(resource))
+        .build();
+    Ok(Some(provider))
+}
+
+/// The span of each request, as built by `TracingLogger`, with our `RequestId`
+/// rather than one of its own, so that logs can be found from the `X-Request-Id` of a response.
+pub struct RequestSpanBuilder;
+
+impl RootSpanBuilder for RequestSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        let http_route = request.match_pattern().unwrap_or_else(|| "default".into());
+        let connection_info = request.connection_info();
+        let span = tracing::info_span!(
+            "HTTP request",
+            http.method = %request.method(),
+            http.route = %http_route,
+            http.flavor = ?request.version(),
+            http.scheme = %connection_info.scheme(),
+            http.host = %connection_info.host(),
+            http.client_ip = %connection_info.realip_remote_addr().unwrap_or(""),
+            http.user_agent = %request
+                .headers()
+                .get("User-Agent")
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or(""),
+            http.target = %request.uri().path_and_query().map(|p| p.as_str()).unwrap_or(""),
+            http.status_code = tracing::field::Empty,
+            otel.name = %format!("HTTP {} {}", request.method(), http_route),
+            otel.kind = "server",
+            otel.status_code = tracing::field::Empty,
+            trace_id = tracing::field::Empty,
+            request_id = %request_id,
+            exception.message = tracing::field::Empty,
+            exception.details = tracing::field::Empty,
+        );
+        drop(connection_info);
+
+        // The parent is the span of the caller, if it sent a `traceparent` header
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&RequestHeaders(request.headers()))
+        });
+        span.set_parent(parent);
+        // Logs can then be found from the trace, whether it started here or with the caller
+        let trace_id = span.context().span().span_context().trace_id();
+        if trace_id != TraceId::INVALID {
+            span.record("trace_id", tracing::field::display(trace_id));
+        }
+        span
+    }
+
+    fn on_request_end<B>(span: Span, outcome: &Result<ServiceResponse<B>, actix_web::Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+struct RequestHeaders<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for RequestHeaders<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use opentelemetry::trace::TracerProvider as _;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn request_spans_record_the_trace_id_of_the_caller() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = TracerProvider::builder().build();
+        let logs = Logs::default();
+        let sink = logs.clone();
+        let subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            move || sink.clone(),
+            Some(provider.tracer("test")),
+        );
+        let request = TestRequest::default()
+            .insert_header((
+                "traceparent",
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            ))
+            .to_srv_request();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = RequestSpanBuilder::on_request_start(&request);
+            span.in_scope(|| tracing::info!("Handling the request"));
+        });
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains(r#""trace_id":"0af7651916cd43dd8448eb211c80319c""#));
+    }
 }
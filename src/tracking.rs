@@ -0,0 +1,552 @@
+use crate::startup::HmacSecret;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::ops::Range;
+use uuid::Uuid;
+
+/*
+What subscribers do with the newsletter emails, when `TrackingSettings` allow it.
+Every email sent gets a random token, recorded in `tracked_deliveries` along with its recipient:
+- an invisible image loads `/t/o/{token}`, which records that the email has been opened;
+- its links go through `/t/c/{token}`, which records the click and redirects to the link.
+Redirect URLs are signed with `HmacSecret`: we only ever redirect to links we have sent,
+so that our domain can't be used to disguise somebody else's.
+*/
+
+// How many links the statistics of an issue list.
+const TOP_LINKS: i64 = 10;
+
+/// A transparent 1x1 GIF, the tracking pixel.
+pub const PIXEL_GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Identifies one newsletter email in the tracking URLs it contains.
+pub fn generate_tracking_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
+pub fn open_pixel_url(base_url: &str, token: &str) -> String {
+    format!("{}/t/o/{}", base_url, token)
+}
+
+/// Adds an image loading `pixel_url` at the end of `html`, inside `<body>` if there is one.
+pub fn add_open_pixel(html: &str, pixel_url: &str) -> String {
+    let pixel = format!(
+        r#"<img src="{}" width="1" height="1" alt="" style="border:0">"#,
+        htmlescape::encode_minimal(pixel_url)
+    );
+    // Lowercasing ASCII leaves the byte offsets as they are
+    let at = html
+        .to_ascii_lowercase()
+        .rfind("</body>")
+        .unwrap_or(html.len());
+    let mut html = html.to_owned();
+    html.insert_str(at, &pixel);
+    html
+}
+
+pub fn click_url(base_url: &str, secret: &HmacSecret, token: &str, url: &str) -> String {
+    format!(
+        "{}/t/c/{}?url={}&sig={}",
+        base_url,
+        token,
+        urlencoding::encode(url),
+        hex::encode(click_mac(secret, token, url).finalize().into_bytes())
+    )
+}
+
+/// Whether `signature` is the one `click_url` computed for `token` and `url`.
+pub fn verify_click(secret: &HmacSecret, token: &str, url: &str, signature: &str) -> bool {
+    let signature = match hex::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    // In constant time
+    click_mac(secret, token, url)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+fn click_mac(secret: &HmacSecret, token: &str, url: &str) -> Hmac<Sha256> {
+    let mut mac = secret.mac(b"click:");
+    // Tokens have no newlines: the token and the URL can't be shifted into one another
+    mac.update(token.as_bytes());
+    mac.update(b"\n");
+    mac.update(url.as_bytes());
+    mac
+}
+
+fn is_web_link(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Replaces the web links of the `href` attributes of the `<a>` and `<area>` tags of `html`
+/// with `track(link)`. Anchors, `mailto:` links and the like are left alone.
+pub fn track_html_links(html: &str, track: impl Fn(&str) -> String) -> String {
+    let mut tracked = String::with_capacity(html.len());
+    let mut rest = 0;
+    let mut position = 0;
+    while let Some(found) = html[position..].find('<') {
+        let name_start = position + found + 1;
+        position = name_start;
+        if html[name_start..].starts_with("!--") {
+            match html[name_start..].find("-->") {
+                Some(length) => position = name_start + length + "-->".len(),
+                None => break,
+            }
+            continue;
+        }
+        // Anything else is a `<` of the text, which some emails don't bother to escape
+        if !html[name_start..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            continue;
+        }
+        let name_end = html[name_start..]
+            .find(|c: char| c.is_ascii_whitespace() || matches!(c, '>' | '/'))
+            .map_or(html.len(), |length| name_start + length);
+        let (attributes, tag_end) = read_attributes(html, name_end);
+        position = tag_end;
+        let name = &html[name_start..name_end];
+        if !name.eq_ignore_ascii_case("a") && !name.eq_ignore_ascii_case("area") {
+            continue;
+        }
+        for attribute in attributes {
+            let value = match attribute.value {
+                Some(value) if html[attribute.name].eq_ignore_ascii_case("href") => value,
+                _ => continue,
+            };
+            let link = match htmlescape::decode_html(&html[value.clone()]) {
+                Ok(link) if is_web_link(&link) => link,
+                _ => continue,
+            };
+            tracked.push_str(&html[rest..value.start]);
+            tracked.push_str(&htmlescape::encode_minimal(&track(&link)));
+            rest = value.end;
+        }
+    }
+    tracked.push_str(&html[rest..]);
+    tracked
+}
+
+// An attribute of a tag, as byte ranges of the document.
+struct Attribute {
+    name: Range<usize>,
+    // Within the quotes. Unquoted values are too rare in emails to bother
+    value: Option<Range<usize>>,
+}
+
+// Reads the attributes of a tag from `position`, just after its name, up to the `>` closing it.
+// Returns them with the position after the tag.
+// Only the attributes whose name comes after whitespace are kept: others are not attributes
+// to every HTML parser.
+fn read_attributes(html: &str, mut position: usize) -> (Vec<Attribute>, usize) {
+    let bytes = html.as_bytes();
+    let skip_whitespace = |from: usize| {
+        bytes[from..]
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .map_or(bytes.len(), |length| from + length)
+    };
+    let mut attributes = Vec::new();
+    loop {
+        let name_start = skip_whitespace(position);
+        match bytes.get(name_start) {
+            None => return (attributes, bytes.len()),
+            Some(b'>') => return (attributes, name_start + 1),
+            Some(b'/' | b'=') => {
+                position = name_start + 1;
+                continue;
+            }
+            Some(_) => {}
+        }
+        let after_whitespace = bytes[name_start - 1].is_ascii_whitespace();
+        let name_end = bytes[name_start..]
+            .iter()
+            .position(|b| b.is_ascii_whitespace() || matches!(b, b'=' | b'>' | b'/'))
+            .map_or(bytes.len(), |length| name_start + length);
+        position = name_end;
+        let mut value = None;
+        let equals = skip_whitespace(name_end);
+        if bytes.get(equals) == Some(&b'=') {
+            let value_start = skip_whitespace(equals + 1);
+            position = match bytes.get(value_start) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    match bytes[value_start + 1..].iter().position(|&b| b == quote) {
+                        Some(length) => {
+                            let value_end = value_start + 1 + length;
+                            value = Some(value_start + 1..value_end);
+                            value_end + 1
+                        }
+                        None => bytes.len(),
+                    }
+                }
+                _ => bytes[value_start..]
+                    .iter()
+                    .position(|b| b.is_ascii_whitespace() || *b == b'>')
+                    .map_or(bytes.len(), |length| value_start + length),
+            };
+        }
+        if after_whitespace {
+            attributes.push(Attribute {
+                name: name_start..name_end,
+                value,
+            });
+        }
+    }
+}
+
+/// Replaces the web links of `text` with `track(link)`.
+/// Punctuation at the end of a link is taken to be part of the sentence around it.
+pub fn track_text_links(text: &str, track: impl Fn(&str) -> String) -> String {
+    let mut tracked = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest
+        .find("http://")
+        .into_iter()
+        .chain(rest.find("https://"))
+        .min()
+    {
+        let length = rest[start..]
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"'))
+            .unwrap_or(rest.len() - start);
+        let link =
+            rest[start..start + length].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']']);
+        tracked.push_str(&rest[..start]);
+        // A scheme alone is no link
+        if link.ends_with("://") {
+            tracked.push_str(link);
+        } else {
+            tracked.push_str(&track(link));
+        }
+        rest = &rest[start + link.len()..];
+    }
+    tracked.push_str(rest);
+    tracked
+}
+
+/// Records that the newsletter email identified by `token` has been sent to `email`.
+/// Nothing is recorded if `email` no longer belongs to a subscriber.
+#[tracing::instrument(skip_all)]
+pub async fn record_delivery(
+    pool: &PgPool,
+    token: &str,
+    newsletter_issue_id: Uuid,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO tracked_deliveries (token, newsletter_issue_id, subscriber_id, delivered_at)
+        SELECT $1, $2, id, now()
+        FROM subscriptions
+        WHERE email = $3 AND erased_at IS NULL
+        "#,
+        token,
+        newsletter_issue_id,
+        email,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record a delivery.")?;
+    Ok(())
+}
+
+/// Records that the tracking pixel of the email identified by `token` has been loaded.
+/// Subscribers who had their data erased are no longer tracked.
+#[tracing::instrument(skip_all)]
+pub async fn record_open(pool: &PgPool, token: &str) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE tracked_deliveries
+        SET first_opened_at = COALESCE(first_opened_at, now()),
+            last_opened_at = now(),
+            open_count = open_count + 1
+        WHERE token = $1
+            AND subscriber_id IN (SELECT id FROM subscriptions WHERE erased_at IS NULL)
+        "#,
+        token,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record an open.")?;
+    Ok(())
+}
+
+/// Records that `url` has been followed from the newsletter email identified by `token`.
+/// Subscribers who had their data erased are no longer tracked.
+#[tracing::instrument(skip(pool, token))]
+pub async fn record_click(pool: &PgPool, token: &str, url: &str) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO link_clicks (delivery_token, url, first_clicked_at, last_clicked_at)
+        SELECT token, $2, now(), now()
+        FROM tracked_deliveries
+        WHERE token = $1
+            AND subscriber_id IN (SELECT id FROM subscriptions WHERE erased_at IS NULL)
+        ON CONFLICT (delivery_token, url) DO UPDATE
+        SET last_clicked_at = now(), click_count = link_clicks.click_count + 1
+        "#,
+        token,
+        url,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record a click.")?;
+    Ok(())
+}
+
+/// How a newsletter issue has been received, over the emails sent while tracking was enabled.
+#[derive(serde::Serialize)]
+pub struct IssueStatistics {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+    pub delivered: i64,
+    // Emails opened at least once
+    pub unique_opens: i64,
+    pub total_opens: i64,
+    // `unique_opens` out of `delivered`, between 0 and 1
+    pub open_rate: f64,
+    // Emails with at least one link followed
+    pub unique_clicks: i64,
+    pub total_clicks: i64,
+    // `unique_clicks` out of `delivered`, between 0 and 1
+    pub click_rate: f64,
+    // The most followed links first
+    pub top_links: Vec<LinkStatistics>,
+}
+
+#[derive(serde::Serialize)]
+pub struct LinkStatistics {
+    pub url: String,
+    // Emails it has been followed from
+    pub unique_clicks: i64,
+    pub total_clicks: i64,
+}
+
+/// `None` if the newsletter issue doesn't exist.
+#[tracing::instrument(skip(pool))]
+pub async fn get_issue_statistics(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueStatistics>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT i.title, i.published_at,
+            count(d.token) AS "delivered!",
+            count(d.first_opened_at) AS "unique_opens!",
+            COALESCE(sum(d.open_count), 0) AS "total_opens!"
+        FROM newsletter_issues i
+        LEFT JOIN tracked_deliveries d ON d.newsletter_issue_id = i.newsletter_issue_id
+        WHERE i.newsletter_issue_id = $1
+        GROUP BY i.newsletter_issue_id
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the statistics of a newsletter issue.")?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let clicks = sqlx::query!(
+        r#"
+        SELECT count(DISTINCT c.delivery_token) AS "unique_clicks!",
+            COALESCE(sum(c.click_count), 0) AS "total_clicks!"
+        FROM link_clicks c
+        JOIN tracked_deliveries d ON d.token = c.delivery_token
+        WHERE d.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the clicks of a newsletter issue.")?;
+    let top_links = sqlx::query_as!(
+        LinkStatistics,
+        r#"
+        SELECT c.url, count(*) AS "unique_clicks!", sum(c.click_count) AS "total_clicks!"
+        FROM link_clicks c
+        JOIN tracked_deliveries d ON d.token = c.delivery_token
+        WHERE d.newsletter_issue_id = $1
+        GROUP BY c.url
+        ORDER BY 2 DESC, 3 DESC, c.url
+        LIMIT $2
+        "#,
+        newsletter_issue_id,
+        TOP_LINKS,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the top links of a newsletter issue.")?;
+    Ok(Some(IssueStatistics {
+        newsletter_issue_id,
+        title: row.title,
+        published_at: row.published_at,
+        delivered: row.delivered,
+        unique_opens: row.unique_opens,
+        total_opens: row.total_opens,
+        open_rate: rate(row.unique_opens, row.delivered),
+        unique_clicks: clicks.unique_clicks,
+        total_clicks: clicks.total_clicks,
+        click_rate: rate(clicks.unique_clicks, row.delivered),
+        top_links,
+    }))
+}
+
+fn rate(count: i64, out_of: i64) -> f64 {
+    if out_of == 0 {
+        0.0
+    } else {
+        count as f64 / out_of as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_open_pixel, click_url, rate, track_html_links, track_text_links, verify_click,
+    };
+    use crate::startup::HmacSecret;
+    use secrecy::Secret;
+
+    fn track(link: &str) -> String {
+        format!("tracked({})", link)
+    }
+
+    #[test]
+    fn the_pixel_goes_at_the_end_of_the_body() {
+        let html = add_open_pixel("<html><body><p>Hi</p></BODY></html>", "https://x.com/t/o/a");
+        assert_eq!(
+            html,
+            r#"<html><body><p>Hi</p><img src="https://x.com/t/o/a" width="1" height="1" alt="" style="border:0"></BODY></html>"#
+        );
+    }
+
+    #[test]
+    fn fragments_get_the_pixel_at_the_end() {
+        let html = add_open_pixel("<p>Hi</p>", "https://x.com/t/o/a");
+        assert!(html.starts_with("<p>Hi</p><img "));
+    }
+
+    #[test]
+    fn nothing_delivered_means_nothing_opened() {
+        assert_eq!(rate(0, 0), 0.0);
+        assert_eq!(rate(1, 4), 0.25);
+    }
+
+    #[test]
+    fn web_links_of_html_are_tracked() {
+        let html =
+            r#"<a href="https://example.com/a?b=1&amp;c=2">A</a> <A HREF='http://x.org'>X</A>"#;
+        assert_eq!(
+            track_html_links(html, track),
+            r#"<a href="tracked(https://example.com/a?b=1&amp;c=2)">A</a> <A HREF='tracked(http://x.org)'>X</A>"#
+        );
+    }
+
+    #[test]
+    fn other_links_of_html_are_left_alone() {
+        let html = r##"<a href="#top">Top</a><a href="mailto:a@b.com">Mail</a><a href=https://x.org>X</a>"##;
+        assert_eq!(track_html_links(html, track), html);
+    }
+
+    #[test]
+    fn links_are_only_tracked_in_the_href_of_a_and_area_tags() {
+        let html = r#"<map><area shape="rect" href="https://x.org/area"></map><a
+            class="button" href = "https://x.org/a">A</a>"#;
+        assert_eq!(
+            track_html_links(html, track),
+            r#"<map><area shape="rect" href="tracked(https://x.org/area)"></map><a
+            class="button" href = "tracked(https://x.org/a)">A</a>"#
+        );
+    }
+
+    #[test]
+    fn data_href_attributes_are_left_alone() {
+        let html = r#"<a data-href="https://x.org/data" href="https://x.org/a">A</a>"#;
+        assert_eq!(
+            track_html_links(html, track),
+            r#"<a data-href="https://x.org/data" href="tracked(https://x.org/a)">A</a>"#
+        );
+    }
+
+    #[test]
+    fn link_tags_are_left_alone() {
+        let html = r#"<link rel="stylesheet" href="https://x.org/style.css">"#;
+        assert_eq!(track_html_links(html, track), html);
+    }
+
+    #[test]
+    fn base_tags_are_left_alone() {
+        let html = r#"<base href="https://x.org/">"#;
+        assert_eq!(track_html_links(html, track), html);
+    }
+
+    #[test]
+    fn href_in_the_text_is_left_alone() {
+        let html = r#"<p>Write href="https://x.org" in the <code>a</code> tag, if 1 < 2.</p>"#;
+        assert_eq!(track_html_links(html, track), html);
+    }
+
+    #[test]
+    fn href_in_the_values_of_other_attributes_is_left_alone() {
+        let html = r#"<a title='href="https://x.org/title"' href="https://x.org/a">A</a>"#;
+        assert_eq!(
+            track_html_links(html, track),
+            r#"<a title='href="https://x.org/title"' href="tracked(https://x.org/a)">A</a>"#
+        );
+    }
+
+    #[test]
+    fn web_links_of_text_are_tracked_without_the_punctuation_around_them() {
+        let text = "Read https://example.com/a?b=1. Or (http://x.org), or not.";
+        assert_eq!(
+            track_text_links(text, track),
+            "Read tracked(https://example.com/a?b=1). Or (tracked(http://x.org)), or not."
+        );
+    }
+
+    #[test]
+    fn click_urls_only_verify_for_the_link_and_token_they_were_computed_for() {
+        let secret = HmacSecret(Secret::new("secret".into()));
+        let url = click_url("https://x.org", &secret, "token", "https://example.com");
+        let signature = url.rsplit("sig=").next().unwrap();
+        assert!(verify_click(
+            &secret,
+            "token",
+            "https://example.com",
+            signature
+        ));
+        assert!(!verify_click(
+            &secret,
+            "token",
+            "https://evil.com",
+            signature
+        ));
+        assert!(!verify_click(
+            &secret,
+            "other",
+            "https://example.com",
+            signature
+        ));
+        assert!(!verify_click(
+            &secret,
+            "token",
+            "https://example.com",
+            "not hex"
+        ));
+    }
+}
@@ -0,0 +1,415 @@
+use crate::configuration::TwoFactorSettings;
+use crate::domain::UserRole;
+use anyhow::Context;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// The defaults every authenticator app understands (RFC 6238, section 4).
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+// Codes from the previous and the next time step are accepted too, to absorb clock drift.
+const ALLOWED_DRIFT: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+// No characters that are easily mistaken for one another (0/o, 1/l/i)
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Where a user stands with regard to the second factor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecondFactor {
+    // A password (or a token) is all it takes
+    Disabled,
+    // The login form asks for a code after the password
+    Enabled,
+    // The role of the user requires a second factor, but they haven't set one up yet
+    EnrollmentRequired,
+}
+
+#[tracing::instrument(name = "Get second factor status", skip(pool, settings))]
+pub async fn second_factor_status(
+    user_id: Uuid,
+    pool: &PgPool,
+    settings: &TwoFactorSettings,
+) -> Result<SecondFactor, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT role, totp_enabled_at FROM users WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the second factor of a user.")?;
+    if row.totp_enabled_at.is_some() {
+        return Ok(SecondFactor::Enabled);
+    }
+    let role = UserRole::parse(row.role).map_err(anyhow::Error::msg)?;
+    if settings.required_for.contains(&role) {
+        Ok(SecondFactor::EnrollmentRequired)
+    } else {
+        Ok(SecondFactor::Disabled)
+    }
+}
+
+/// Returns the secret the user should add to their authenticator,
+/// or `None` if they already have a second factor.
+/// Reloading the enrollment page keeps the same secret, so a code scanned earlier still works.
+#[tracing::instrument(name = "Start TOTP enrollment", skip(pool))]
+pub async fn start_enrollment(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Secret<Vec<u8>>>, anyhow::Error> {
+    let secret: Vec<u8> = (0..20).map(|_| rand::thread_rng().gen()).collect();
+    let row = sqlx::query!(
+        r#"
+        UPDATE users SET totp_secret = COALESCE(totp_secret, $2)
+        WHERE user_id = $1 AND totp_enabled_at IS NULL
+        RETURNING totp_secret AS "totp_secret!"
+        "#,
+        user_id,
+        secret,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to store a new TOTP secret.")?;
+    Ok(row.map(|r| Secret::new(r.totp_secret)))
+}
+
+/// Turn the second factor on, once the user has shown that their authenticator produces valid codes.
+/// Returns the recovery codes, to be shown exactly once, or `None` if `code` is wrong.
+#[tracing::instrument(name = "Confirm TOTP enrollment", skip(code, pool))]
+pub async fn confirm_enrollment(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+) -> Result<Option<Vec<String>>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret AS "totp_secret!"
+        FROM users
+        WHERE user_id = $1 AND totp_enabled_at IS NULL AND totp_secret IS NOT NULL
+        "#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a pending TOTP secret.")?;
+    let step = match row.and_then(|r| matching_step(&r.totp_secret, code, unix_now())) {
+        Some(step) => step,
+        None => return Ok(None),
+    };
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|c| hash_recovery_code(c))
+        .collect();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE users SET totp_enabled_at = $2, totp_last_used_step = $3
+        WHERE user_id = $1
+        "#,
+        user_id,
+        Utc::now(),
+        step as i64,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enable TOTP.")?;
+    sqlx::query!(
+        r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete old recovery codes.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO totp_recovery_codes (user_id, code_hash)
+        SELECT $1, * FROM UNNEST($2::text[])
+        "#,
+        user_id,
+        &code_hashes,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable TOTP.")?;
+    Ok(Some(recovery_codes))
+}
+
+/// Check the code typed after the password: either the current TOTP code or an unused recovery code.
+/// Both can only be used once.
+#[tracing::instrument(name = "Verify second factor", skip(code, pool))]
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret AS "totp_secret!"
+        FROM users
+        WHERE user_id = $1 AND totp_enabled_at IS NOT NULL
+        "#,
+        user_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a TOTP secret.")?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(false),
+    };
+
+    if let Some(step) = matching_step(&row.totp_secret, code, unix_now()) {
+        let result = sqlx::query!(
+            r#"
+            UPDATE users SET totp_last_used_step = $2
+            WHERE user_id = $1 AND (totp_last_used_step IS NULL OR totp_last_used_step < $2)
+            "#,
+            user_id,
+            step as i64,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record the use of a TOTP code.")?;
+        return Ok(result.rows_affected() == 1);
+    }
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE totp_recovery_codes SET used_at = $3
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code),
+        Utc::now(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the use of a recovery code.")?;
+    Ok(result.rows_affected() == 1)
+}
+
+#[tracing::instrument(name = "Disable TOTP", skip(pool))]
+pub async fn disable_two_factor(user_id: Uuid, pool: &PgPool) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to disable TOTP.")?;
+    sqlx::query!(
+        r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable TOTP.")?;
+    Ok(())
+}
+
+/// The URI encoded in the QR code that authenticator apps scan.
+/// https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+pub fn otpauth_uri(issuer: &str, username: &str, secret: &Secret<Vec<u8>>) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        urlencoding::encode(issuer),
+        urlencoding::encode(username),
+        base32_encode(secret.expose_secret()),
+        urlencoding::encode(issuer),
+        DIGITS,
+        STEP_SECONDS,
+    )
+}
+
+pub fn qr_code_svg(data: &str) -> Result<String, anyhow::Error> {
+    let code = qrcode::QrCode::new(data.as_bytes()).context("Failed to encode a QR code.")?;
+    Ok(code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build())
+}
+
+/// The code an authenticator set up with `secret` shows at `unix_time`.
+pub fn code_at(secret: &[u8], unix_time: u64) -> String {
+    format!(
+        "{:0width$}",
+        hotp(secret, unix_time / STEP_SECONDS, DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+// RFC 4226, section 5.3
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC can take a key of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+    binary % 10u32.pow(digits)
+}
+
+// Returns the time step `code` was generated for, if it is close enough to `now`.
+fn matching_step(secret: &[u8], code: &str, now: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = now / STEP_SECONDS;
+    (current.saturating_sub(ALLOWED_DRIFT)..=current + ALLOWED_DRIFT)
+        .find(|step| hotp(secret, *step, DIGITS) == code)
+}
+
+fn unix_now() -> u64 {
+    Utc::now().timestamp() as u64
+}
+
+fn generate_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut code: String = (0..10)
+        .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+        .collect();
+    code.insert(5, '-');
+    code
+}
+
+// Users may type recovery codes in upper case, or without the dash.
+fn hash_recovery_code(code: &str) -> String {
+    let normalised: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalised.as_bytes()))
+}
+
+// RFC 4648, without padding: that is what authenticator apps expect.
+fn base32_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn hotp_matches_the_rfc_4226_test_vectors() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as u64, 6), code);
+        }
+    }
+
+    #[test]
+    fn totp_matches_the_rfc_6238_test_vectors() {
+        for (time, code) in [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+        ] {
+            assert_eq!(hotp(RFC_SECRET, time / STEP_SECONDS, 8), code);
+        }
+    }
+
+    #[test]
+    fn codes_from_adjacent_steps_are_accepted() {
+        let now = 1111111111;
+        let previous = format!("{:06}", hotp(RFC_SECRET, now / STEP_SECONDS - 1, 6));
+        assert_eq!(
+            matching_step(RFC_SECRET, &previous, now),
+            Some(now / STEP_SECONDS - 1)
+        );
+    }
+
+    #[test]
+    fn codes_from_distant_steps_are_rejected() {
+        let now = 1111111111;
+        let old = format!("{:06}", hotp(RFC_SECRET, now / STEP_SECONDS - 2, 6));
+        assert_eq!(matching_step(RFC_SECRET, &old, now), None);
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        for code in ["", "12345", "1234567", "12a456"] {
+            assert_eq!(matching_step(RFC_SECRET, code, 59), None);
+        }
+    }
+
+    #[test]
+    fn base32_matches_the_rfc_4648_test_vectors() {
+        for (input, output) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(base32_encode(input.as_bytes()), output);
+        }
+    }
+
+    #[test]
+    fn recovery_codes_are_normalised_before_hashing() {
+        assert_eq!(
+            hash_recovery_code("abcde-fghjk"),
+            hash_recovery_code(" ABCDEFGHJK ")
+        );
+    }
+}
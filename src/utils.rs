@@ -0,0 +1,45 @@
+use actix_web::http::header::LOCATION;
+use actix_web::{HttpRequest, HttpResponse};
+use std::net::{IpAddr, SocketAddr};
+
+// Return an opaque 500 while preserving the error root's cause for logging.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+// The address of the client, as seen by us or, behind `trusted_proxies` reverse proxies,
+// by the first of them.
+// Every proxy appends the address it got the request from to `X-Forwarded-For`: the entries
+// to the left of theirs come from the client, who can make them up. So does `Forwarded`.
+pub fn client_ip(
+    request: &HttpRequest,
+    trust_forwarded_headers: bool,
+    trusted_proxies: usize,
+) -> Option<IpAddr> {
+    if !trust_forwarded_headers || trusted_proxies == 0 {
+        return request.peer_addr().map(|a| a.ip());
+    }
+    // Split as bytes: an entry that isn't text must not shift the others
+    let mut hops: Vec<&[u8]> = request
+        .headers()
+        .get_all("x-forwarded-for")
+        .flat_map(|value| value.as_bytes().split(|&b| b == b','))
+        .collect();
+    hops.reverse();
+    let address = std::str::from_utf8(hops.get(trusted_proxies - 1)?)
+        .ok()?
+        .trim();
+    address
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| address.parse::<SocketAddr>().ok().map(|a| a.ip()))
+}
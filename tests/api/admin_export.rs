@@ -0,0 +1,211 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn import_subscribers(app: &TestApp, csv: &str, mode: &str) {
+    app.post_subscribers_import(csv, mode)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn csv_exports_contain_a_header_and_the_filtered_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    import_subscribers(
+        &app,
+        "email,name\nursula_le_guin@gmail.com,le guin\n",
+        "confirmed",
+    )
+    .await;
+    import_subscribers(
+        &app,
+        "email,name\noctavia@gmail.com,Octavia\n",
+        "send_confirmation",
+    )
+    .await;
+
+    // Act
+    let response = app
+        .get_subscribers_export("format=csv&status=confirmed")
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "text/csv; charset=utf-8",
+        response.headers()["Content-Type"]
+    );
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at");
+    assert!(lines[1].contains("ursula_le_guin@gmail.com,le guin,confirmed"));
+}
+
+#[tokio::test]
+async fn ndjson_exports_contain_one_subscriber_per_line() {
+    // Arrange
+    let app = spawn_app().await;
+    import_subscribers(
+        &app,
+        "email,name\nursula_le_guin@gmail.com,le guin\noctavia@gmail.com,Octavia\n",
+        "confirmed",
+    )
+    .await;
+
+    // Act
+    let response = app.get_subscribers_export("format=ndjson").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body = response.text().await.unwrap();
+    let mut emails: Vec<String> = body
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+        .map(|v| v["email"].as_str().unwrap().to_owned())
+        .collect();
+    // Both rows were imported at the same instant, so their relative order is arbitrary
+    emails.sort();
+    assert_eq!(
+        emails,
+        vec!["octavia@gmail.com", "ursula_le_guin@gmail.com"]
+    );
+}
+
+#[tokio::test]
+async fn an_empty_csv_export_still_has_a_header() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let body = app.get_subscribers_export("").await.text().await.unwrap();
+
+    // Assert
+    assert_eq!(body, "id,email,name,status,subscribed_at\n");
+}
+
+#[tokio::test]
+async fn exports_are_recorded_with_the_user_who_requested_them() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.get_subscribers_export("format=ndjson&status=pending_confirmation")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let saved = sqlx::query!("SELECT user_id, format, status FROM subscriber_exports",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved export.");
+    assert_eq!(saved.user_id, Some(app.test_user.user_id));
+    assert_eq!(saved.format, "ndjson");
+    assert_eq!(saved.status.as_deref(), Some("pending_confirmation"));
+}
+
+#[tokio::test]
+async fn exports_require_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/admin/subscribers/export", &app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    let exports = sqlx::query!("SELECT id FROM subscriber_exports",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(exports.is_empty());
+}
+
+#[tokio::test]
+async fn exports_run_past_one_page_and_keep_rows_that_no_longer_pass_validation() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT md5(i::text)::uuid, 'subscriber' || i || '@example.com', 'Subscriber ' || i,
+            now() - interval '1 hour' + i * interval '1 second', 'confirmed'
+        FROM generate_series(1, 1500) AS i
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // Stored before validation got stricter: it ends up on the second page of the export
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'not-an-email', '', now(), 'confirmed')
+        "#,
+        uuid::Uuid::new_v4(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let body = app
+        .get_subscribers_export("format=ndjson")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    let subscribers: Vec<serde_json::Value> = body
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(subscribers.len(), 1501);
+    assert_eq!(subscribers[0]["email"], "subscriber1@example.com");
+    assert_eq!(subscribers[1500]["email"], "not-an-email");
+    assert_eq!(subscribers[1500]["name"], "");
+}
+
+#[tokio::test]
+async fn csv_exports_do_not_contain_formulas() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, '-2+3@example.com', '=HYPERLINK("https://example.com")', now(), 'confirmed')
+        "#,
+        uuid::Uuid::new_v4(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let csv = app
+        .get_subscribers_export("format=csv")
+        .await
+        .text()
+        .await
+        .unwrap();
+    let ndjson = app
+        .get_subscribers_export("format=ndjson")
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    let record = reader.records().next().unwrap().unwrap();
+    assert_eq!(&record[1], "'-2+3@example.com");
+    assert_eq!(&record[2], "'=HYPERLINK(\"https://example.com\")");
+    // Only spreadsheets need it: the JSON export keeps the stored values
+    let subscriber: serde_json::Value = serde_json::from_str(ndjson.trim()).unwrap();
+    assert_eq!(subscriber["email"], "-2+3@example.com");
+    assert_eq!(subscriber["name"], "=HYPERLINK(\"https://example.com\")");
+}
@@ -0,0 +1,134 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn importing_as_confirmed_stores_confirmed_subscribers_without_sending_emails() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let csv = "email,name\nursula_le_guin@gmail.com,le guin\noctavia@gmail.com,Octavia Butler\n";
+
+    // Act
+    let response = app.post_subscribers_import(csv, "confirmed").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    let saved = sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert!(saved.iter().all(|s| s.status == "confirmed"));
+}
+
+#[tokio::test]
+async fn importing_with_confirmation_sends_a_confirmation_email_per_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let csv = "name,email\nle guin,ursula_le_guin@gmail.com\nOctavia Butler,octavia@gmail.com\n";
+
+    // Act
+    let response = app.post_subscribers_import(csv, "send_confirmation").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.iter().all(|s| s.status == "pending_confirmation"));
+
+    // The links in the emails must work like the ones sent by `POST /subscriptions`
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn invalid_and_duplicate_rows_are_reported_by_line() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_subscribers_import("email,name\nexisting@gmail.com,Existing\n", "confirmed")
+        .await
+        .error_for_status()
+        .unwrap();
+    let csv = "email,name\n\
+        not-an-email,Somebody\n\
+        existing@gmail.com,Existing\n\
+        fresh@gmail.com,Fresh\n\
+        fresh@gmail.com,Fresh again\n\
+        other@gmail.com,<script>\n";
+
+    // Act
+    let response = app.post_subscribers_import(csv, "confirmed").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["duplicates"], 2);
+    assert_eq!(report["invalid"], 2);
+    let lines: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(lines, vec![2, 3, 5, 6]);
+}
+
+#[tokio::test]
+async fn files_without_the_expected_columns_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscribers_import(
+            "mail,full_name\nursula_le_guin@gmail.com,le guin\n",
+            "confirmed",
+        )
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn imports_require_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/import", &app.address))
+        .body("email,name\nursula_le_guin@gmail.com,le guin\n")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email FROM subscriptions",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
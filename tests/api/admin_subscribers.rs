@@ -0,0 +1,255 @@
+use crate::helpers::{spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Use the public API of the application under test to create
+/// one pending subscriber per (name, email) pair.
+async fn create_subscribers(app: &TestApp, subscribers: &[(&str, &str)]) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    for (name, email) in subscribers {
+        let body = format!(
+            "name={}&email={}",
+            urlencoding::encode(name),
+            urlencoding::encode(email)
+        );
+        app.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+}
+
+async fn subscriber_id(app: &TestApp, email: &str) -> String {
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .id
+        .to_string()
+}
+
+fn emails(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_status_and_searched_by_prefix() {
+    // Arrange
+    let app = spawn_app().await;
+    create_subscribers(
+        &app,
+        &[
+            ("le guin", "ursula_le_guin@gmail.com"),
+            ("Ursula Andress", "andress@gmail.com"),
+            ("Octavia Butler", "octavia@gmail.com"),
+        ],
+    )
+    .await;
+    let id = subscriber_id(&app, "octavia@gmail.com").await;
+    app.put_subscriber_status(&id, serde_json::json!({"status": "confirmed"}))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let confirmed: serde_json::Value = app
+        .get_admin_subscribers("status=confirmed")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let searched: serde_json::Value = app
+        .get_admin_subscribers("search=URSULA")
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(emails(&confirmed), vec!["octavia@gmail.com"]);
+    assert_eq!(
+        emails(&searched),
+        vec!["ursula_le_guin@gmail.com", "andress@gmail.com"]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated_with_a_cursor() {
+    // Arrange
+    let app = spawn_app().await;
+    create_subscribers(
+        &app,
+        &[
+            ("one", "one@gmail.com"),
+            ("two", "two@gmail.com"),
+            ("three", "three@gmail.com"),
+        ],
+    )
+    .await;
+
+    // Act
+    let first_page: serde_json::Value = app
+        .get_admin_subscribers("limit=2")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let cursor = first_page["next_cursor"].as_str().unwrap();
+    let second_page: serde_json::Value = app
+        .get_admin_subscribers(&format!("limit=2&cursor={}", cursor))
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(emails(&first_page), vec!["one@gmail.com", "two@gmail.com"]);
+    assert_eq!(emails(&second_page), vec!["three@gmail.com"]);
+    assert!(second_page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn invalid_list_parameters_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("status=deleted", "unknown status"),
+        ("cursor=garbage", "invalid cursor"),
+        ("limit=0", "limit too small"),
+        ("subscribed_after=yesterday", "invalid date"),
+    ];
+
+    for (query, description) in test_cases {
+        // Act
+        let response = app.get_admin_subscribers(query).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the query had an {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_single_subscriber_can_be_fetched() {
+    // Arrange
+    let app = spawn_app().await;
+    create_subscribers(&app, &[("le guin", "ursula_le_guin@gmail.com")]).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act
+    let response = app.get_admin_subscriber(&id).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["name"], "le guin");
+    assert_eq!(subscriber["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribers_that_no_longer_pass_validation_are_returned_as_stored() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'not-an-email', '', now(), 'confirmed')
+        "#,
+        id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let get_response = app.get_admin_subscriber(&id.to_string()).await;
+    let put_response = app
+        .put_subscriber_status(
+            &id.to_string(),
+            serde_json::json!({"status": "pending_confirmation"}),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, get_response.status().as_u16());
+    let subscriber: serde_json::Value = get_response.json().await.unwrap();
+    assert_eq!(subscriber["email"], "not-an-email");
+    assert_eq!(subscriber["name"], "");
+    assert_eq!(200, put_response.status().as_u16());
+    let subscriber: serde_json::Value = put_response.json().await.unwrap();
+    assert_eq!(subscriber["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn unknown_subscribers_return_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    let id = Uuid::new_v4().to_string();
+
+    // Act
+    let get_response = app.get_admin_subscriber(&id).await;
+    let put_response = app
+        .put_subscriber_status(&id, serde_json::json!({"status": "confirmed"}))
+        .await;
+
+    // Assert
+    assert_eq!(404, get_response.status().as_u16());
+    assert_eq!(404, put_response.status().as_u16());
+}
+
+#[tokio::test]
+async fn changing_to_an_invalid_status_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_subscribers(&app, &[("le guin", "ursula_le_guin@gmail.com")]).await;
+    let id = subscriber_id(&app, "ursula_le_guin@gmail.com").await;
+
+    // Act
+    let response = app
+        .put_subscriber_status(&id, serde_json::json!({"status": "deleted"}))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
@@ -0,0 +1,274 @@
+use crate::helpers::{spawn_app, TestUser};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn owners_can_add_users_with_a_one_time_password() {
+    // Arrange
+    let mut app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_admin_user(serde_json::json!({ "username": "ursula", "role": "editor" }))
+        .await;
+
+    // Assert
+    assert_eq!(201, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["username"], "ursula");
+    assert_eq!(body["role"], "editor");
+    assert_eq!(body["disabled"], false);
+
+    // The new user has to change the password before using the API
+    app.test_user = TestUser {
+        user_id: Uuid::parse_str(body["user_id"].as_str().unwrap()).unwrap(),
+        username: "ursula".into(),
+        password: body["one_time_password"].as_str().unwrap().into(),
+    };
+    let response = app.get_admin_subscribers("").await;
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn usernames_must_be_unique() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({ "username": "ursula", "role": "viewer" });
+    app.post_admin_user(body.clone())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_admin_user(body).await;
+
+    // Assert
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn users_are_listed_with_their_role() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = TestUser::generate();
+    viewer.store(&app.db_pool, "viewer").await;
+
+    // Act
+    let response = app.get_admin_users().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let users: Vec<serde_json::Value> = response.json().await.unwrap();
+    let listed = users
+        .iter()
+        .find(|u| u["username"] == viewer.username.as_str())
+        .unwrap();
+    assert_eq!(listed["role"], "viewer");
+}
+
+#[tokio::test]
+async fn only_owners_can_manage_users() {
+    // Arrange
+    let mut app = spawn_app().await;
+    let editor = TestUser::generate();
+    editor.store(&app.db_pool, "editor").await;
+    app.test_user = editor;
+
+    // Act
+    let list = app.get_admin_users().await;
+    let add = app
+        .post_admin_user(serde_json::json!({ "username": "ursula", "role": "owner" }))
+        .await;
+
+    // Assert
+    assert_eq!(403, list.status().as_u16());
+    assert_eq!(403, add.status().as_u16());
+}
+
+#[tokio::test]
+async fn changing_the_role_of_a_user_changes_their_permissions() {
+    // Arrange
+    let mut app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool, "editor").await;
+
+    // Act
+    let response = app
+        .put_admin_user(
+            &user.user_id,
+            "role",
+            serde_json::json!({ "role": "viewer" }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    app.test_user = user;
+    let export = app.get_subscribers_export("").await;
+    assert_eq!(403, export.status().as_u16());
+    let list = app.get_admin_subscribers("").await;
+    assert_eq!(200, list.status().as_u16());
+}
+
+#[tokio::test]
+async fn disabled_users_cannot_authenticate() {
+    // Arrange
+    let mut app = spawn_app().await;
+    let user = TestUser::generate();
+    user.store(&app.db_pool, "editor").await;
+
+    // Act
+    let response = app
+        .put_admin_user(
+            &user.user_id,
+            "disabled",
+            serde_json::json!({ "disabled": true }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["disabled"], true);
+    app.test_user = user;
+    let response = app.get_admin_subscribers("").await;
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_last_active_owner_cannot_be_demoted_or_disabled() {
+    // Arrange
+    let app = spawn_app().await;
+    // The bootstrap admin is an owner too: disable it, so that the test user is the last one
+    sqlx::query!("UPDATE users SET disabled_at = now() WHERE username = 'admin'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let owner_id = app.test_user.user_id;
+
+    // Act
+    let demote = app
+        .put_admin_user(&owner_id, "role", serde_json::json!({ "role": "editor" }))
+        .await;
+    let disable = app
+        .put_admin_user(
+            &owner_id,
+            "disabled",
+            serde_json::json!({ "disabled": true }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(409, demote.status().as_u16());
+    assert_eq!(409, disable.status().as_u16());
+    let saved = sqlx::query!(
+        "SELECT role, disabled_at FROM users WHERE user_id = $1",
+        owner_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.role, "owner");
+    assert!(saved.disabled_at.is_none());
+}
+
+#[tokio::test]
+async fn changing_an_unknown_user_returns_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .put_admin_user(
+            &Uuid::new_v4(),
+            "role",
+            serde_json::json!({ "role": "viewer" }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_email_address_of_a_user_can_be_changed_or_removed() {
+    // Arrange
+    let app = spawn_app().await;
+    let viewer = TestUser::generate();
+    viewer.store(&app.db_pool, "viewer").await;
+
+    // Act - Part 1 - Set it
+    let response = app
+        .put_admin_user(
+            &viewer.user_id,
+            "email",
+            serde_json::json!({ "email": "viewer@example.com" }),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["email"], "viewer@example.com");
+
+    // Act - Part 2 - Remove it
+    let response = app
+        .put_admin_user(
+            &viewer.user_id,
+            "email",
+            serde_json::json!({ "email": null }),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["email"].is_null());
+}
+
+#[tokio::test]
+async fn email_addresses_must_be_unique_regardless_of_case() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_admin_user(serde_json::json!({
+        "username": "ursula",
+        "role": "viewer",
+        "email": "ursula@example.com",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act - Part 1 - A new user
+    let response = app
+        .post_admin_user(serde_json::json!({
+            "username": "victor",
+            "role": "viewer",
+            "email": "Ursula@example.com",
+        }))
+        .await;
+    assert_eq!(409, response.status().as_u16());
+
+    // Act - Part 2 - An existing user
+    let response = app
+        .put_admin_user(
+            &app.test_user.user_id,
+            "email",
+            serde_json::json!({ "email": "URSULA@example.com" }),
+        )
+        .await;
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn an_invalid_email_address_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_admin_user(serde_json::json!({
+            "username": "ursula",
+            "role": "viewer",
+            "email": "not-an-email",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
@@ -0,0 +1,177 @@
+use crate::helpers::{spawn_app, TestApp, TestUser};
+
+async fn publish_with_token(app: &TestApp, token: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/newsletters", &app.address))
+        .bearer_auth(token)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn a_token_with_the_publish_scope_can_publish() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, token) = app.create_api_token(&["publish"]).await;
+
+    // Act
+    let response = publish_with_token(&app, &token).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn tokens_are_limited_to_their_scopes() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, token) = app.create_api_token(&["publish"]).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers", &app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn tokens_are_only_stored_as_a_hash_and_never_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, token) = app.create_api_token(&["read-subscribers"]).await;
+
+    // Act
+    let listed = app.get_api_tokens().await.text().await.unwrap();
+
+    // Assert
+    assert!(!listed.contains(&token));
+    let saved = sqlx::query!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(saved.token_hash, token);
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let (token_id, token) = app.create_api_token(&["publish"]).await;
+
+    // Act
+    let response = app.delete_api_token(&token_id).await;
+
+    // Assert
+    assert_eq!(204, response.status().as_u16());
+    let response = publish_with_token(&app, &token).await;
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, token) = app.create_api_token(&["publish"]).await;
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = publish_with_token(&app, &token).await;
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn tokens_cannot_be_used_to_create_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, token) = app.create_api_token(&["publish", "manage-users"]).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/tokens", &app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "name": "escalation", "scopes": ["publish"] }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn tokens_cannot_exceed_the_role_of_their_user() {
+    // Arrange
+    let mut app = spawn_app().await;
+    let viewer = TestUser::generate();
+    viewer.store(&app.db_pool, "viewer").await;
+    app.test_user = viewer;
+
+    // Act
+    let response = app
+        .post_api_token(serde_json::json!({ "name": "CI", "scopes": ["publish"] }))
+        .await;
+
+    // Assert
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn invalid_token_requests_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "name": "CI", "scopes": ["everything"] }),
+            "unknown scope",
+        ),
+        (
+            serde_json::json!({ "name": "CI", "scopes": [] }),
+            "no scopes",
+        ),
+        (
+            serde_json::json!({ "name": " ", "scopes": ["publish"] }),
+            "empty name",
+        ),
+        (
+            serde_json::json!({
+                "name": "CI",
+                "scopes": ["publish"],
+                "expires_at": "2020-01-01T00:00:00Z"
+            }),
+            "expiry in the past",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_api_token(body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use crate::helpers::{spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn publish(app: &TestApp, title: &str, subscriber_only: bool) {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": title,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as <em>HTML</em></p>",
+            },
+            "subscriber_only": subscriber_only,
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+async fn store_confirmed_subscriber(app: &TestApp) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'reader@example.com', 'reader', now(), 'confirmed')
+        "#,
+        Uuid::new_v4(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+// The emails sent so far, as sent to the email delivery service.
+async fn sent_emails(app: &TestApp) -> Vec<serde_json::Value> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn the_archive_lists_public_issues_only() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Public issue", false).await;
+    publish(&app, "Members & friends", true).await;
+
+    // Act
+    let response = app.get_archive("").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"<a href="/archive/public-issue">Public issue</a>"#));
+    assert!(!html_page.contains("Members"));
+}
+
+#[tokio::test]
+async fn public_issues_are_rendered_in_the_browser() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Public <issue>", false).await;
+
+    // Act
+    let response = app.get_archive("/public-issue").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let csp = response.headers()["content-security-policy"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    assert!(!csp.contains("script-src"));
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<h1>Public &lt;issue&gt;</h1>"));
+    assert!(html_page.contains("<p>Newsletter body as <em>HTML</em></p>"));
+}
+
+#[tokio::test]
+async fn subscriber_only_and_unknown_issues_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Members only", true).await;
+
+    // Act
+    let subscriber_only = app.get_archive("/members-only").await;
+    let unknown = app.get_archive("/never-published").await;
+
+    // Assert
+    assert_eq!(404, subscriber_only.status().as_u16());
+    assert_eq!(404, unknown.status().as_u16());
+}
+
+#[tokio::test]
+async fn issues_with_the_same_title_get_different_slugs() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    publish(&app, "Weekly digest", false).await;
+    publish(&app, "Weekly digest", false).await;
+
+    // Assert
+    let slugs: Vec<String> =
+        sqlx::query_scalar!("SELECT slug FROM newsletter_issues ORDER BY published_at")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(slugs[0], "weekly-digest");
+    assert!(slugs[1].starts_with("weekly-digest-"));
+    assert_eq!(
+        200,
+        app.get_archive(&format!("/{}", slugs[1])).await.status()
+    );
+}
+
+#[tokio::test]
+async fn emails_of_public_issues_link_to_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    store_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish(&app, "Public issue", false).await;
+    app.dispatch_all_pending_emails().await;
+    publish(&app, "Members only", true).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let emails = sent_emails(&app).await;
+    let link = format!("{}/archive/public-issue", app.base_url);
+    assert!(emails[0]["HtmlBody"].as_str().unwrap().contains(&link));
+    assert!(emails[0]["TextBody"].as_str().unwrap().contains(&link));
+    assert!(!emails[1]["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("/archive/"));
+    assert!(!emails[1]["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("/archive/"));
+}
@@ -0,0 +1,361 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestUser};
+use rust_newsletter::audit_log::{record_command, AuditAction};
+use uuid::Uuid;
+
+fn entries(page: &serde_json::Value) -> &Vec<serde_json::Value> {
+    page["entries"].as_array().unwrap()
+}
+
+#[tokio::test]
+async fn publishing_is_recorded_with_who_did_it_and_from_where() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let request_id = response.headers()["x-request-id"].to_str().unwrap();
+
+    // Assert
+    let page: serde_json::Value = app
+        .get_audit_log(&[("action", "newsletter.published")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let entry = &entries(&page)[0];
+    assert_eq!(entry["user_id"], app.test_user.user_id.to_string());
+    assert_eq!(entry["username"], app.test_user.username);
+    assert_eq!(entry["ip"], "127.0.0.1");
+    assert_eq!(entry["request_id"], request_id);
+    assert_eq!(entry["details"]["title"], "Newsletter title");
+}
+
+#[tokio::test]
+async fn failed_and_successful_logins_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password",
+    }))
+    .await;
+    app.test_user.login(&app).await;
+
+    // Assert
+    let page: serde_json::Value = app
+        .get_audit_log(&[("action", "login.failed")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let failure = &entries(&page)[0];
+    assert!(failure["user_id"].is_null());
+    assert_eq!(
+        failure["details"]["target_user_id"],
+        app.test_user.user_id.to_string()
+    );
+    assert!(failure["details"].get("username").is_none());
+    let page: serde_json::Value = app
+        .get_audit_log(&[("action", "login.succeeded")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        entries(&page)[0]["user_id"],
+        app.test_user.user_id.to_string()
+    );
+}
+
+#[tokio::test]
+async fn failed_logins_do_not_keep_unknown_usernames() {
+    // Arrange
+    let app = spawn_app().await;
+    // Somebody typing their password in the wrong field
+    let username = "my-secret-password";
+
+    // Act
+    app.post_login(&serde_json::json!({
+        "username": username,
+        "password": "wrong-password",
+    }))
+    .await;
+
+    // Assert
+    let page: serde_json::Value = app
+        .get_audit_log(&[("action", "login.failed")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let failure = &entries(&page)[0];
+    assert!(failure["user_id"].is_null());
+    assert!(failure["details"].get("target_user_id").is_none());
+    assert!(!failure.to_string().contains(username));
+}
+
+#[tokio::test]
+async fn throttled_logins_are_recorded() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttle.free_attempts = 1;
+        c.login_throttle.base_delay_milliseconds = 60_000;
+    })
+    .await;
+    // The test user reads the audit log: somebody else gets throttled
+    let editor = TestUser::generate();
+    editor.store(&app.db_pool, "editor").await;
+    for _ in 0..2 {
+        app.post_login(&serde_json::json!({
+            "username": &editor.username,
+            "password": "wrong-password",
+        }))
+        .await;
+    }
+
+    // Act
+    editor.login(&app).await;
+
+    // Assert
+    let page: serde_json::Value = app
+        .get_audit_log(&[("action", "login.throttled")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let entry = &entries(&page)[0];
+    assert!(entry["user_id"].is_null());
+    assert_eq!(
+        entry["details"]["target_user_id"],
+        editor.user_id.to_string()
+    );
+    assert_eq!(entry["details"]["step"], "password");
+}
+
+#[tokio::test]
+async fn rejected_api_credentials_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", &app.address))
+        .basic_auth(&app.test_user.username, Some("wrong-password"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(401, response.status().as_u16());
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", &app.address))
+        .bearer_auth("not-a-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(401, response.status().as_u16());
+
+    // Assert
+    let page: serde_json::Value = app
+        .get_audit_log(&[("action", "api.authentication_failed")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let (bearer, basic) = (&entries(&page)[0], &entries(&page)[1]);
+    assert!(bearer["user_id"].is_null());
+    assert_eq!(bearer["details"]["scheme"], "bearer");
+    assert!(basic["user_id"].is_null());
+    assert_eq!(
+        basic["details"]["target_user_id"],
+        app.test_user.user_id.to_string()
+    );
+    assert_eq!(basic["details"]["scheme"], "basic");
+}
+
+#[tokio::test]
+async fn password_changes_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+
+    // Act
+    app.post_change_password(&serde_json::json!({
+        "current_password": &app.test_user.password,
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    }))
+    .await;
+
+    // Assert
+    let page: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/audit-log", &app.address))
+        .query(&[("action", "password.changed")])
+        .basic_auth(&app.test_user.username, Some(&new_password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        entries(&page)[0]["user_id"],
+        app.test_user.user_id.to_string()
+    );
+}
+
+#[tokio::test]
+async fn user_changes_are_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = TestUser::generate();
+    editor.store(&app.db_pool, "editor").await;
+
+    // Act
+    app.put_admin_user(
+        &editor.user_id,
+        "role",
+        serde_json::json!({ "role": "viewer" }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    let page: serde_json::Value = app
+        .get_audit_log(&[("action", "user.changed")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let entry = &entries(&page)[0];
+    assert_eq!(entry["user_id"], app.test_user.user_id.to_string());
+    assert_eq!(entry["details"]["user_id"], editor.user_id.to_string());
+    assert_eq!(entry["details"]["role"], "viewer");
+}
+
+#[tokio::test]
+async fn commands_are_recorded_without_a_user() {
+    // Arrange
+    let app = spawn_app().await;
+    let details = serde_json::json!({ "user_id": app.test_user.user_id });
+
+    // Act
+    record_command(&app.db_pool, AuditAction::PasswordReset, details)
+        .await
+        .unwrap();
+
+    // Assert
+    let page: serde_json::Value = app
+        .get_audit_log(&[("action", "password.reset")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let entry = &entries(&page)[0];
+    assert!(entry["user_id"].is_null());
+    assert!(entry["ip"].is_null());
+    assert_eq!(
+        entry["details"]["user_id"],
+        app.test_user.user_id.to_string()
+    );
+}
+
+#[tokio::test]
+async fn entries_are_filtered_by_user_and_paginated_newest_first() {
+    // Arrange
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        app.test_user.login(&app).await;
+    }
+    // Somebody else's login must not show up
+    let editor = TestUser::generate();
+    editor.store(&app.db_pool, "editor").await;
+    editor.login(&app).await;
+    let user_id = app.test_user.user_id.to_string();
+
+    // Act
+    let first_page: serde_json::Value = app
+        .get_audit_log(&[("user_id", &user_id), ("limit", "2")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let cursor = first_page["next_cursor"].as_i64().unwrap().to_string();
+    let second_page: serde_json::Value = app
+        .get_audit_log(&[("user_id", &user_id), ("limit", "2"), ("cursor", &cursor)])
+        .await
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    let ids: Vec<i64> = entries(&first_page)
+        .iter()
+        .chain(entries(&second_page))
+        .map(|entry| entry["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids.len(), 3);
+    assert!(ids.windows(2).all(|pair| pair[0] > pair[1]));
+    assert!(second_page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn unknown_actions_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_audit_log(&[("action", "everything")]).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn only_owners_can_read_the_audit_log() {
+    // Arrange
+    let mut app = spawn_app().await;
+    let editor = TestUser::generate();
+    editor.store(&app.db_pool, "editor").await;
+    app.test_user = editor;
+
+    // Act
+    let response = app.get_audit_log(&[]).await;
+
+    // Assert
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn entries_cannot_be_changed_or_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let update = sqlx::query!("UPDATE audit_log SET user_id = $1", Uuid::new_v4())
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query!("DELETE FROM audit_log")
+        .execute(&app.db_pool)
+        .await;
+    let truncate = sqlx::query!("TRUNCATE audit_log")
+        .execute(&app.db_pool)
+        .await;
+
+    // Assert
+    assert!(update.is_err());
+    assert!(delete.is_err());
+    assert!(truncate.is_err());
+}
@@ -0,0 +1,74 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use rust_newsletter::authentication::{bootstrap_admin, PasswordPolicy};
+use rust_newsletter::configuration::{get_configuration, BootstrapAdminSettings};
+use secrecy::Secret;
+
+#[tokio::test]
+async fn a_fresh_deployment_gets_an_admin_with_a_one_time_password() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT must_change_password FROM users WHERE username = 'admin'",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the bootstrap admin.");
+    assert!(saved.must_change_password);
+}
+
+#[tokio::test]
+async fn a_configured_password_hash_can_be_used_right_away() {
+    // Arrange
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(b"correct horse battery staple", &salt)
+    .unwrap()
+    .to_string();
+    let app = spawn_app_with(|c| {
+        c.bootstrap_admin.username = "root".into();
+        c.bootstrap_admin.password_hash = Some(Secret::new(password_hash));
+    })
+    .await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers", &app.address))
+        .basic_auth("root", Some("correct horse battery staple"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn no_admin_is_created_if_there_already_are_users() {
+    // Arrange
+    let app = spawn_app().await;
+    let settings = BootstrapAdminSettings {
+        username: "another-admin".into(),
+        password_hash: None,
+    };
+
+    let policy = PasswordPolicy::new(&get_configuration().unwrap().password_hashing).unwrap();
+
+    // Act
+    bootstrap_admin(&settings, &policy, &app.db_pool)
+        .await
+        .expect("Failed to bootstrap the admin user.");
+
+    // Assert
+    let saved = sqlx::query!("SELECT username FROM users WHERE username = 'another-admin'",)
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
@@ -0,0 +1,151 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn the_login_form_sets_a_csrf_cookie_and_embeds_the_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/login", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let cookie = response
+        .cookies()
+        .find(|c| c.name() == "csrf_nonce")
+        .expect("No CSRF cookie was set.");
+    assert!(cookie.http_only());
+    assert!(cookie.same_site_strict());
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"<input type="hidden" name="csrf_token" value=""#));
+}
+
+#[tokio::test]
+async fn the_csrf_token_is_stable_for_a_browser() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let first = app.csrf_token().await;
+    let second = app.csrf_token().await;
+
+    // Assert
+    assert_eq!(first, second);
+}
+
+#[tokio::test]
+async fn a_login_without_a_csrf_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.csrf_token().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_csrf_token_from_another_browser_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = app.csrf_token().await;
+    let other_browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password,
+        "csrf_token": token,
+    });
+
+    // Act - Part 1 - Without a cookie at all
+    let response = other_browser
+        .post(format!("{}/login", &app.address))
+        .form(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(403, response.status().as_u16());
+
+    // Act - Part 2 - With a cookie of its own
+    other_browser
+        .get(format!("{}/login", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let response = other_browser
+        .post(format!("{}/login", &app.address))
+        .form(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_forged_csrf_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.csrf_token().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.address))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password,
+            "csrf_token": "00".repeat(32),
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn admin_forms_require_a_csrf_token_too() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/admin/password", &app.address))
+        .form(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(403, response.status().as_u16());
+    // The password was not changed
+    let response = app.test_user.login(&app).await;
+    assert_is_redirect_to(&response, "/");
+}
@@ -0,0 +1,240 @@
+use crate::helpers::{spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+/// Subscribe through the public API and return the token from the confirmation email.
+async fn subscribe_and_get_token(app: &TestApp) -> String {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    confirmation_links
+        .html
+        .query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[tokio::test]
+async fn admins_can_export_everything_held_about_an_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = subscribe_and_get_token(&app).await;
+
+    // Act
+    let response = app.get_gdpr_export(EMAIL).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let package: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(package["email"], EMAIL);
+    assert_eq!(package["subscriptions"][0]["name"], "le guin");
+    assert_eq!(
+        package["subscription_tokens"][0]["subscription_token"],
+        token.as_str()
+    );
+}
+
+#[tokio::test]
+async fn exporting_an_unknown_email_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_gdpr_export(EMAIL).await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn exports_are_recorded_in_the_audit_log_without_the_email() {
+    // Arrange
+    let app = spawn_app().await;
+    subscribe_and_get_token(&app).await;
+
+    // Act
+    app.get_gdpr_export(EMAIL).await.error_for_status().unwrap();
+
+    // Assert
+    let page: serde_json::Value = app
+        .get_audit_log(&[("action", "data_subject.exported")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    let entry = &page["entries"][0];
+    assert_eq!(entry["user_id"], app.test_user.user_id.to_string());
+    assert_eq!(
+        entry["details"]["subscriber_ids"].as_array().unwrap().len(),
+        1
+    );
+    assert!(!entry.to_string().contains(EMAIL));
+}
+
+#[tokio::test]
+async fn erasure_pseudonymises_the_subscription_and_deletes_its_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    subscribe_and_get_token(&app).await;
+
+    // Act
+    let response = app.post_gdpr_erase(EMAIL).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email, name, status, erased_at FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    // The row is kept for our aggregate numbers, but nothing personal is left in it
+    assert_ne!(saved.email, EMAIL);
+    assert_ne!(saved.name, "le guin");
+    assert_eq!(saved.status, "pending_confirmation");
+    assert!(saved.erased_at.is_some());
+    let tokens = sqlx::query!("SELECT subscription_token FROM subscription_tokens",)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(tokens.is_empty());
+    assert_eq!(404, app.get_gdpr_export(EMAIL).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn erasure_cancels_the_pending_deliveries_to_an_email() {
+    // Arrange
+    let app = spawn_app().await;
+    subscribe_and_get_token(&app).await;
+    let issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues
+        (newsletter_issue_id, title, text_content, html_content, published_at, slug)
+        VALUES ($1, 'Title', 'Text', '<p>HTML</p>', now(), 'title')
+        "#,
+        issue_id,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // Not due yet, so that the delivery worker leaves it alone
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email, execute_after)
+        VALUES ($1, $2, now() + interval '1 hour')
+        "#,
+        issue_id,
+        EMAIL,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app.post_gdpr_erase(EMAIL).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["cancelled_deliveries"], 1);
+    let pending = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(pending.is_empty());
+}
+
+#[tokio::test]
+async fn an_erased_email_can_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    subscribe_and_get_token(&app).await;
+    app.post_gdpr_erase(EMAIL).await.error_for_status().unwrap();
+
+    // Act
+    subscribe_and_get_token(&app).await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT email FROM subscriptions WHERE erased_at IS NULL",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, EMAIL);
+}
+
+#[tokio::test]
+async fn subscribers_can_download_their_data_with_their_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = subscribe_and_get_token(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscriptions/data", &app.address))
+        .query(&[("subscription_token", &token)])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let package: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(package["subscriptions"][0]["email"], EMAIL);
+}
+
+#[tokio::test]
+async fn subscribers_can_erase_their_data_with_their_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = subscribe_and_get_token(&app).await;
+    let erase = || {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/erase", &app.address))
+            .form(&[("subscription_token", &token)])
+            .send()
+    };
+
+    // Act
+    let response = erase().await.unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT erased_at FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.erased_at.is_some());
+    // The token was erased along with everything else
+    assert_eq!(401, erase().await.unwrap().status().as_u16());
+}
+
+#[tokio::test]
+async fn unknown_tokens_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscriptions/data", &app.address))
+        .query(&[("subscription_token", "not-a-real-token")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
@@ -0,0 +1,114 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish(app: &TestApp, title: &str, subscriber_only: bool) {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": title,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "subscriber_only": subscriber_only,
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_rss_feed_has_the_public_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Public issue", false).await;
+    publish(&app, "Members only", true).await;
+
+    // Act
+    let response = app.get_feed("rss.xml", &[]).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/rss+xml; charset=utf-8"
+    );
+    let feed = response.text().await.unwrap();
+    assert!(feed.contains("<title>Public issue</title>"));
+    assert!(feed.contains(&format!(
+        "<link>{}/archive/public-issue</link>",
+        app.base_url
+    )));
+    assert!(feed.contains("&lt;p&gt;Newsletter body as HTML&lt;/p&gt;"));
+    assert!(!feed.contains("Members only"));
+}
+
+#[tokio::test]
+async fn the_atom_feed_has_the_public_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Public issue", false).await;
+    publish(&app, "Members only", true).await;
+
+    // Act
+    let response = app.get_feed("atom.xml", &[]).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/atom+xml; charset=utf-8"
+    );
+    let feed = response.text().await.unwrap();
+    assert!(feed.contains("<title>Public issue</title>"));
+    assert!(feed.contains(&format!(
+        "<link href=\"{}/archive/public-issue\"/>",
+        app.base_url
+    )));
+    assert!(!feed.contains("Members only"));
+}
+
+#[tokio::test]
+async fn feeds_are_not_sent_again_if_the_etag_matches() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Public issue", false).await;
+    let response = app.get_feed("rss.xml", &[]).await;
+    let etag = response.headers()["etag"].to_str().unwrap().to_owned();
+
+    // Act
+    let unchanged = app.get_feed("rss.xml", &[("If-None-Match", &etag)]).await;
+    publish(&app, "Another issue", false).await;
+    let changed = app.get_feed("rss.xml", &[("If-None-Match", &etag)]).await;
+
+    // Assert
+    assert_eq!(304, unchanged.status().as_u16());
+    assert_eq!(unchanged.headers()["etag"], etag.as_str());
+    assert!(unchanged.text().await.unwrap().is_empty());
+    assert_eq!(200, changed.status().as_u16());
+    assert_ne!(changed.headers()["etag"], etag.as_str());
+}
+
+#[tokio::test]
+async fn feeds_are_not_sent_again_if_not_modified_since() {
+    // Arrange
+    let app = spawn_app().await;
+    publish(&app, "Public issue", false).await;
+    let response = app.get_feed("atom.xml", &[]).await;
+    let last_modified = response.headers()["last-modified"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    // Act
+    let unchanged = app
+        .get_feed("atom.xml", &[("If-Modified-Since", &last_modified)])
+        .await;
+    let older = app
+        .get_feed(
+            "atom.xml",
+            &[("If-Modified-Since", "Sat, 01 Jan 2000 00:00:00 GMT")],
+        )
+        .await;
+
+    // Assert
+    assert_eq!(304, unchanged.status().as_u16());
+    assert_eq!(200, older.status().as_u16());
+}
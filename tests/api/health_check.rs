@@ -1,4 +1,6 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use wiremock::matchers::method;
+use wiremock::{Mock, ResponseTemplate};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -10,7 +12,7 @@ async fn health_check_works() {
 
     // Act
     let response = client
-        .get(&format!("{}/health_check", &test_app.address))
+        .get(format!("{}/health_check", &test_app.address))
         .send()
         .await
         .expect("Failed to execute request.");
@@ -18,3 +20,115 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+async fn get_readiness(app: &TestApp) -> (u16, serde_json::Value) {
+    let response = app
+        .api_client
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn liveness_works() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_application_is_ready_once_its_database_is_migrated() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let (status, body) = get_readiness(&app).await;
+
+    // Assert
+    assert_eq!(status, 200);
+    assert_eq!(body["ready"], true);
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["migrations"]["status"], "up");
+    // Off unless configured
+    assert_eq!(body["checks"]["email"]["status"], "skipped");
+}
+
+#[tokio::test]
+async fn the_application_is_not_ready_with_migrations_left_to_apply() {
+    // Arrange
+    let app = spawn_app().await;
+    // As if the application had been deployed before its last migration was run
+    sqlx::query(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let (status, body) = get_readiness(&app).await;
+
+    // Assert
+    assert_eq!(status, 503);
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["migrations"]["status"], "down");
+    assert_eq!(
+        body["checks"]["migrations"]["error"],
+        "1 migration(s) to apply."
+    );
+}
+
+#[tokio::test]
+async fn the_email_backend_is_checked_if_configured() {
+    // Arrange
+    let app = spawn_app_with(|c| c.health.check_email_backend = true).await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (status, body) = get_readiness(&app).await;
+
+    // Assert
+    // Any answer will do: the service is there
+    assert_eq!(status, 200);
+    assert_eq!(body["checks"]["email"]["status"], "up");
+}
+
+#[tokio::test]
+async fn the_application_is_not_ready_if_the_email_backend_is_unreachable() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.health.check_email_backend = true;
+        // Nothing listens there
+        c.email_client.base_url = "http://127.0.0.1:9".into();
+    })
+    .await;
+
+    // Act
+    let (status, body) = get_readiness(&app).await;
+
+    // Assert
+    assert_eq!(status, 503);
+    assert_eq!(body["checks"]["email"]["status"], "down");
+    assert_eq!(
+        body["checks"]["email"]["error"],
+        "The email delivery service can't be reached."
+    );
+}
@@ -6,28 +6,47 @@
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 
-use rust_newsletter::configuration::{get_configuration, DatabaseSettings};
-use rust_newsletter::startup::{get_connection_pool, Application};
+use rust_newsletter::configuration::{
+    get_configuration, DatabaseSettings, DeliverySettings, Settings, TrackingSettings,
+};
+use rust_newsletter::email_client::EmailClient;
+use rust_newsletter::issue_delivery_worker::{try_execute_task, ExecutionOutcome, Shutdown};
+use rust_newsletter::startup::{get_connection_pool, Application, HmacSecret};
 use rust_newsletter::telemetry::{get_subscriber, init_subscriber};
 
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 
 use once_cell::sync::Lazy;
+use opentelemetry::sdk::trace::TracerProvider;
+use opentelemetry::trace::TracerProvider as _;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 use wiremock::MockServer;
 
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
-static TRACING: Lazy<()> = Lazy::new(|| {
+// Spans get OpenTelemetry contexts, so that `traceparent` headers are propagated,
+// but they aren't exported anywhere. The provider has to live as long as the tests.
+static TRACING: Lazy<TracerProvider> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    let tracer_provider = TracerProvider::builder().build();
+    let tracer = Some(tracer_provider.tracer("test"));
 
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(default_filter_level, subscriber_name, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            tracer,
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(default_filter_level, subscriber_name, std::io::sink);
+        let subscriber =
+            get_subscriber(subscriber_name, default_filter_level, std::io::sink, tracer);
         init_subscriber(subscriber);
     }
+    tracer_provider
 });
 
 pub struct TestApp {
@@ -37,6 +56,17 @@ pub struct TestApp {
     pub port: u16,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    // Where `/metrics` is served, if not at `address`
+    pub metrics_address: Option<String>,
+    pub email_client: EmailClient,
+    pub delivery_settings: DeliverySettings,
+    // The application's own, as linked to from the emails
+    pub base_url: String,
+    pub tracking: TrackingSettings,
+    pub hmac_secret: HmacSecret,
+    // Stops the application, which is done once `running` completes
+    pub shutdown: Shutdown,
+    pub running: JoinHandle<Result<(), std::io::Error>>,
 }
 
 pub struct TestUser {
@@ -51,24 +81,252 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    // The CSRF token of `api_client`, as embedded in our forms.
+    pub async fn csrf_token(&self) -> String {
+        let html_page = self.get_login_html().await;
+        let start = html_page
+            .find(r#"name="csrf_token" value=""#)
+            .expect("The login form has no CSRF token.")
+            + r#"name="csrf_token" value=""#.len();
+        let end = start + html_page[start..].find('"').unwrap();
+        html_page[start..end].to_owned()
+    }
+
+    // Form helpers submit the token along with the form, like a browser would.
+    async fn with_csrf_token<Body>(&self, body: &Body) -> serde_json::Value
+    where
+        Body: serde::Serialize,
+    {
+        let mut body = serde_json::to_value(body).unwrap();
+        body["csrf_token"] = self.csrf_token().await.into();
+        body
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             // This `reqwest` form method makes sure that the body is URL-encoded
             // and the `Content-Type` header is set accordingly.
             //
-            .form(body)
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_totp(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/login/totp", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login_totp(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/totp", &self.address))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "code": code }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_forgot_password_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/password/forgot", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_forgot_password(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/password/forgot", &self.address))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "email": email }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_reset_password(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/password/reset", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_reset_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/password/reset", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_totp(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/totp", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_totp_html(&self) -> String {
+        self.get_totp().await.text().await.unwrap()
+    }
+
+    // `action` is either "enable" or "disable"
+    pub async fn post_totp(&self, action: &str, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/totp/{}", &self.address, action))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "code": code }))
+                    .await,
+            )
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    // Sends the emails of the newsletter issues published so far, instead of waiting
+    // for the delivery worker of the application to get to them.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            let outcome = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.delivery_settings,
+                &self.base_url,
+                &self.tracking,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap();
+            if let ExecutionOutcome::EmptyQueue = outcome {
+                break;
+            }
+        }
+        // The delivery worker may be sending one of them: the queue looks empty until it's done
+        for _ in 0..100 {
+            let pending = sqlx::query_scalar!(
+                r#"SELECT count(*) AS "count!" FROM issue_delivery_queue WHERE execute_after <= now()"#
+            )
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap();
+            if pending == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("The pending emails weren't dispatched.");
+    }
+
+    pub async fn get_metrics(&self) -> reqwest::Response {
+        let address = self.metrics_address.as_ref().unwrap_or(&self.address);
+        self.api_client
+            .get(format!("{}/metrics", address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_home_html(&self) -> String {
+        self.api_client
+            .get(&self.address)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    // `path` is relative to `/archive`, e.g. `""` for the listing or `"/{slug}"` for an issue.
+    pub async fn get_archive(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/archive{}", &self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_feed(&self, feed: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+        let mut request = self
+            .api_client
+            .get(format!("{}/archive/{}", &self.address, feed));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.send().await.expect("Failed to execute request.")
+    }
+
+    // The signed timestamp of the subscription form on the home page.
+    pub async fn subscription_form_issued_at(&self) -> String {
+        let html_page = self.get_home_html().await;
+        let start = html_page
+            .find(r#"name="form_issued_at" value=""#)
+            .expect("The subscription form has no timestamp.")
+            + r#"name="form_issued_at" value=""#.len();
+        let end = start + html_page[start..].find('"').unwrap();
+        html_page[start..end].to_owned()
+    }
+
+    // Submits `body` along with the fields of the form on the home page, like a browser would.
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        let issued_at = self.subscription_form_issued_at().await;
+        self.post_subscriptions_raw(format!("{}&form_issued_at={}", body, issued_at))
+            .await
+    }
+
+    pub async fn post_subscriptions_raw(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -78,7 +336,7 @@ impl TestApp {
 
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -106,20 +364,207 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscriber(&self, subscriber_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_subscriber_status(
+        &self,
+        subscriber_id: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .put(format!(
+                "{}/admin/subscribers/{}/status",
+                &self.address, subscriber_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscribers_import(&self, csv: &str, mode: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/import?mode={}",
+                &self.address, mode
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Content-Type", "text/csv")
+            .body(csv.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                &self.address, query
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_newsletter_stats(&self, newsletter_issue_id: &Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}/stats",
+                &self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_gdpr_export(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/gdpr/export", &self.address))
+            .query(&[("email", email)])
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_gdpr_erase(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/gdpr/erase", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_audit_log(&self, query: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/audit-log", &self.address))
+            .query(query)
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_users(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_admin_user(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/users", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_admin_user(
+        &self,
+        user_id: &Uuid,
+        property: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .put(format!(
+                "{}/admin/users/{}/{}",
+                &self.address, user_id, property
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_api_token(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/tokens", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_api_tokens(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/tokens", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_api_token(&self, token_id: &str) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/admin/tokens/{}", &self.address, token_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Create an API token for the test user and return its id and the token itself.
+    pub async fn create_api_token(&self, scopes: &[&str]) -> (String, String) {
+        let body: serde_json::Value = self
+            .post_api_token(serde_json::json!({ "name": "test", "scopes": scopes }))
+            .await
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        (
+            body["id"].as_str().unwrap().to_owned(),
+            body["token"].as_str().unwrap().to_owned(),
+        )
+    }
 }
 
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
@@ -128,6 +573,15 @@ pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
 }
 
 impl TestUser {
+    // Log in through the login form, so that `api_client` holds a session cookie.
+    pub async fn login(&self, app: &TestApp) -> reqwest::Response {
+        app.post_login(&serde_json::json!({
+            "username": &self.username,
+            "password": &self.password
+        }))
+        .await
+    }
+
     pub fn generate() -> Self {
         Self {
             user_id: Uuid::new_v4(),
@@ -136,7 +590,7 @@ impl TestUser {
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn store(&self, pool: &PgPool, role: &str) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         // Match parameters of the default password (used in validate_credentials)
         let password_hash = Argon2::new(
@@ -149,11 +603,12 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, role)
+            VALUES ($1, $2, $3, $4)",
             self.user_id,
             self.username,
             password_hash,
+            role,
         )
         .execute(pool)
         .await
@@ -162,6 +617,11 @@ impl TestUser {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+// Same as `spawn_app`, with a chance to tweak the configuration before the application is built.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -173,6 +633,9 @@ pub async fn spawn_app() -> TestApp {
         // Use a random OS port
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
+        // Forms are submitted as soon as they are loaded
+        c.subscription_protection.min_fill_seconds = 0;
+        customise(&mut c);
         c
     };
     // Create and migrate the database
@@ -185,7 +648,9 @@ pub async fn spawn_app() -> TestApp {
         .expect("Failed to build application.");
     // Get the port before spawning the application
     let application_port = application.port();
-    let _ = tokio::spawn(application.run_until_stopped());
+    let metrics_port = application.metrics_port();
+    let shutdown = application.shutdown();
+    let running = tokio::spawn(application.run_until_stopped());
 
     // create a client instance to propagate our cookies across requests.
     let client = reqwest::Client::builder()
@@ -201,8 +666,16 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         port: application_port,
         test_user: TestUser::generate(),
+        metrics_address: metrics_port.map(|port| format!("http://localhost:{}", port)),
+        email_client: configuration.email_client.client(),
+        delivery_settings: configuration.delivery,
+        base_url: configuration.application.base_url,
+        tracking: configuration.tracking,
+        hmac_secret: HmacSecret(configuration.application.hmac_secret.clone()),
+        shutdown,
+        running,
     };
-    test_app.test_user.store(&test_app.db_pool).await;
+    test_app.test_user.store(&test_app.db_pool, "owner").await;
     test_app
 }
 
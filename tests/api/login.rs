@@ -1,5 +1,16 @@
 use crate::helpers::assert_is_redirect_to;
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+}
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -26,3 +37,35 @@ async fn an_error_flash_message_is_set_on_failure() {
     let html_page = app.get_login_html().await;
     assert!(!html_page.contains(r#"Authentication failed"#));
 }
+
+#[tokio::test]
+async fn weaker_password_hashes_are_upgraded_on_login() {
+    // Arrange - the test user is stored with m=15000
+    let app = spawn_app_with(|c| c.password_hashing.memory_kib = 19456).await;
+
+    // Act
+    let response = app.test_user.login(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/");
+    assert!(stored_password_hash(&app)
+        .await
+        .starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+    // The new hash still matches the password
+    let response = app.test_user.login(&app).await;
+    assert_is_redirect_to(&response, "/");
+}
+
+#[tokio::test]
+async fn stronger_password_hashes_are_left_alone() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_hashing.memory_kib = 8192).await;
+    let password_hash = stored_password_hash(&app).await;
+
+    // Act
+    let response = app.test_user.login(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/");
+    assert_eq!(password_hash, stored_password_hash(&app).await);
+}
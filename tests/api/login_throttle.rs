@@ -0,0 +1,156 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app_with, TestApp, TestUser};
+use rust_newsletter::configuration::Settings;
+use uuid::Uuid;
+
+async fn fail_login(app: &TestApp, username: &str) {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": username,
+            "password": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+fn lock_out_after(attempts: u32) -> impl FnOnce(&mut Settings) {
+    move |c: &mut Settings| {
+        c.login_throttle.free_attempts = 100;
+        c.login_throttle.username_lockout_after = attempts;
+    }
+}
+
+#[tokio::test]
+async fn a_username_is_locked_out_after_too_many_failed_logins() {
+    // Arrange
+    let app = spawn_app_with(lock_out_after(3)).await;
+    for _ in 0..3 {
+        fail_login(&app, &app.test_user.username).await;
+    }
+
+    // Act - Part 1 - The right password does not help anymore
+    let response = app.test_user.login(&app).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - The same error as for a wrong password
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"<p><i>Authentication failed</i></p>"#));
+}
+
+#[tokio::test]
+async fn other_users_are_not_affected_by_a_locked_out_username() {
+    // Arrange
+    let mut app = spawn_app_with(lock_out_after(3)).await;
+    for _ in 0..3 {
+        fail_login(&app, &app.test_user.username).await;
+    }
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool, "editor").await;
+    app.test_user = other_user;
+
+    // Act
+    let response = app.test_user.login(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/");
+}
+
+#[tokio::test]
+async fn failed_logins_beyond_the_free_attempts_have_to_wait() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttle.free_attempts = 1;
+        c.login_throttle.base_delay_milliseconds = 60_000;
+    })
+    .await;
+    for _ in 0..2 {
+        fail_login(&app, &app.test_user.username).await;
+    }
+
+    // Act
+    let response = app.test_user.login(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_ip_address_is_locked_out_after_too_many_failed_logins() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_throttle.ip_lockout_after = 3).await;
+    for _ in 0..3 {
+        fail_login(&app, &Uuid::new_v4().to_string()).await;
+    }
+
+    // Act
+    let response = app.test_user.login(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn basic_credentials_are_throttled_too() {
+    // Arrange
+    let mut app = spawn_app_with(lock_out_after(2)).await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let password = std::mem::replace(&mut app.test_user.password, Uuid::new_v4().to_string());
+    for _ in 0..2 {
+        let response = app.post_newsletters(newsletter_request_body.clone()).await;
+        assert_eq!(401, response.status().as_u16());
+    }
+    app.test_user.password = password;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn made_up_forwarded_addresses_do_not_escape_the_ip_lockout() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttle.ip_lockout_after = 3;
+        c.login_throttle.trust_forwarded_headers = true;
+        c.application.trusted_proxies = 1;
+    })
+    .await;
+    // The address on the right is the one the proxy saw, the others are made up by the client
+    let attempt = |username: String, password: String, spoofed: String| {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers", &app.address))
+            .basic_auth(username, Some(password))
+            .header("X-Forwarded-For", format!("{}, 203.0.113.7", spoofed))
+            .header("Forwarded", format!("for={}", spoofed))
+            .send()
+    };
+    for i in 0..3 {
+        let response = attempt(
+            Uuid::new_v4().to_string(),
+            Uuid::new_v4().to_string(),
+            format!("198.51.100.{}", i),
+        )
+        .await
+        .unwrap();
+        assert_eq!(401, response.status().as_u16());
+    }
+
+    // Act
+    let response = attempt(
+        app.test_user.username.clone(),
+        app.test_user.password.clone(),
+        "198.51.100.200".into(),
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
@@ -1,6 +1,28 @@
+mod admin_export;
+mod admin_import;
+mod admin_subscribers;
+mod admin_users;
+mod api_tokens;
+mod archive;
+mod audit_log;
+mod bootstrap_admin;
+mod change_password;
+mod csrf;
+mod data_subjects;
+mod feeds;
 mod health_check;
 mod helpers;
 mod login;
+mod login_throttle;
+mod metrics;
 mod newsletter;
+mod password_reset;
+mod request_id;
+mod security_headers;
+mod shutdown;
+mod subscription_protection;
 mod subscriptions;
 mod subscriptions_confirm;
+mod telemetry;
+mod tracking;
+mod two_factor;
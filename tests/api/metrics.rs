@@ -0,0 +1,91 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+// Every application of the test suite records into the same metrics:
+// we can only check that series are there, not their exact values.
+
+#[tokio::test]
+async fn metrics_are_exposed_in_the_prometheus_text_format() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_metrics().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"newsletter_db_pool_connections{state="idle"}"#));
+    assert!(body.contains(r#"newsletter_db_pool_connections{state="in_use"}"#));
+}
+
+#[tokio::test]
+async fn requests_are_labelled_with_the_pattern_of_their_route() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = Uuid::new_v4();
+
+    // Act
+    app.api_client
+        .get(format!(
+            "{}/admin/subscribers/{}",
+            &app.address, subscriber_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let body = app.get_metrics().await.text().await.unwrap();
+
+    // Assert
+    assert!(body.contains(
+        r#"newsletter_http_requests_total{method="GET",route="/admin/subscribers/{subscriber_id}",status="#
+    ));
+    assert!(body.contains(r#"newsletter_http_request_duration_seconds_bucket{method="GET",route="/admin/subscribers/{subscriber_id}""#));
+    assert!(!body.contains(&subscriber_id.to_string()));
+}
+
+#[tokio::test]
+async fn emails_and_password_verifications_are_counted() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.test_user.login(&app).await;
+    let body = app.get_metrics().await.text().await.unwrap();
+
+    // Assert
+    assert!(body.contains(r#"newsletter_emails_total{kind="confirmation",outcome="sent"}"#));
+    assert!(body.contains("newsletter_password_verification_duration_seconds_count"));
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_a_port_of_their_own() {
+    // Arrange
+    let app = spawn_app_with(|c| c.metrics.port = Some(0)).await;
+
+    // Act
+    let on_metrics_port = app.get_metrics().await;
+    let on_application_port = app
+        .api_client
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(on_metrics_port.status().as_u16(), 200);
+    assert_eq!(on_application_port.status().as_u16(), 404);
+}
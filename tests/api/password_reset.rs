@@ -0,0 +1,251 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use std::time::Duration;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const EMAIL: &str = "owner@example.com";
+
+async fn spawn_app_with_email() -> TestApp {
+    let app = spawn_app().await;
+    sqlx::query!(
+        "UPDATE users SET email = $1 WHERE user_id = $2",
+        EMAIL,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+// The email is sent in the background: wait for it.
+async fn reset_email(app: &TestApp) -> wiremock::Request {
+    for _ in 0..50 {
+        if let Some(request) = app.email_server.received_requests().await.unwrap().pop() {
+            return request;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("No password reset email was sent.");
+}
+
+async fn request_reset_token(app: &TestApp) -> String {
+    app.post_forgot_password(EMAIL).await;
+    let email_request = reset_email(app).await;
+    let link = app.get_confirmation_links(&email_request).plain_text;
+    link.query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+fn new_passwords(token: &str, password: &str) -> serde_json::Value {
+    serde_json::json!({
+        "token": token,
+        "new_password": password,
+        "new_password_check": password,
+    })
+}
+
+#[tokio::test]
+async fn the_response_does_not_reveal_whether_an_account_exists() {
+    // Arrange
+    let app = spawn_app_with_email().await;
+
+    // Act - Part 1 - An email address we know
+    let response = app.post_forgot_password(EMAIL).await;
+    assert_is_redirect_to(&response, "/password/forgot");
+    let known_html = app.get_forgot_password_html().await;
+
+    // Act - Part 2 - One we don't
+    let response = app.post_forgot_password("nobody@example.com").await;
+    assert_is_redirect_to(&response, "/password/forgot");
+    let unknown_html = app.get_forgot_password_html().await;
+
+    // Assert
+    assert_eq!(known_html, unknown_html);
+    assert!(known_html.contains("If an account uses this email address"));
+}
+
+#[tokio::test]
+async fn a_reset_link_lets_the_user_choose_a_new_password() {
+    // Arrange
+    let mut app = spawn_app_with_email().await;
+    app.post_forgot_password(EMAIL).await;
+    let email_request = reset_email(&app).await;
+    let link = app.get_confirmation_links(&email_request).html;
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+
+    // Act - Part 1 - Follow the link
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains(&token));
+
+    // Act - Part 2 - Choose a new password
+    let new_password = Uuid::new_v4().to_string();
+    let response = app
+        .post_reset_password(&new_passwords(&token, &new_password))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your password has been reset. You can now log in.</i></p>"));
+
+    // Act - Part 3 - Log in with it
+    app.test_user.password = new_password;
+    let response = app.test_user.login(&app).await;
+    assert_is_redirect_to(&response, "/");
+
+    // Act - Part 4 - The reset is in the audit log
+    let page: serde_json::Value = app
+        .get_audit_log(&[("action", "password.reset")])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        page["entries"][0]["user_id"],
+        app.test_user.user_id.to_string()
+    );
+}
+
+#[tokio::test]
+async fn a_reset_token_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app_with_email().await;
+    let token = request_reset_token(&app).await;
+    let response = app
+        .post_reset_password(&new_passwords(&token, &Uuid::new_v4().to_string()))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act
+    let response = app
+        .post_reset_password(&new_passwords(&token, &Uuid::new_v4().to_string()))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password/forgot");
+    let html_page = app.get_forgot_password_html().await;
+    assert!(html_page.contains("This link is invalid or has expired."));
+    let response = app.get_reset_password(&token).await;
+    assert_is_redirect_to(&response, "/password/forgot");
+}
+
+#[tokio::test]
+async fn an_expired_reset_token_is_rejected() {
+    // Arrange
+    let app = spawn_app_with_email().await;
+    let token = request_reset_token(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_reset_password(&new_passwords(&token, &Uuid::new_v4().to_string()))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password/forgot");
+    let response = app.test_user.login(&app).await;
+    assert_is_redirect_to(&response, "/");
+}
+
+#[tokio::test]
+async fn an_unknown_reset_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_reset_password("not-a-token").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password/forgot");
+}
+
+#[tokio::test]
+async fn a_rejected_new_password_does_not_use_up_the_token() {
+    // Arrange
+    let app = spawn_app_with_email().await;
+    let token = request_reset_token(&app).await;
+
+    // Act
+    let response = app
+        .post_reset_password(&new_passwords(&token, "short"))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/password/reset?token={}", urlencoding::encode(&token)),
+    );
+    let response = app.get_reset_password(&token).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn reset_tokens_are_stored_hashed() {
+    // Arrange
+    let app = spawn_app_with_email().await;
+
+    // Act
+    let token = request_reset_token(&app).await;
+
+    // Assert
+    let stored = sqlx::query!("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+    assert!(!stored.token_hash.contains(&token));
+}
+
+#[tokio::test]
+async fn the_login_form_links_to_the_forgot_password_page() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let html_page = app.get_login_html().await;
+
+    // Assert
+    assert!(html_page.contains(r#"href="/password/forgot""#));
+}
+
+#[tokio::test]
+async fn reset_requests_are_limited_per_email_address() {
+    // Arrange
+    let app = spawn_app_with_email().await;
+    for _ in 0..3 {
+        app.post_forgot_password(EMAIL).await;
+    }
+
+    // Act
+    let response = app.post_forgot_password(EMAIL).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/password/forgot");
+    let html_page = app.get_forgot_password_html().await;
+    assert!(html_page.contains("Too many password reset requests"));
+    // The emails are sent in the background: wait for them, and give a fourth one a chance to show up
+    for _ in 0..50 {
+        if app.email_server.received_requests().await.unwrap().len() >= 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let received = app.email_server.received_requests().await.unwrap();
+    assert_eq!(received.len(), 3);
+}
@@ -0,0 +1,110 @@
+use crate::helpers::{spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const SUBSCRIBER: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn get_health_check(app: &TestApp, request_id: Option<&str>) -> reqwest::Response {
+    let mut request = app.api_client.get(format!("{}/health_check", &app.address));
+    if let Some(request_id) = request_id {
+        request = request.header("X-Request-Id", request_id);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+fn request_id(response: &reqwest::Response) -> &str {
+    response.headers()["X-Request-Id"].to_str().unwrap()
+}
+
+#[tokio::test]
+async fn every_response_gets_a_fresh_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let first = get_health_check(&app, None).await;
+    let second = get_health_check(&app, None).await;
+
+    // Assert
+    assert!(Uuid::parse_str(request_id(&first)).is_ok());
+    assert_ne!(request_id(&first), request_id(&second));
+}
+
+#[tokio::test]
+async fn the_request_id_of_a_proxy_is_kept() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get_health_check(&app, Some("lb-7f3a:42")).await;
+
+    // Assert
+    assert_eq!(request_id(&response), "lb-7f3a:42");
+}
+
+#[tokio::test]
+async fn a_request_id_that_is_unsafe_to_log_is_replaced() {
+    // Arrange
+    let app = spawn_app().await;
+    let too_long = "a".repeat(129);
+
+    for request_id_sent in ["forged\" level=\"error", too_long.as_str()] {
+        // Act
+        let response = get_health_check(&app, Some(request_id_sent)).await;
+
+        // Assert
+        assert!(Uuid::parse_str(request_id(&response)).is_ok());
+    }
+}
+
+#[tokio::test]
+async fn the_request_id_is_sent_to_the_email_delivery_service() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header("X-Request-Id", "support-ticket-1234"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let issued_at = app.subscription_form_issued_at().await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", "support-ticket-1234")
+        .body(format!("{}&form_issued_at={}", SUBSCRIBER, issued_at))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    // Mock verifies on Drop that the header was sent
+}
+
+#[tokio::test]
+async fn server_errors_only_show_the_request_id() {
+    // Arrange
+    let app = spawn_app().await;
+    // Sabotage the database
+    sqlx::query!("ALTER TABLE subscriptions DROP COLUMN email;",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(SUBSCRIBER.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    let request_id = request_id(&response).to_owned();
+    let body = response.text().await.unwrap();
+    assert!(body.contains(&format!("please quote this request ID: {}", request_id)));
+    // The cause is logged, not shown
+    assert!(!body.to_lowercase().contains("email"));
+}
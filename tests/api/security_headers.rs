@@ -0,0 +1,122 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn get(app: &TestApp, path: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn header<'a>(response: &'a reqwest::Response, name: &str) -> Option<&'a str> {
+    response
+        .headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn public_pages_get_a_csp_with_a_fresh_nonce() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let first = get(&app, "/").await;
+    let second = get(&app, "/").await;
+
+    // Assert
+    let csp = header(&first, "Content-Security-Policy").unwrap();
+    assert!(csp.contains("script-src 'nonce-"));
+    assert!(csp.contains("frame-ancestors 'none'"));
+    assert_ne!(Some(csp), header(&second, "Content-Security-Policy"));
+    assert_eq!(header(&first, "X-Frame-Options"), Some("DENY"));
+    assert_eq!(header(&first, "X-Content-Type-Options"), Some("nosniff"));
+    assert_eq!(
+        header(&first, "Referrer-Policy"),
+        Some("strict-origin-when-cross-origin")
+    );
+    assert_eq!(header(&first, "Cache-Control"), None);
+    // Off unless configured
+    assert_eq!(header(&first, "Strict-Transport-Security"), None);
+}
+
+#[tokio::test]
+async fn login_and_admin_pages_are_not_cached_and_not_referred() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    for path in ["/login", "/password/forgot", "/admin/password"] {
+        // Act
+        let response = get(&app, path).await;
+
+        // Assert
+        assert_eq!(200, response.status().as_u16(), "{}", path);
+        assert_eq!(header(&response, "Cache-Control"), Some("no-store"));
+        assert_eq!(header(&response, "Referrer-Policy"), Some("no-referrer"));
+        assert!(header(&response, "Content-Security-Policy")
+            .unwrap()
+            .contains("form-action 'self'"));
+    }
+}
+
+#[tokio::test]
+async fn api_responses_get_a_csp_that_allows_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Admin API, rejected
+    let response = get(&app, "/admin/subscribers").await;
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        header(&response, "Content-Security-Policy"),
+        Some("default-src 'none'; frame-ancestors 'none'")
+    );
+    assert_eq!(header(&response, "Cache-Control"), Some("no-store"));
+
+    // Act - Part 2 - Public API
+    let response = get(&app, "/health_check").await;
+    assert_eq!(
+        header(&response, "Content-Security-Policy"),
+        Some("default-src 'none'; frame-ancestors 'none'")
+    );
+    assert_eq!(header(&response, "X-Content-Type-Options"), Some("nosniff"));
+    assert_eq!(header(&response, "Cache-Control"), None);
+}
+
+#[tokio::test]
+async fn hsts_is_sent_when_configured() {
+    // Arrange
+    let app = spawn_app_with(|c| c.security_headers.hsts_max_age_seconds = 600).await;
+
+    // Act
+    let response = get(&app, "/").await;
+
+    // Assert
+    assert_eq!(
+        header(&response, "Strict-Transport-Security"),
+        Some("max-age=600; includeSubDomains")
+    );
+}
+
+#[tokio::test]
+async fn inline_styles_carry_the_nonce_of_the_csp() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get(&app, "/").await;
+
+    // Assert
+    let csp = header(&response, "Content-Security-Policy")
+        .unwrap()
+        .to_owned();
+    let nonce = csp
+        .split("'nonce-")
+        .nth(1)
+        .and_then(|rest| rest.split('\'').next())
+        .unwrap()
+        .to_owned();
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(&format!(r#"<style nonce="{}">"#, nonce)));
+}
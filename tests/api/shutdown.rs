@@ -0,0 +1,128 @@
+use crate::helpers::{spawn_app_with, TestApp};
+use sqlx::PgPool;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+// How long the email delivery service takes to answer, long enough to shut down mid-delivery.
+const SEND_DELAY: Duration = Duration::from_secs(2);
+
+async fn store_confirmed_subscribers(app: &TestApp, count: usize) {
+    for _ in 0..count {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, 'reader', now(), 'confirmed')
+            "#,
+            Uuid::new_v4(),
+            format!("{}@example.com", Uuid::new_v4()),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+}
+
+async fn publish_newsletter(app: &TestApp) {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+async fn wait_for_first_email(app: &TestApp) {
+    for _ in 0..100 {
+        if !app
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .is_empty()
+        {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("The delivery worker didn't send anything.");
+}
+
+async fn queued_deliveries(pool: &PgPool) -> i64 {
+    sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn shutdown_lets_the_email_being_sent_finish_and_keeps_the_rest_queued() {
+    // Arrange
+    let app = spawn_app_with(|c| c.delivery.poll_interval_milliseconds = 50).await;
+    store_confirmed_subscribers(&app, 3).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(SEND_DELAY))
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    wait_for_first_email(&app).await;
+
+    // Act
+    app.shutdown.trigger();
+    let stopped = tokio::time::timeout(Duration::from_secs(10), app.running).await;
+
+    // Assert
+    assert!(matches!(stopped, Ok(Ok(Ok(())))));
+    let sent = app.email_server.received_requests().await.unwrap();
+    assert_eq!(sent.len(), 1);
+    // The email that was sent is done with, the others wait for the next start
+    assert_eq!(queued_deliveries(&app.db_pool).await, 2);
+    // New requests are refused, once the listener has been closed in the background
+    let mut refused = false;
+    for _ in 0..20 {
+        if reqwest::get(format!("{}/health_check", &app.address))
+            .await
+            .is_err()
+        {
+            refused = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(refused);
+}
+
+#[tokio::test]
+async fn shutdown_gives_up_on_the_email_being_sent_after_the_deadline() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.delivery.poll_interval_milliseconds = 50;
+        c.application.shutdown_timeout_seconds = 0;
+    })
+    .await;
+    store_confirmed_subscribers(&app, 2).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(SEND_DELAY))
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    wait_for_first_email(&app).await;
+
+    // Act
+    let started_at = Instant::now();
+    app.shutdown.trigger();
+    let stopped = tokio::time::timeout(Duration::from_secs(10), app.running).await;
+
+    // Assert
+    assert!(matches!(stopped, Ok(Ok(Ok(())))));
+    assert!(started_at.elapsed() < SEND_DELAY);
+    // Nothing is lost: the email being sent stays queued, to be sent again
+    assert_eq!(queued_deliveries(&app.db_pool).await, 2);
+}
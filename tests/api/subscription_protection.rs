@@ -0,0 +1,167 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn expect_emails(app: &TestApp, count: u64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(count)
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn stored_subscriptions(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn the_home_page_has_a_subscription_form_with_bot_protection() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let html_page = app.get_home_html().await;
+
+    // Assert
+    assert!(html_page.contains(r#"<form action="/subscriptions" method="post">"#));
+    assert!(html_page.contains(r#"name="website""#));
+    assert!(html_page.contains(r#"name="form_issued_at""#));
+}
+
+#[tokio::test]
+async fn a_filled_in_honeypot_is_ignored_without_sending_an_email() {
+    // Arrange
+    let app = spawn_app().await;
+    expect_emails(&app, 0).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!("{}&website=https%3A%2F%2Fspam.example", BODY))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(stored_subscriptions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_post_without_a_valid_form_timestamp_is_ignored() {
+    // Arrange
+    let app = spawn_app().await;
+    expect_emails(&app, 0).await;
+    let issued_at = app.subscription_form_issued_at().await;
+    let (timestamp, signature) = issued_at.split_once('.').unwrap();
+    let forged = format!("{}.{}", timestamp.parse::<i64>().unwrap() - 60, signature);
+
+    for body in [
+        BODY.to_owned(),
+        format!("{}&form_issued_at={}", BODY, timestamp),
+        format!("{}&form_issued_at={}", BODY, forged),
+    ] {
+        // Act
+        let response = app.post_subscriptions_raw(body).await;
+
+        // Assert
+        assert_eq!(200, response.status().as_u16());
+    }
+    assert_eq!(stored_subscriptions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_form_submitted_too_quickly_is_ignored() {
+    // Arrange
+    let app = spawn_app_with(|c| c.subscription_protection.min_fill_seconds = 60).await;
+    expect_emails(&app, 0).await;
+
+    // Act
+    let response = app.post_subscriptions(BODY.into()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(stored_subscriptions(&app).await, 0);
+}
+
+#[tokio::test]
+async fn an_expired_form_is_rejected() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.subscription_protection.min_fill_seconds = 0;
+        c.subscription_protection.max_form_age_seconds = 0;
+    })
+    .await;
+    expect_emails(&app, 0).await;
+    let issued_at = app.subscription_form_issued_at().await;
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    // Act
+    let response = app
+        .post_subscriptions_raw(format!("{}&form_issued_at={}", BODY, issued_at))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscriptions_are_limited_per_ip_address() {
+    // Arrange
+    let app = spawn_app_with(|c| c.subscription_protection.per_ip_per_hour = 2).await;
+    expect_emails(&app, 2).await;
+    for i in 0..2 {
+        let body = format!("name=le%20guin&email=ursula{}%40example{}.com", i, i);
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40example.org".into())
+        .await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+    assert_eq!(stored_subscriptions(&app).await, 2);
+}
+
+// A different address and email address every time, always at the same domain.
+async fn subscribe_from(app: &TestApp, i: u32) -> reqwest::Response {
+    let issued_at = app.subscription_form_issued_at().await;
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("X-Forwarded-For", format!("192.0.2.{}", i))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(format!(
+            "name=le%20guin&email=ursula{}%40Example.com&form_issued_at={}",
+            i, issued_at
+        ))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn subscriptions_are_limited_per_email_domain() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.subscription_protection.per_email_domain_per_hour = 2;
+        c.subscription_protection.trust_forwarded_headers = true;
+    })
+    .await;
+    expect_emails(&app, 2).await;
+    for i in 0..2 {
+        assert_eq!(200, subscribe_from(&app, i).await.status().as_u16());
+    }
+
+    // Act
+    let response = subscribe_from(&app, 2).await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+}
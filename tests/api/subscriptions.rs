@@ -29,7 +29,7 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
     // get the body of a request intercepted by wiremock::MockServer
     //We can use its received_requests method
     let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = test_app.get_confirmation_links(&email_request);
+    let confirmation_links = test_app.get_confirmation_links(email_request);
 
     // The two links should be identical
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
@@ -0,0 +1,101 @@
+use crate::helpers::spawn_app;
+use opentelemetry::trace::TracerProvider;
+use rust_newsletter::configuration::OpenTelemetrySettings;
+use rust_newsletter::telemetry::{get_subscriber, get_tracer_provider};
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const TRACE_ID: &str = "0af7651916cd43dd8448eb211c80319c";
+
+// The exporter is driven by the runtime: the test blocks a thread while it flushes
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_to_the_configured_collector() {
+    // Arrange
+    let collector = MockServer::start().await;
+    Mock::given(path("/v1/traces"))
+        .and(method("POST"))
+        .and(header("Content-Type", "application/x-protobuf"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1..)
+        .mount(&collector)
+        .await;
+    let settings = OpenTelemetrySettings {
+        otlp_endpoint: Some(format!("{}/v1/traces", collector.uri())),
+        timeout_milliseconds: 1000,
+    };
+    let provider = get_tracer_provider("test", &settings)
+        .expect("Failed to build the exporter.")
+        .expect("No exporter, even though a collector is configured.");
+    let subscriber = get_subscriber(
+        "test".into(),
+        "info".into(),
+        std::io::sink,
+        Some(provider.tracer("test")),
+    );
+
+    // Act
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info_span!("Publish a newsletter issue").in_scope(|| {});
+    });
+    // Spans are exported in batches: don't wait for the next one
+    let results = tokio::task::spawn_blocking(move || provider.force_flush())
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(results.iter().all(Result::is_ok));
+    // Mock verifies on Drop that a batch was received
+}
+
+#[tokio::test]
+async fn no_spans_are_exported_without_a_collector() {
+    // Arrange
+    let settings = OpenTelemetrySettings {
+        otlp_endpoint: None,
+        timeout_milliseconds: 1000,
+    };
+
+    // Act
+    let provider = get_tracer_provider("test", &settings).unwrap();
+
+    // Assert
+    assert!(provider.is_none());
+}
+
+#[tokio::test]
+async fn the_trace_of_a_request_is_carried_on_to_the_email_delivery_service() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let issued_at = app.subscription_form_issued_at().await;
+
+    // Act
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header(
+            "traceparent",
+            format!("00-{}-b7ad6b7169203331-01", TRACE_ID),
+        )
+        .body(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_issued_at={}",
+            issued_at
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request.headers.get(&"traceparent".into()).unwrap();
+    // Same trace, different span
+    assert!(traceparent
+        .as_str()
+        .starts_with(&format!("00-{}-", TRACE_ID)));
+    assert!(!traceparent.as_str().contains("b7ad6b7169203331"));
+}
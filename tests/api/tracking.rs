@@ -0,0 +1,274 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const EMAIL: &str = "reader@example.com";
+
+// Tracking is off unless it is enabled.
+async fn spawn_app_with_tracking() -> TestApp {
+    spawn_app_with(|c| {
+        c.tracking.opens = true;
+        c.tracking.clicks = true;
+    })
+    .await
+}
+
+async fn store_confirmed_subscriber(app: &TestApp) {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, 'reader', now(), 'confirmed')
+        "#,
+        Uuid::new_v4(),
+        EMAIL,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+// Publishes an issue and sends it to the confirmed subscribers, returning its id.
+async fn publish_and_send(app: &TestApp) -> Uuid {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text, more at https://example.com/more.",
+                "html": r#"<p>Newsletter body as HTML, <a href="https://example.com/more">more</a></p>"#,
+            }
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+    sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+// The HTML body of the last email sent.
+async fn last_html_body(app: &TestApp) -> String {
+    last_email(app).await["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+async fn last_email(app: &TestApp) -> serde_json::Value {
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    serde_json::from_slice(&request.body).unwrap()
+}
+
+// Where the tracking pixel of `html_body` is served by the application under test.
+fn pixel_url(app: &TestApp, html_body: &str) -> String {
+    let start = html_body.find("/t/o/").unwrap();
+    let end = start + html_body[start..].find('"').unwrap();
+    format!("{}{}", app.address, &html_body[start..end])
+}
+
+// Where the first tracked link of `html_body` goes through the application under test.
+fn click_url(app: &TestApp, html_body: &str) -> String {
+    let start = html_body.find("/t/c/").unwrap();
+    let end = start + html_body[start..].find('"').unwrap();
+    format!("{}{}", app.address, &html_body[start..end]).replace("&amp;", "&")
+}
+
+async fn stats(app: &TestApp, newsletter_issue_id: &Uuid) -> serde_json::Value {
+    app.get_newsletter_stats(newsletter_issue_id)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn opens_are_counted_once_per_email() {
+    // Arrange
+    let app = spawn_app_with_tracking().await;
+    store_confirmed_subscriber(&app).await;
+    let issue_id = publish_and_send(&app).await;
+    let pixel_url = pixel_url(&app, &last_html_body(&app).await);
+
+    // Act
+    for _ in 0..2 {
+        let response = reqwest::get(&pixel_url).await.unwrap();
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!(response.headers()["content-type"], "image/gif");
+    }
+
+    // Assert
+    let stats = stats(&app, &issue_id).await;
+    assert_eq!(stats["delivered"], 1);
+    assert_eq!(stats["unique_opens"], 1);
+    assert_eq!(stats["total_opens"], 2);
+    assert_eq!(stats["open_rate"], 1.0);
+}
+
+#[tokio::test]
+async fn unopened_issues_have_no_opens() {
+    // Arrange
+    let app = spawn_app_with_tracking().await;
+    store_confirmed_subscriber(&app).await;
+
+    // Act
+    let issue_id = publish_and_send(&app).await;
+
+    // Assert
+    let stats = stats(&app, &issue_id).await;
+    assert_eq!(stats["delivered"], 1);
+    assert_eq!(stats["unique_opens"], 0);
+    assert_eq!(stats["open_rate"], 0.0);
+}
+
+#[tokio::test]
+async fn unknown_tokens_get_the_pixel_too() {
+    // Arrange
+    let app = spawn_app_with_tracking().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/t/o/unknown", app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["content-type"], "image/gif");
+}
+
+#[tokio::test]
+async fn nothing_is_tracked_by_default() {
+    // Arrange
+    let app = spawn_app().await;
+    store_confirmed_subscriber(&app).await;
+
+    // Act
+    let issue_id = publish_and_send(&app).await;
+
+    // Assert
+    let email = last_email(&app).await;
+    let html_body = email["HtmlBody"].as_str().unwrap();
+    assert!(!html_body.contains("/t/o/"));
+    assert!(html_body.contains(r#"href="https://example.com/more""#));
+    assert!(email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("https://example.com/more."));
+    let stats = stats(&app, &issue_id).await;
+    assert_eq!(stats["delivered"], 0);
+}
+
+#[tokio::test]
+async fn statistics_of_unknown_issues_are_not_found() {
+    // Arrange
+    let app = spawn_app_with_tracking().await;
+
+    // Act
+    let response = app.get_newsletter_stats(&Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn tracked_deliveries_are_exported_and_erased_with_the_subscriber() {
+    // Arrange
+    let app = spawn_app_with_tracking().await;
+    store_confirmed_subscriber(&app).await;
+    let issue_id = publish_and_send(&app).await;
+    reqwest::get(pixel_url(&app, &last_html_body(&app).await))
+        .await
+        .unwrap();
+
+    // Act
+    app.api_client
+        .get(click_url(&app, &last_html_body(&app).await))
+        .send()
+        .await
+        .unwrap();
+    let package: serde_json::Value = app.get_gdpr_export(EMAIL).await.json().await.unwrap();
+    let report: serde_json::Value = app.post_gdpr_erase(EMAIL).await.json().await.unwrap();
+    // The email is still in the mailbox of the subscriber
+    reqwest::get(pixel_url(&app, &last_html_body(&app).await))
+        .await
+        .unwrap();
+    app.api_client
+        .get(click_url(&app, &last_html_body(&app).await))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let delivery = &package["tracked_deliveries"][0];
+    assert_eq!(delivery["newsletter_issue_id"], issue_id.to_string());
+    assert_eq!(delivery["open_count"], 1);
+    assert_eq!(package["link_clicks"][0]["url"], "https://example.com/more");
+    assert_eq!(report["erased_subscriptions"], 1);
+    assert_eq!(404, app.get_gdpr_export(EMAIL).await.status().as_u16());
+    // Erasing a subscriber does not rewrite the statistics of past issues
+    let stats = stats(&app, &issue_id).await;
+    assert_eq!(stats["delivered"], 1);
+    assert_eq!(stats["unique_opens"], 1);
+    assert_eq!(stats["total_opens"], 1);
+    assert_eq!(stats["unique_clicks"], 1);
+    assert_eq!(stats["total_clicks"], 1);
+}
+
+#[tokio::test]
+async fn links_redirect_and_are_counted_per_email() {
+    // Arrange
+    let app = spawn_app_with_tracking().await;
+    store_confirmed_subscriber(&app).await;
+    let issue_id = publish_and_send(&app).await;
+    let email = last_email(&app).await;
+    let click_url = click_url(&app, email["HtmlBody"].as_str().unwrap());
+
+    // Act
+    for _ in 0..2 {
+        let response = app.api_client.get(&click_url).send().await.unwrap();
+        assert_eq!(302, response.status().as_u16());
+        assert_eq!(response.headers()["location"], "https://example.com/more");
+    }
+
+    // Assert
+    assert!(email["TextBody"].as_str().unwrap().contains("/t/c/"));
+    let stats = stats(&app, &issue_id).await;
+    assert_eq!(stats["unique_clicks"], 1);
+    assert_eq!(stats["total_clicks"], 2);
+    assert_eq!(stats["click_rate"], 1.0);
+    let top_link = &stats["top_links"][0];
+    assert_eq!(top_link["url"], "https://example.com/more");
+    assert_eq!(top_link["unique_clicks"], 1);
+    assert_eq!(top_link["total_clicks"], 2);
+}
+
+#[tokio::test]
+async fn links_that_were_not_signed_are_not_followed() {
+    // Arrange
+    let app = spawn_app_with_tracking().await;
+    store_confirmed_subscriber(&app).await;
+    let issue_id = publish_and_send(&app).await;
+    let click_url = click_url(&app, &last_html_body(&app).await);
+    let tampered = click_url.replace("example.com", "evil.com");
+
+    // Act
+    let response = app.api_client.get(&tampered).send().await.unwrap();
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert!(response.headers().get("location").is_none());
+    assert_eq!(stats(&app, &issue_id).await["total_clicks"], 0);
+}
@@ -0,0 +1,309 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use rust_newsletter::domain::UserRole;
+use rust_newsletter::two_factor::code_at;
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+async fn totp_secret(app: &TestApp) -> Vec<u8> {
+    sqlx::query!(
+        "SELECT totp_secret FROM users WHERE user_id = $1",
+        app.test_user.user_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .totp_secret
+    .expect("No TOTP secret was stored.")
+}
+
+// Log in and set up two-factor authentication for the test user.
+// The current code is used up by the enrollment: log in with the one from the next time step.
+async fn enable_totp(app: &TestApp) -> (Vec<u8>, Vec<String>) {
+    app.test_user.login(app).await;
+    app.get_totp().await;
+    let secret = totp_secret(app).await;
+    let response = app.post_totp("enable", &code_at(&secret, now())).await;
+    assert_eq!(200, response.status().as_u16());
+    let html_page = response.text().await.unwrap();
+    let recovery_codes = html_page
+        .split("<li><code>")
+        .skip(1)
+        .map(|s| s.split("</code>").next().unwrap().to_owned())
+        .collect();
+    (secret, recovery_codes)
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_set_up_two_factor_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_totp().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn enrolling_shows_a_qr_code_and_recovery_codes_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Show the enrollment form
+    let html_page = app.get_totp_html().await;
+    assert!(html_page.contains("<svg"));
+    assert!(html_page.contains(&format!(
+        "otpauth://totp/Rust%20Newsletter:{}?secret=",
+        app.test_user.username
+    )));
+    // Reloading the page keeps the same secret
+    let secret = totp_secret(&app).await;
+    app.get_totp().await;
+    assert_eq!(secret, totp_secret(&app).await);
+
+    // Act - Part 2 - Confirm with a code
+    let response = app.post_totp("enable", &code_at(&secret, now())).await;
+    assert_eq!(200, response.status().as_u16());
+    let html_page = response.text().await.unwrap();
+    assert_eq!(10, html_page.matches("<li><code>").count());
+
+    // Act - Part 3 - The codes are not shown again
+    let html_page = app.get_totp_html().await;
+    assert!(html_page.contains("Two-factor authentication is enabled."));
+    assert!(!html_page.contains("<li><code>"));
+}
+
+#[tokio::test]
+async fn a_wrong_code_does_not_enable_two_factor_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.get_totp().await;
+    let secret = totp_secret(&app).await;
+
+    // Act
+    let response = app
+        .post_totp("enable", &code_at(&secret, now() - 3600))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/totp");
+    let html_page = app.get_totp_html().await;
+    assert!(html_page.contains("<p><i>The code is invalid.</i></p>"));
+    let enabled = sqlx::query!(
+        "SELECT totp_enabled_at FROM users WHERE user_id = $1",
+        app.test_user.user_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .totp_enabled_at;
+    assert!(enabled.is_none());
+}
+
+#[tokio::test]
+async fn users_with_two_factor_authentication_need_a_code_to_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+
+    // Act - Part 1 - The password alone is not enough
+    let response = app.test_user.login(&app).await;
+    assert_is_redirect_to(&response, "/login/totp");
+    assert_is_redirect_to(&app.get_totp().await, "/login");
+
+    // Act - Part 2 - A wrong code
+    let response = app.post_login_totp(&code_at(&secret, now() - 3600)).await;
+    assert_is_redirect_to(&response, "/login/totp");
+    let html_page = app.get_login_totp().await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>The code is invalid.</i></p>"));
+
+    // Act - Part 3 - The right code
+    let response = app.post_login_totp(&code_at(&secret, now() + 30)).await;
+    assert_is_redirect_to(&response, "/");
+    assert_eq!(200, app.get_totp().await.status().as_u16());
+}
+
+#[tokio::test]
+async fn totp_codes_cannot_be_used_twice() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.get_totp().await;
+    let secret = totp_secret(&app).await;
+    let code = code_at(&secret, now());
+    app.post_totp("enable", &code).await;
+    app.test_user.login(&app).await;
+
+    // Act - the code used to confirm the enrollment
+    let response = app.post_login_totp(&code).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/totp");
+}
+
+#[tokio::test]
+async fn a_recovery_code_can_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_totp(&app).await;
+
+    // Act - Part 1 - Log in with a recovery code, typed in upper case
+    app.test_user.login(&app).await;
+    let response = app.post_login_totp(&recovery_codes[0].to_uppercase()).await;
+    assert_is_redirect_to(&response, "/");
+
+    // Act - Part 2 - Try again with the same one
+    app.test_user.login(&app).await;
+    let response = app.post_login_totp(&recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/login/totp");
+}
+
+#[tokio::test]
+async fn basic_credentials_need_a_totp_code_once_two_factor_authentication_is_enabled() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+
+    // Act - Part 1 - Without a code
+    let response = app.get_admin_users().await;
+    assert_eq!(401, response.status().as_u16());
+
+    // Act - Part 2 - With a code
+    let response = app
+        .api_client
+        .get(format!("{}/admin/users", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("X-TOTP-Code", code_at(&secret, now() + 30))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn api_tokens_are_not_affected_by_two_factor_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, token) = app.create_api_token(&["read-subscribers"]).await;
+    enable_totp(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers", &app.address))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn roles_can_be_required_to_set_up_two_factor_authentication() {
+    // Arrange
+    let app = spawn_app_with(|c| c.two_factor.required_for = vec![UserRole::Owner]).await;
+
+    // Act - Part 1 - Basic credentials are refused until the second factor is set up
+    let response = app.get_admin_users().await;
+    assert_eq!(403, response.status().as_u16());
+
+    // Act - Part 2 - The login form leads to the enrollment
+    let response = app.test_user.login(&app).await;
+    assert_is_redirect_to(&response, "/admin/totp");
+    let html_page = app.get_totp_html().await;
+    assert!(html_page.contains(
+        "<p><i>You must set up two-factor authentication before you can continue.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn a_required_second_factor_cannot_be_disabled() {
+    // Arrange
+    let app = spawn_app_with(|c| c.two_factor.required_for = vec![UserRole::Owner]).await;
+    let (secret, _) = enable_totp(&app).await;
+
+    // Act
+    let response = app
+        .post_totp("disable", &code_at(&secret, now() + 30))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/totp");
+    let html_page = app.get_totp_html().await;
+    assert!(html_page.contains("<p><i>Your role requires two-factor authentication.</i></p>"));
+}
+
+#[tokio::test]
+async fn two_factor_authentication_can_be_disabled_with_a_code() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+
+    // Act - Part 1 - Disable
+    let response = app
+        .post_totp("disable", &code_at(&secret, now() + 30))
+        .await;
+    assert_is_redirect_to(&response, "/admin/totp");
+    let html_page = app.get_totp_html().await;
+    assert!(html_page.contains("<p><i>Two-factor authentication has been disabled.</i></p>"));
+
+    // Act - Part 2 - The password is enough again
+    let response = app.test_user.login(&app).await;
+    assert_is_redirect_to(&response, "/");
+}
+
+#[tokio::test]
+async fn enabling_and_disabling_two_factor_authentication_is_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+
+    // Act
+    app.post_totp("disable", &code_at(&secret, now() + 30))
+        .await;
+
+    // Assert
+    for action in ["two_factor.enabled", "two_factor.disabled"] {
+        let page: serde_json::Value = app
+            .get_audit_log(&[("action", action)])
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            page["entries"][0]["user_id"],
+            app.test_user.user_id.to_string()
+        );
+    }
+}
+
+#[tokio::test]
+async fn wrong_codes_are_recorded_without_a_user() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enable_totp(&app).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    app.post_login_totp(&code_at(&secret, now() - 3600)).await;
+
+    // Assert - read directly: the API needs a code once two-factor authentication is on
+    let entry =
+        sqlx::query!("SELECT user_id, details FROM audit_log WHERE action = 'login.failed'",)
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to fetch the audit log entry.");
+    assert!(entry.user_id.is_none());
+    assert_eq!(
+        entry.details["target_user_id"],
+        app.test_user.user_id.to_string()
+    );
+    assert_eq!(entry.details["step"], "second_factor");
+}